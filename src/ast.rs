@@ -1,16 +1,265 @@
-use std::fmt::Display;
+use crate::{
+    int::{FixedInt, Int, Width},
+    map::Map,
+    types::MonoType,
+};
+use std::{cell::RefCell, fmt::Display, rc::Rc};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum BinOp {
     Plus,
     Times,
     Le,
+    Eq,
+    Concat,
+    Land,
+    Lor,
+    Lxor,
+    Lsl,
+    Lsr,
+    /// `x |> f` and `f <| x`, both `f(x)`.
+    Pipe,
+    PipeBack,
+    /// `f >> g` and `g << f`, both `fn (x) -> g(f(x))`.
+    Compose,
+    ComposeBack,
+}
+
+impl BinOp {
+    pub const ALL: [BinOp; 14] = [
+        BinOp::Plus,
+        BinOp::Times,
+        BinOp::Le,
+        BinOp::Eq,
+        BinOp::Concat,
+        BinOp::Land,
+        BinOp::Lor,
+        BinOp::Lxor,
+        BinOp::Lsl,
+        BinOp::Lsr,
+        BinOp::Pipe,
+        BinOp::PipeBack,
+        BinOp::Compose,
+        BinOp::ComposeBack,
+    ];
+
+    pub fn from_symbol(symbol: &str) -> Option<BinOp> {
+        BinOp::ALL.into_iter().find(|op| op.to_string() == symbol)
+    }
+}
+
+impl Display for BinOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BinOp::Plus => write!(f, "+"),
+            BinOp::Times => write!(f, "*"),
+            BinOp::Le => write!(f, "<="),
+            BinOp::Eq => write!(f, "=="),
+            BinOp::Concat => write!(f, "^"),
+            BinOp::Land => write!(f, "land"),
+            BinOp::Lor => write!(f, "lor"),
+            BinOp::Lxor => write!(f, "lxor"),
+            BinOp::Lsl => write!(f, "lsl"),
+            BinOp::Lsr => write!(f, "lsr"),
+            BinOp::Pipe => write!(f, "|>"),
+            BinOp::PipeBack => write!(f, "<|"),
+            BinOp::Compose => write!(f, ">>"),
+            BinOp::ComposeBack => write!(f, "<<"),
+        }
+    }
+}
+
+/// Functions built into the interpreter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Prim {
+    /// Convert a value to its string representation.
+    Show,
+    /// Convert an integer to `Int`.
+    ToInt,
+    /// Convert an integer to a fixed-width integer, truncating it.
+    ToFixed(Width),
+    /// Evaluate a suspension, or return the value it has already computed.
+    Force,
+    /// Write a string and a newline to the interpreter's output.
+    Print,
+    /// Read a line from the interpreter's input, without its newline.
+    ReadLine,
+    /// Read a whole file into a string.
+    ReadFile,
+    /// Write a string to a file, given its path first.
+    WriteFile,
+    /// `array_init(n, f)`, the array of `f(0)`, ..., `f(n - 1)`, which is
+    /// empty if `n` is not positive.
+    ArrayInit,
+    ArrayGet,
+    /// `array_set(a, i, x)`, a copy of `a` with `x` at `i`.
+    ArraySet,
+    ArrayLength,
+    /// `array_fold(f, init, a)`, `f(...f(f(init, a0), a1)..., an)`.
+    ArrayFold,
+    /// `map_empty()`, the map without keys.
+    MapEmpty,
+    /// The value of a key, which must be in the map.
+    MapGet,
+    /// `map_set(m, k, v)`, `m` with `k` bound to `v`.
+    MapSet,
+    MapRemove,
+    /// Whether a key is in the map.
+    MapHas,
+    MapSize,
+    /// The keys of a map in order, as an array.
+    MapKeys,
+    /// Start running a function as a new task.
+    Spawn,
+    /// Wait for a task to finish and return its result.
+    Await,
+    /// `chan()`, a new channel without messages.
+    Chan,
+    /// `send(c, x)`, put `x` at the back of `c`.
+    Send,
+    /// Take the message at the front of a channel, waiting for one if it
+    /// is empty.
+    Recv,
+    /// `$memoize(s, v)`, what forcing the suspension `s` continues with once
+    /// its expression has the value `v`. Like the other `$` primitives, it
+    /// has no name a program can use, and is only made by the interpreter.
+    Memoize,
+    /// `$array_init_from(n, f, a, v)`, `array_init(n, f)` continued after
+    /// `a` has been built and `f` has returned `v` for the next index.
+    ArrayInitFrom,
+    /// `$array_fold_from(f, a, i, acc)`, `array_fold(f, init, a)` continued
+    /// from index `i`, with `acc` folded from the elements before it.
+    ArrayFoldFrom,
+}
+
+impl Prim {
+    pub const ALL: [Prim; 32] = [
+        Prim::Show,
+        Prim::Force,
+        Prim::Print,
+        Prim::ReadLine,
+        Prim::ReadFile,
+        Prim::WriteFile,
+        Prim::ArrayInit,
+        Prim::ArrayGet,
+        Prim::ArraySet,
+        Prim::ArrayLength,
+        Prim::ArrayFold,
+        Prim::MapEmpty,
+        Prim::MapGet,
+        Prim::MapSet,
+        Prim::MapRemove,
+        Prim::MapHas,
+        Prim::MapSize,
+        Prim::MapKeys,
+        Prim::Spawn,
+        Prim::Await,
+        Prim::Chan,
+        Prim::Send,
+        Prim::Recv,
+        Prim::ToInt,
+        Prim::ToFixed(Width::I8),
+        Prim::ToFixed(Width::I16),
+        Prim::ToFixed(Width::I32),
+        Prim::ToFixed(Width::I64),
+        Prim::ToFixed(Width::U8),
+        Prim::ToFixed(Width::U16),
+        Prim::ToFixed(Width::U32),
+        Prim::ToFixed(Width::U64),
+    ];
+
+    /// Number of arguments the primitive takes before it runs.
+    pub fn arity(&self) -> usize {
+        match self {
+            Prim::Show
+            | Prim::ToInt
+            | Prim::ToFixed(_)
+            | Prim::Force
+            | Prim::Print
+            | Prim::ReadLine
+            | Prim::ReadFile
+            | Prim::ArrayLength
+            | Prim::MapEmpty
+            | Prim::MapSize
+            | Prim::MapKeys
+            | Prim::Spawn
+            | Prim::Await
+            | Prim::Chan
+            | Prim::Recv => 1,
+            Prim::WriteFile
+            | Prim::ArrayInit
+            | Prim::ArrayGet
+            | Prim::MapGet
+            | Prim::MapRemove
+            | Prim::MapHas
+            | Prim::Send
+            | Prim::Memoize => 2,
+            Prim::ArraySet | Prim::ArrayFold | Prim::MapSet => 3,
+            Prim::ArrayInitFrom | Prim::ArrayFoldFrom => 4,
+        }
+    }
+}
+
+impl Display for Prim {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Prim::Show => write!(f, "show"),
+            Prim::ToInt => write!(f, "int"),
+            // `int8`, `uint8`...
+            Prim::ToFixed(width) => write!(f, "{}", width.to_string().to_lowercase()),
+            Prim::Force => write!(f, "force"),
+            Prim::Print => write!(f, "print"),
+            Prim::ReadLine => write!(f, "read_line"),
+            Prim::ReadFile => write!(f, "read_file"),
+            Prim::WriteFile => write!(f, "write_file"),
+            Prim::ArrayInit => write!(f, "array_init"),
+            Prim::ArrayGet => write!(f, "array_get"),
+            Prim::ArraySet => write!(f, "array_set"),
+            Prim::ArrayLength => write!(f, "array_length"),
+            Prim::ArrayFold => write!(f, "array_fold"),
+            Prim::MapEmpty => write!(f, "map_empty"),
+            Prim::MapGet => write!(f, "map_get"),
+            Prim::MapSet => write!(f, "map_set"),
+            Prim::MapRemove => write!(f, "map_remove"),
+            Prim::MapHas => write!(f, "map_has"),
+            Prim::MapSize => write!(f, "map_size"),
+            Prim::MapKeys => write!(f, "map_keys"),
+            Prim::Spawn => write!(f, "spawn"),
+            Prim::Await => write!(f, "await"),
+            Prim::Chan => write!(f, "chan"),
+            Prim::Send => write!(f, "send"),
+            Prim::Recv => write!(f, "recv"),
+            Prim::Memoize => write!(f, "$memoize"),
+            Prim::ArrayInitFrom => write!(f, "$array_init_from"),
+            Prim::ArrayFoldFrom => write!(f, "$array_fold_from"),
+        }
+    }
+}
+
+/// `| op(arg, k) -> body` in a handler.
+#[derive(Clone, Debug)]
+pub struct OpClause {
+    pub op: String,
+    pub arg: String,
+    pub k: String,
+    pub body: Expr,
+}
+
+/// The state of a suspension.
+#[derive(Clone, Debug)]
+pub enum Thunk {
+    Delayed(Expr),
+    Forced(Expr),
 }
 
 #[derive(Clone, Debug)]
 pub enum Expr {
-    Int(i64),
+    /// `()`, the value of expressions that are run for their effect.
+    Unit,
+    Int(Int),
+    Fixed(FixedInt),
     Bool(bool),
+    Str(String),
     Var(String),
     Let {
         x: String,
@@ -35,14 +284,194 @@ pub enum Expr {
         func: Box<Expr>,
         arg: Box<Expr>,
     },
+    /// `fn (~label) -> body`, or `fn (?label = default) -> body` when the
+    /// argument may be left out. The parameter is bound to `label`.
+    LabeledFn {
+        label: String,
+        default: Option<Box<Expr>>,
+        body: Box<Expr>,
+    },
+    /// `func(~label: arg)`.
+    LabeledApply {
+        func: Box<Expr>,
+        label: String,
+        arg: Box<Expr>,
+    },
+    /// What a function with an optional parameter is passed for it: the
+    /// argument, or `None` when the call leaves it out. The type checker
+    /// elaborates labels into these and `OrDefault`.
+    OptArg(Option<Box<Expr>>),
+    /// The value of the optional argument `arg`, or `default` if there is
+    /// none.
+    OrDefault {
+        arg: Box<Expr>,
+        default: Box<Expr>,
+    },
+    /// A primitive applied to fewer arguments than its arity.
+    Prim {
+        prim: Prim,
+        args: Vec<Expr>,
+    },
+    /// `lazy e`, which evaluates to a suspension of `e`.
+    Lazy(Box<Expr>),
+    /// A delayed computation. Copies made by substitution share the thunk,
+    /// so it is evaluated at most once.
+    Suspension(Rc<RefCell<Thunk>>),
+    /// `{ label = e, ... }`. A record holds its fields in order, and an
+    /// earlier field shadows a later one with the same label.
+    Record(Vec<(String, Expr)>),
+    /// `{ label = value | record }`.
+    Extend {
+        label: String,
+        value: Box<Expr>,
+        record: Box<Expr>,
+    },
+    /// `record.label`.
+    Select {
+        record: Box<Expr>,
+        label: String,
+    },
+    /// An array of values. Arrays are never changed in place: setting an
+    /// element copies the array.
+    Array(Rc<Vec<Expr>>),
+    /// A map from values to values, ordered by its keys.
+    Map(Map<Expr, Expr>),
+    /// A task started by `spawn`, by its number in the scheduler, and the
+    /// casts `(from, to)` its result goes through, in order, when it is
+    /// cast from or to a `Dyn`.
+    Task(usize, Vec<(MonoType, MonoType)>),
+    /// A channel made by `chan`, by its number in the scheduler. Received
+    /// messages go through the casts in order, and sent ones through their
+    /// inverses in reverse order.
+    Chan(usize, Vec<(MonoType, MonoType)>),
+    /// `record \ label`, which removes the first field labeled `label`.
+    Remove {
+        record: Box<Expr>,
+        label: String,
+    },
+    /// `class name var { method : type, ... }` declared for `body`.
+    Class {
+        name: String,
+        var: String,
+        methods: Vec<(String, MonoType)>,
+        body: Box<Expr>,
+    },
+    /// `instance class typ { method = e, ... }` declared for `body`.
+    Instance {
+        class: String,
+        typ: MonoType,
+        methods: Vec<(String, Expr)>,
+        body: Box<Expr>,
+    },
+    /// `effect name : typ` declared for `body`.
+    Effect {
+        name: String,
+        typ: MonoType,
+        body: Box<Expr>,
+    },
+    /// `let* x = e1 in e2` at `pos`, where `op` is `let*` or `let+`, and
+    /// `f` is `fn (x) -> e2`. The type checker elaborates it into
+    /// `bind(f, e1)` or `map(f, e1)`, which take the function first like
+    /// the prelude's `map` and `option_bind`.
+    BindingOp {
+        op: String,
+        pos: (usize, usize),
+        e1: Box<Expr>,
+        f: Box<Expr>,
+    },
+    /// `perform op(arg)`.
+    Perform {
+        op: String,
+        arg: Box<Expr>,
+    },
+    /// `handle body with | op(arg, k) -> e ... | return x -> e`. A handler
+    /// without a `return` clause returns the value of `body` as it is.
+    Handle {
+        body: Box<Expr>,
+        clauses: Vec<OpClause>,
+        ret: (String, Box<Expr>),
+    },
+    /// `assert cond`, which fails at `pos`, a line and column, when `cond`
+    /// is false.
+    Assert {
+        cond: Box<Expr>,
+        pos: (usize, usize),
+    },
+    /// Fails with `InterpretError::MatchFailure`. A refutable pattern at
+    /// `pos` is desugared into a check that evaluates to this when the value
+    /// does not match.
+    MatchFailure {
+        pos: (usize, usize),
+    },
+    /// `test "name" = e in body`. A program evaluates to `body`; the test
+    /// `e` is only evaluated, when evaluation reaches it, by
+    /// `Interpreter::run_tests`.
+    Test {
+        name: String,
+        pos: (usize, usize),
+        e: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// The methods of an instance, in the order the class declares them.
+    /// Classes and instances are elaborated into dictionaries by the type
+    /// checker, and dictionaries are passed to overloaded functions as
+    /// ordinary arguments.
+    Dict(Vec<Expr>),
+    /// The `index`th method in `dict`.
+    Method {
+        dict: Box<Expr>,
+        index: usize,
+    },
+    /// `(expr : typ)`. A `Dyn` in `typ` opts that part of `expr` out of
+    /// static inference.
+    Ascribe {
+        expr: Box<Expr>,
+        typ: MonoType,
+    },
+    /// `expr` of type `from`, checked at runtime to have type `to`. The
+    /// type checker inserts these where `Dyn` meets a static type.
+    Cast {
+        expr: Box<Expr>,
+        from: MonoType,
+        to: MonoType,
+    },
+    /// A number with a unit of measure, e.g. `5<m>`, where `measure` is a
+    /// `MonoType::Measure`. The unit only exists for the type checker.
+    Measured {
+        expr: Box<Expr>,
+        measure: MonoType,
+    },
+    /// A literal as it is spelled in the source, e.g. `0xFF` or `1_000`,
+    /// together with its value.
+    Literal {
+        spelling: String,
+        value: Box<Expr>,
+    },
 }
 
 impl Expr {
     pub fn is_value(&self) -> bool {
         match self {
-            Expr::Int(_) | Expr::Bool(_) | Expr::Fn { arg: _, body: _ } => true,
-            _ => false,
+            Expr::Dict(methods) => return methods.iter().all(Expr::is_value),
+            Expr::Record(fields) => return fields.iter().all(|(_, value)| value.is_value()),
+            Expr::OptArg(arg) => return arg.as_ref().is_none_or(|arg| arg.is_value()),
+            _ => {}
         }
+        matches!(
+            self,
+            Expr::Unit
+                | Expr::Int(_)
+                | Expr::Fixed(_)
+                | Expr::Bool(_)
+                | Expr::Str(_)
+                | Expr::Fn { arg: _, body: _ }
+                | Expr::Prim { prim: _, args: _ }
+                | Expr::Suspension(_)
+                | Expr::Array(_)
+                | Expr::Map(_)
+                | Expr::Task(..)
+                | Expr::Chan(..)
+        )
     }
 }
 
@@ -50,21 +479,80 @@ impl Expr {
 impl Display for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Expr::Unit => write!(f, "()"),
             Expr::Int(i) => write!(f, "{i}"),
+            Expr::Fixed(i) => write!(f, "{i}"),
             Expr::Bool(b) => write!(f, "{b}"),
-            Expr::Fn { arg: _, body: _ } => write!(f, "<function>"),
+            Expr::Str(s) => write!(f, "{s:?}"),
+            Expr::Fn { arg: _, body: _ } | Expr::Prim { prim: _, args: _ } => {
+                write!(f, "<function>")
+            }
+            Expr::Suspension(_) => write!(f, "<lazy>"),
+            Expr::Task(..) => write!(f, "<task>"),
+            Expr::Chan(..) => write!(f, "<chan>"),
+            Expr::Record(fields) if fields.is_empty() => write!(f, "{{}}"),
+            Expr::Record(fields) => {
+                write!(f, "{{ ")?;
+                for (i, (label, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{label} = {value}")?;
+                }
+                write!(f, " }}")
+            }
+            Expr::Array(values) => {
+                write!(f, "[|")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "|]")
+            }
+            Expr::Map(map) => {
+                write!(f, "{{|")?;
+                for (i, (key, value)) in map.entries().into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{key} => {value}")?;
+                }
+                write!(f, "|}}")
+            }
             other => unreachable!("Expression must be a value, but it is {other:?}"),
         }
     }
 }
 
+/// Fresh variable names that start with `prefix`, which no identifier can
+/// start with.
+#[derive(Clone, Debug)]
+pub(crate) struct VariableNameGenerator {
+    prefix: &'static str,
+    counter: u32,
+}
+
+impl VariableNameGenerator {
+    pub(crate) fn new(prefix: &'static str) -> Self {
+        VariableNameGenerator { prefix, counter: 0 }
+    }
+
+    pub(crate) fn next(&mut self) -> String {
+        self.counter += 1;
+        format!("{}{}", self.prefix, self.counter)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Expr;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_is_value() {
-        let e = Expr::Int(4);
+        let e = Expr::Int(4.into());
         assert_eq!(e.is_value(), true);
 
         let e = Expr::Bool(true);
@@ -72,5 +560,11 @@ mod tests {
 
         let e = Expr::Var("x".into());
         assert_eq!(e.is_value(), false);
+
+        let e = Expr::Literal {
+            spelling: "0x4".into(),
+            value: Box::new(Expr::Int(4.into())),
+        };
+        assert_eq!(e.is_value(), false);
     }
 }
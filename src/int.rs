@@ -0,0 +1,382 @@
+use num_bigint::{BigInt, ParseBigIntError, Sign};
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    ops::{Add, Mul},
+    str::FromStr,
+};
+
+/// An arbitrary-precision integer. Values that fit in an `i64` are always
+/// kept in `Small`, so ordinary arithmetic never allocates.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Int {
+    Small(i64),
+    Big(BigInt),
+}
+
+impl Int {
+    fn from_big(big: BigInt) -> Self {
+        match i64::try_from(&big) {
+            Ok(small) => Int::Small(small),
+            Err(_) => Int::Big(big),
+        }
+    }
+
+    fn to_big(&self) -> BigInt {
+        match self {
+            Int::Small(small) => BigInt::from(*small),
+            Int::Big(big) => big.clone(),
+        }
+    }
+
+    /// The low 64 bits in two's complement, as `i64` arithmetic would wrap.
+    pub fn wrapping_i64(&self) -> i64 {
+        match self {
+            Int::Small(small) => *small,
+            Int::Big(big) => {
+                let (sign, digits) = big.to_u64_digits();
+                let low = digits.first().copied().unwrap_or(0) as i64;
+                match sign {
+                    Sign::Minus => low.wrapping_neg(),
+                    _ => low,
+                }
+            }
+        }
+    }
+
+    /// Parse `digits` in `radix`, e.g. `ff` in 16. A leading `-` is allowed.
+    pub fn from_str_radix(digits: &str, radix: u32) -> Option<Int> {
+        BigInt::parse_bytes(digits.as_bytes(), radix).map(Int::from_big)
+    }
+
+    /// The nearest `i64`, as saturating `i64` arithmetic would clamp.
+    pub fn saturating_i64(&self) -> i64 {
+        match self {
+            Int::Small(small) => *small,
+            Int::Big(big) => match big.sign() {
+                Sign::Minus => i64::MIN,
+                _ => i64::MAX,
+            },
+        }
+    }
+}
+
+impl From<i64> for Int {
+    fn from(small: i64) -> Self {
+        Int::Small(small)
+    }
+}
+
+impl Add for &Int {
+    type Output = Int;
+
+    fn add(self, rhs: &Int) -> Int {
+        match (self, rhs) {
+            (Int::Small(lhs), Int::Small(rhs)) => match lhs.checked_add(*rhs) {
+                Some(sum) => Int::Small(sum),
+                None => Int::Big(BigInt::from(*lhs) + rhs),
+            },
+            _ => Int::from_big(self.to_big() + rhs.to_big()),
+        }
+    }
+}
+
+impl Mul for &Int {
+    type Output = Int;
+
+    fn mul(self, rhs: &Int) -> Int {
+        match (self, rhs) {
+            (Int::Small(lhs), Int::Small(rhs)) => match lhs.checked_mul(*rhs) {
+                Some(product) => Int::Small(product),
+                None => Int::Big(BigInt::from(*lhs) * rhs),
+            },
+            _ => Int::from_big(self.to_big() * rhs.to_big()),
+        }
+    }
+}
+
+impl PartialOrd for Int {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Int {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Int::Small(lhs), Int::Small(rhs)) => lhs.cmp(rhs),
+            _ => self.to_big().cmp(&other.to_big()),
+        }
+    }
+}
+
+impl FromStr for Int {
+    type Err = ParseBigIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(small) => Ok(Int::Small(small)),
+            Err(_) => Ok(Int::from_big(s.parse()?)),
+        }
+    }
+}
+
+impl Display for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Int::Small(small) => write!(f, "{small}"),
+            Int::Big(big) => write!(f, "{big}"),
+        }
+    }
+}
+
+/// The width and signedness of a fixed-width integer type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Width {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl Width {
+    pub const ALL: [Width; 8] = [
+        Width::I8,
+        Width::I16,
+        Width::I32,
+        Width::I64,
+        Width::U8,
+        Width::U16,
+        Width::U32,
+        Width::U64,
+    ];
+
+    pub fn bits(&self) -> u32 {
+        match self {
+            Width::I8 | Width::U8 => 8,
+            Width::I16 | Width::U16 => 16,
+            Width::I32 | Width::U32 => 32,
+            Width::I64 | Width::U64 => 64,
+        }
+    }
+
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Width::I8 | Width::I16 | Width::I32 | Width::I64)
+    }
+
+    /// Literal suffix, e.g. `u8` in `255u8`.
+    pub fn suffix(&self) -> String {
+        let sign = if self.is_signed() { "i" } else { "u" };
+        format!("{sign}{}", self.bits())
+    }
+
+    pub fn from_suffix(suffix: &str) -> Option<Width> {
+        Width::ALL
+            .into_iter()
+            .find(|width| width.suffix() == suffix)
+    }
+
+    fn mask(&self) -> u64 {
+        u64::MAX >> (64 - self.bits())
+    }
+}
+
+/// Type name, e.g. `UInt8`.
+impl Display for Width {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.is_signed() { "" } else { "U" };
+        write!(f, "{sign}Int{}", self.bits())
+    }
+}
+
+/// A fixed-width integer. Arithmetic wraps around in two's complement at the
+/// width of the operands, which always have the same width.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FixedInt {
+    width: Width,
+    /// The value's bit pattern, with every bit above `width` cleared.
+    bits: u64,
+}
+
+impl FixedInt {
+    fn new(width: Width, bits: u64) -> Self {
+        Self {
+            width,
+            bits: bits & width.mask(),
+        }
+    }
+
+    pub fn width(&self) -> Width {
+        self.width
+    }
+
+    /// `i` truncated to `width`, as a C cast would do.
+    pub fn wrapping_from(width: Width, i: &Int) -> Self {
+        Self::new(width, i.wrapping_i64() as u64)
+    }
+
+    /// `i`, if it is in the range of `width`.
+    pub fn checked_from(width: Width, i: &Int) -> Option<Self> {
+        let fixed = Self::wrapping_from(width, i);
+        if &fixed.to_int() == i {
+            Some(fixed)
+        } else {
+            None
+        }
+    }
+
+    /// The value sign-extended to 64 bits for signed widths, and
+    /// zero-extended for unsigned ones.
+    fn extended(&self) -> u64 {
+        if self.width.is_signed() {
+            let unused = 64 - self.width.bits();
+            (((self.bits << unused) as i64) >> unused) as u64
+        } else {
+            self.bits
+        }
+    }
+
+    pub fn to_int(&self) -> Int {
+        if self.width.is_signed() {
+            Int::Small(self.extended() as i64)
+        } else {
+            Int::from_big(BigInt::from(self.bits))
+        }
+    }
+
+    pub fn wrapping_add(&self, rhs: &Self) -> Self {
+        Self::new(self.width, self.bits.wrapping_add(rhs.bits))
+    }
+
+    pub fn wrapping_mul(&self, rhs: &Self) -> Self {
+        Self::new(self.width, self.bits.wrapping_mul(rhs.bits))
+    }
+
+    pub fn land(&self, rhs: &Self) -> Self {
+        Self::new(self.width, self.bits & rhs.bits)
+    }
+
+    pub fn lor(&self, rhs: &Self) -> Self {
+        Self::new(self.width, self.bits | rhs.bits)
+    }
+
+    pub fn lxor(&self, rhs: &Self) -> Self {
+        Self::new(self.width, self.bits ^ rhs.bits)
+    }
+
+    /// Shift left by `rhs`, read as unsigned. Every bit is shifted out once
+    /// `rhs` reaches the width.
+    pub fn lsl(&self, rhs: &Self) -> Self {
+        match rhs.bits < self.width.bits() as u64 {
+            true => Self::new(self.width, self.bits << rhs.bits),
+            false => Self::new(self.width, 0),
+        }
+    }
+
+    /// Logical shift right by `rhs`, read as unsigned, filling with zeros
+    /// even for signed widths.
+    pub fn lsr(&self, rhs: &Self) -> Self {
+        match rhs.bits < self.width.bits() as u64 {
+            true => Self::new(self.width, self.bits >> rhs.bits),
+            false => Self::new(self.width, 0),
+        }
+    }
+}
+
+impl PartialOrd for FixedInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FixedInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.width.is_signed() {
+            true => (self.extended() as i64).cmp(&(other.extended() as i64)),
+            false => self.bits.cmp(&other.bits),
+        }
+    }
+}
+
+impl Display for FixedInt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_int())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FixedInt, Int, Width};
+
+    #[test]
+    fn test_promote_and_demote() {
+        let max = Int::from(i64::MAX);
+        let big = &max + &Int::from(1);
+        assert!(matches!(big, Int::Big(_)));
+        assert_eq!(big.to_string(), "9223372036854775808");
+
+        let back = &big + &Int::from(-1);
+        assert_eq!(back, max);
+
+        let square = &big * &big;
+        assert_eq!(square.to_string(), "85070591730234615865843651857942052864");
+        assert!(max < big && big < square);
+    }
+
+    #[test]
+    fn test_wrapping_and_saturating() {
+        let max = Int::from(i64::MAX);
+        let min = Int::from(i64::MIN);
+        let one = Int::from(1);
+        let minus_one = Int::from(-1);
+        assert_eq!((&max + &one).wrapping_i64(), i64::MIN);
+        assert_eq!((&min + &minus_one).wrapping_i64(), i64::MAX);
+        assert_eq!(
+            (&max * &max).wrapping_i64(),
+            i64::MAX.wrapping_mul(i64::MAX)
+        );
+        assert_eq!(
+            (&min * &max).wrapping_i64(),
+            i64::MIN.wrapping_mul(i64::MAX)
+        );
+        assert_eq!((&max + &one).saturating_i64(), i64::MAX);
+        assert_eq!((&min * &max).saturating_i64(), i64::MIN);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("-42".parse::<Int>().unwrap(), Int::Small(-42));
+        let long = "123456789012345678901234567890";
+        assert_eq!(long.parse::<Int>().unwrap().to_string(), long);
+    }
+
+    #[test]
+    fn test_fixed_width() {
+        let u8 = |i: i64| FixedInt::wrapping_from(Width::U8, &Int::from(i));
+        let i8 = |i: i64| FixedInt::wrapping_from(Width::I8, &Int::from(i));
+
+        assert_eq!(u8(255).wrapping_add(&u8(1)), u8(0));
+        assert_eq!(i8(127).wrapping_add(&i8(1)), i8(-128));
+        assert_eq!(i8(-128).wrapping_mul(&i8(-1)), i8(-128));
+        assert_eq!(i8(-1).to_int(), Int::from(-1));
+        assert_eq!(u8(-1).to_int(), Int::from(255));
+        assert!(i8(-1) < i8(0) && u8(0) < u8(-1));
+
+        assert_eq!(u8(0b1100).land(&u8(0b1010)), u8(0b1000));
+        assert_eq!(u8(0b1100).lor(&u8(0b1010)), u8(0b1110));
+        assert_eq!(u8(0b1100).lxor(&u8(0b1010)), u8(0b0110));
+        assert_eq!(u8(1).lsl(&u8(7)), u8(128));
+        assert_eq!(u8(1).lsl(&u8(8)), u8(0));
+        assert_eq!(i8(-128).lsr(&i8(7)), i8(1));
+
+        assert!(FixedInt::checked_from(Width::U8, &Int::from(256)).is_none());
+        assert!(FixedInt::checked_from(Width::I8, &Int::from(-129)).is_none());
+        let u64_max = "18446744073709551615".parse::<Int>().unwrap();
+        let fixed = FixedInt::checked_from(Width::U64, &u64_max).unwrap();
+        assert_eq!(fixed.to_int(), u64_max);
+    }
+}
@@ -1,60 +1,721 @@
-use crate::ast::{BinOp, Expr};
-use std::collections::HashSet;
+use crate::ast::{BinOp, Expr, OpClause, Prim, Thunk, VariableNameGenerator};
+use crate::int::{FixedInt, Int};
+use crate::map::Map;
+use crate::prelude::Prelude;
+use crate::types::{Label, MonoType};
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    io::{self, BufRead, Write},
+    rc::Rc,
+    str::FromStr,
+};
 
 #[derive(Clone, Debug)]
 pub enum InterpretError {
     UnboundValue(String),
     NotAValue,
+    /// An arithmetic result that does not fit in an `i64`, under
+    /// `OverflowPolicy::Checked`.
+    Overflow {
+        op: BinOp,
+        lhs: Int,
+        rhs: Int,
+    },
+    /// A value that cannot be compared: a function or suspension, or a
+    /// value of another shape than the one it is compared with. The type
+    /// checker rules both out, except for values cast from a `Dyn`.
+    Incomparable(Expr),
+    /// A failure to read or write the console or a file.
+    Io(String),
+    /// `assert` on a false condition, at a line and column.
+    AssertionFailed {
+        pos: (usize, usize),
+    },
+    /// An array indexed outside of `0..length`.
+    IndexOutOfBounds {
+        index: Int,
+        length: usize,
+    },
+    /// `map_get` of a key that is not in the map.
+    KeyNotFound(Expr),
+    /// A field selected or removed from a record without it. The type
+    /// checker rules this out, and casts from a `Dyn` blame the record
+    /// before it gets here.
+    MissingField(String),
+    /// A value that does not match the refutable pattern at `pos`.
+    MatchFailure {
+        pos: (usize, usize),
+    },
+    /// An operation performed by `perform op(arg)`, on its way out to the
+    /// nearest handler of `op`. `k` is the function that resumes the
+    /// computation from where `op` was performed: every evaluation step the
+    /// operation passes through on its way out adds itself to `k`. The
+    /// operation is an error only if no handler catches it.
+    Perform {
+        op: String,
+        arg: Box<Expr>,
+        k: Box<Expr>,
+    },
+    /// A value cast from the `Dyn` written at `pos` that does not have the
+    /// type it is used at.
+    Blame {
+        pos: (usize, usize),
+        expected: MonoType,
+        found: Box<Expr>,
+    },
+    /// Every task left is waiting for a task to finish or for a message on
+    /// a channel, so none of them ever will.
+    Deadlock,
+    /// A test that evaluating the program never reached, e.g. one in a
+    /// function that is never called.
+    TestNotReached,
 }
 
-#[derive(Clone, Debug)]
-struct VariableNameGenerator {
-    counter: u32,
+/// What `Interpreter::eval_bin` does with an arithmetic result that does not
+/// fit in an `i64`. `Int` can hold any integer, but only `Unbounded` keeps
+/// results outside the range of an `i64`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Fail with `InterpretError::Overflow`.
+    #[default]
+    Checked,
+    /// Wrap around in two's complement.
+    Wrapping,
+    /// Clamp to `i64::MIN` or `i64::MAX`.
+    Saturating,
+    /// Keep the exact result as a big integer.
+    Unbounded,
 }
 
-impl VariableNameGenerator {
-    fn new() -> Self {
-        VariableNameGenerator { counter: 0 }
-    }
+impl FromStr for OverflowPolicy {
+    type Err = String;
 
-    fn next(&mut self) -> String {
-        self.counter += 1;
-        format!("${}", self.counter)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "checked" => Ok(OverflowPolicy::Checked),
+            "wrapping" => Ok(OverflowPolicy::Wrapping),
+            "saturating" => Ok(OverflowPolicy::Saturating),
+            "unbounded" => Ok(OverflowPolicy::Unbounded),
+            _ => Err(format!("unknown overflow policy `{s}`")),
+        }
     }
 }
 
+/// The result of a `test` block: whether its condition held, or the error
+/// evaluating it ran into.
 #[derive(Clone, Debug)]
+pub struct TestOutcome {
+    pub name: String,
+    pub pos: (usize, usize),
+    pub result: Result<bool, InterpretError>,
+}
+
+#[derive(Clone)]
 pub struct Interpreter {
     var_name_generator: VariableNameGenerator,
+    overflow_policy: OverflowPolicy,
+    /// Stdin when `None`, which is only locked while a line is read.
+    input: Option<Rc<RefCell<dyn BufRead>>>,
+    output: Rc<RefCell<dyn Write>>,
+    /// The values of the prelude's bindings, which are substituted for the
+    /// free variables that name them before a program runs.
+    prelude: HashMap<String, Expr>,
+    /// The seed of the order tasks run in.
+    seed: u64,
+    /// The outcomes of the tests run so far, while `run_tests` runs.
+    test_outcomes: Option<Vec<TestOutcome>>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
         Self {
-            var_name_generator: VariableNameGenerator::new(),
+            var_name_generator: VariableNameGenerator::new("$"),
+            overflow_policy: OverflowPolicy::default(),
+            input: None,
+            output: Rc::new(RefCell::new(io::stdout())),
+            prelude: HashMap::new(),
+            seed: 0,
+            test_outcomes: None,
         }
     }
 
+    /// Start with the bindings of `prelude` in scope.
+    pub fn with_prelude(mut self, prelude: &Prelude) -> Self {
+        self.prelude = prelude.values().iter().cloned().collect();
+        self
+    }
+
+    /// Evaluate the prelude's binding of `x` to `e`, with those before it
+    /// in scope, and bring it into scope for those after it.
+    pub(crate) fn define(&mut self, x: &str, e: &Expr) -> Result<Expr, InterpretError> {
+        let value = self.eval(e)?;
+        self.prelude.insert(x.into(), value.clone());
+        Ok(value)
+    }
+
+    /// `expr` with the values of the prelude's bindings and the primitives
+    /// substituted for the free variables that name them. Those values
+    /// refer to no variable, so a `let` in `expr` can never capture what
+    /// they refer to, even though it shadows a binding of the prelude or a
+    /// primitive.
+    fn resolve_globals(&mut self, expr: &Expr) -> Result<Expr, InterpretError> {
+        let mut expr = expr.clone();
+        for (x, value) in self.prelude.clone() {
+            expr = self.substitute(&expr, &value, &x)?;
+        }
+        for prim in Prim::ALL {
+            let value = Expr::Prim { prim, args: vec![] };
+            expr = self.substitute(&expr, &value, &prim.to_string())?;
+        }
+        Ok(expr)
+    }
+
+    /// Read `read_line` from `input` instead of stdin.
+    pub fn with_input(mut self, input: Rc<RefCell<dyn BufRead>>) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    /// Write what `print` prints to `output` instead of stdout, e.g. to a
+    /// shared `Vec<u8>` to capture it.
+    pub fn with_output(mut self, output: Rc<RefCell<dyn Write>>) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn with_overflow_policy(mut self, overflow_policy: OverflowPolicy) -> Self {
+        self.overflow_policy = overflow_policy;
+        self
+    }
+
+    /// Pick the order tasks run in with a generator seeded with `seed`,
+    /// instead of `0`.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Evaluate `expr` as the main task of a program. The tasks it spawns
+    /// take turns with it: a task runs until it spawns, awaits, sends or
+    /// receives, and then the scheduler picks the next task to run from
+    /// those that are not waiting. The program ends when the main task
+    /// does, whether other tasks have finished or not.
     pub fn eval(&mut self, expr: &Expr) -> Result<Expr, InterpretError> {
+        let mut scheduler = Scheduler::new(self.seed);
+        scheduler.results.push(None);
+        scheduler.ready.push((0, self.resolve_globals(expr)?));
+        while !scheduler.ready.is_empty() {
+            let (task, expr) = scheduler.pick();
+            let (op, arg, k) = match self.eval_expr(&expr) {
+                Ok(value) if task == 0 => return Ok(value),
+                Ok(value) => {
+                    let (woken, awaiting) = scheduler
+                        .awaiting
+                        .drain(..)
+                        .partition(|(awaited, _, _)| *awaited == task);
+                    scheduler.awaiting = awaiting;
+                    for (_, waiter, k) in woken {
+                        scheduler.resume(waiter, k, value.clone());
+                    }
+                    scheduler.results[task] = Some(value);
+                    continue;
+                }
+                Err(InterpretError::Perform { op, arg, k }) => (op, *arg, *k),
+                Err(e) => return Err(e),
+            };
+            match (op.as_str(), arg) {
+                ("$spawn", f) => {
+                    let spawned = scheduler.results.len();
+                    scheduler.results.push(None);
+                    let body = Expr::Apply {
+                        func: Box::new(f),
+                        arg: Box::new(Expr::Unit),
+                    };
+                    scheduler.ready.push((spawned, body));
+                    scheduler.resume(task, k, Expr::Task(spawned, vec![]));
+                }
+                ("$await", Expr::Task(awaited, _)) => match &scheduler.results[awaited] {
+                    Some(value) => scheduler.resume(task, k, value.clone()),
+                    None => scheduler.awaiting.push((awaited, task, k)),
+                },
+                ("$chan", _) => {
+                    scheduler.channels.push(Channel::default());
+                    let chan = Expr::Chan(scheduler.channels.len() - 1, vec![]);
+                    scheduler.resume(task, k, chan);
+                }
+                ("$send", Expr::Dict(args)) => {
+                    let [Expr::Chan(chan, _), message] = <[Expr; 2]>::try_from(args).unwrap()
+                    else {
+                        unreachable!()
+                    };
+                    let channel = &mut scheduler.channels[chan];
+                    match channel.receivers.pop_front() {
+                        Some((receiver, rk)) => scheduler.resume(receiver, rk, message),
+                        None => channel.messages.push_back(message),
+                    }
+                    scheduler.resume(task, k, Expr::Unit);
+                }
+                ("$recv", Expr::Chan(chan, _)) => {
+                    let channel = &mut scheduler.channels[chan];
+                    match channel.messages.pop_front() {
+                        Some(message) => scheduler.resume(task, k, message),
+                        None => channel.receivers.push_back((task, k)),
+                    }
+                }
+                (_, arg) => {
+                    return Err(InterpretError::Perform {
+                        op,
+                        arg: Box::new(arg),
+                        k: Box::new(k),
+                    })
+                }
+            }
+        }
+        Err(InterpretError::Deadlock)
+    }
+
+    fn eval_expr(&mut self, expr: &Expr) -> Result<Expr, InterpretError> {
         let result = match expr {
-            Expr::Int(_) | Expr::Bool(_) => expr.clone(),
+            Expr::Unit
+            | Expr::Int(_)
+            | Expr::Fixed(_)
+            | Expr::Bool(_)
+            | Expr::Str(_)
+            | Expr::Prim { prim: _, args: _ } => expr.clone(),
+            // Variables bound by the program, the prelude or a primitive are
+            // substituted away before they are evaluated.
             Expr::Var(x) => Err(InterpretError::UnboundValue(x.clone()))?,
             Expr::Let { x, e1, e2 } => self.eval_let(x, e1, e2)?,
             Expr::If { guard, e1, e2 } => self.eval_if(guard, e1, e2)?,
-            Expr::Bin { op, e1, e2 } => self.eval_bin(op.clone(), e1, e2)?,
+            Expr::Bin { op, e1, e2 } => self.eval_bin(*op, e1, e2)?,
             Expr::Fn { arg: _, body: _ } => expr.clone(),
             Expr::Apply { func, arg } => self.eval_apply(func, arg)?,
+            Expr::Literal { spelling: _, value } => *value.clone(),
+            Expr::Lazy(e) => Expr::Suspension(Rc::new(RefCell::new(Thunk::Delayed(*e.clone())))),
+            Expr::Suspension(_)
+            | Expr::Array(_)
+            | Expr::Map(_)
+            | Expr::Task(..)
+            | Expr::Chan(..) => expr.clone(),
+            Expr::Dict(methods) => {
+                let mut values = vec![];
+                for (i, method) in methods.iter().enumerate() {
+                    let value = self.eval_in(method, |hole| {
+                        Expr::Dict(
+                            values
+                                .iter()
+                                .cloned()
+                                .chain([hole])
+                                .chain(methods[i + 1..].iter().cloned())
+                                .collect(),
+                        )
+                    })?;
+                    values.push(value);
+                }
+                Expr::Dict(values)
+            }
+            Expr::Method { dict, index } => {
+                let dict = self.eval_in(dict, |hole| Expr::Method {
+                    dict: Box::new(hole),
+                    index: *index,
+                })?;
+                match dict {
+                    Expr::Dict(methods) => methods[*index].clone(),
+                    _ => unreachable!(),
+                }
+            }
+            Expr::Record(fields) => {
+                let mut values = vec![];
+                for (i, (label, value)) in fields.iter().enumerate() {
+                    let value = self.eval_in(value, |hole| {
+                        Expr::Record(
+                            values
+                                .iter()
+                                .cloned()
+                                .chain([(label.clone(), hole)])
+                                .chain(fields[i + 1..].iter().cloned())
+                                .collect(),
+                        )
+                    })?;
+                    values.push((label.clone(), value));
+                }
+                Expr::Record(values)
+            }
+            Expr::Extend {
+                label,
+                value,
+                record,
+            } => {
+                let value = self.eval_in(value, |hole| Expr::Extend {
+                    label: label.clone(),
+                    value: Box::new(hole),
+                    record: record.clone(),
+                })?;
+                let record = self.eval_in(record, |hole| Expr::Extend {
+                    label: label.clone(),
+                    value: Box::new(value.clone()),
+                    record: Box::new(hole),
+                })?;
+                match record {
+                    Expr::Record(mut fields) => {
+                        fields.insert(0, (label.clone(), value));
+                        Expr::Record(fields)
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            Expr::Select { record, label } => match self.eval_in(record, |hole| Expr::Select {
+                record: Box::new(hole),
+                label: label.clone(),
+            })? {
+                Expr::Record(fields) => fields
+                    .into_iter()
+                    .find(|(l, _)| l == label)
+                    .map(|(_, value)| value)
+                    .ok_or_else(|| InterpretError::MissingField(label.clone()))?,
+                _ => unreachable!(),
+            },
+            Expr::Remove { record, label } => match self.eval_in(record, |hole| Expr::Remove {
+                record: Box::new(hole),
+                label: label.clone(),
+            })? {
+                Expr::Record(mut fields) => {
+                    let index = fields
+                        .iter()
+                        .position(|(l, _)| l == label)
+                        .ok_or_else(|| InterpretError::MissingField(label.clone()))?;
+                    fields.remove(index);
+                    Expr::Record(fields)
+                }
+                _ => unreachable!(),
+            },
+            Expr::Perform { op, arg } => {
+                let arg = self.eval_in(arg, |hole| Expr::Perform {
+                    op: op.clone(),
+                    arg: Box::new(hole),
+                })?;
+                let x = self.var_name_generator.next();
+                Err(InterpretError::Perform {
+                    op: op.clone(),
+                    arg: Box::new(arg),
+                    k: Box::new(Expr::Fn {
+                        arg: x.clone(),
+                        body: Box::new(Expr::Var(x)),
+                    }),
+                })?
+            }
+            Expr::Handle { body, clauses, ret } => self.eval_handle(body, clauses, ret)?,
+            Expr::Assert { cond, pos } => {
+                let cond = self.eval_in(cond, |hole| Expr::Assert {
+                    cond: Box::new(hole),
+                    pos: *pos,
+                })?;
+                match cond {
+                    Expr::Bool(true) => Expr::Unit,
+                    Expr::Bool(false) => Err(InterpretError::AssertionFailed { pos: *pos })?,
+                    _ => unreachable!(),
+                }
+            }
+            Expr::Test { name, pos, e, body } => {
+                if self.test_outcomes.is_some() {
+                    let result = match self.eval_in(e, |hole| Expr::Test {
+                        name: name.clone(),
+                        pos: *pos,
+                        e: Box::new(hole),
+                        body: body.clone(),
+                    }) {
+                        Err(perform @ InterpretError::Perform { .. }) => Err(perform)?,
+                        result => result.map(|value| matches!(value, Expr::Bool(true))),
+                    };
+                    self.test_outcomes.as_mut().unwrap().push(TestOutcome {
+                        name: name.clone(),
+                        pos: *pos,
+                        result,
+                    });
+                }
+                *body.clone()
+            }
+            Expr::OptArg(Some(arg)) => Expr::OptArg(Some(Box::new(
+                self.eval_in(arg, |hole| Expr::OptArg(Some(Box::new(hole))))?,
+            ))),
+            Expr::OptArg(None) => expr.clone(),
+            Expr::MatchFailure { pos } => Err(InterpretError::MatchFailure { pos: *pos })?,
+            Expr::OrDefault { arg, default } => match self.eval_in(arg, |hole| Expr::OrDefault {
+                arg: Box::new(hole),
+                default: default.clone(),
+            })? {
+                Expr::OptArg(Some(arg)) => *arg,
+                Expr::OptArg(None) => *default.clone(),
+                _ => unreachable!(),
+            },
+            Expr::Class { .. } | Expr::Instance { .. } | Expr::Effect { .. } => {
+                unreachable!("declarations are elaborated away by the type checker")
+            }
+            Expr::LabeledFn { .. } | Expr::LabeledApply { .. } => {
+                unreachable!("labels are elaborated away by the type checker")
+            }
+            Expr::BindingOp { .. } => {
+                unreachable!("binding operators are elaborated away by the type checker")
+            }
+            Expr::Ascribe { .. } => {
+                unreachable!("ascriptions are elaborated into casts by the type checker")
+            }
+            Expr::Measured { .. } => {
+                unreachable!("units of measure are elaborated away by the type checker")
+            }
+            Expr::Cast { expr: e, from, to } => {
+                let value = self.eval_in(e, |hole| Expr::Cast {
+                    expr: Box::new(hole),
+                    from: from.clone(),
+                    to: to.clone(),
+                })?;
+                self.cast(value, from, to)?
+            }
         };
         if !result.is_value() {
-            self.eval(&result)
+            self.eval_expr(&result)
         } else {
             Ok(result)
         }
     }
 
+    /// Run the tests in `expr`, which has been elaborated by the type
+    /// checker, by evaluating it once: each test is run in the scope it is
+    /// written in whenever evaluation reaches it, so a test in a function
+    /// body is run every time the function is called. Tests evaluation
+    /// never reaches, e.g. those in a function that is never called, fail
+    /// with `InterpretError::TestNotReached`. Also returns the result of
+    /// evaluating `expr`.
+    pub fn run_tests(&mut self, expr: &Expr) -> (Vec<TestOutcome>, Result<Expr, InterpretError>) {
+        self.test_outcomes = Some(vec![]);
+        let result = self.eval(expr);
+        let mut outcomes = self.test_outcomes.take().unwrap();
+        for (name, pos) in Self::tests(expr) {
+            if !outcomes.iter().any(|outcome| outcome.pos == pos) {
+                outcomes.push(TestOutcome {
+                    name,
+                    pos,
+                    result: Err(InterpretError::TestNotReached),
+                });
+            }
+        }
+        (outcomes, result)
+    }
+
+    /// The names and positions of the tests anywhere in `expr`.
+    fn tests(expr: &Expr) -> Vec<(String, (usize, usize))> {
+        match expr {
+            Expr::Unit
+            | Expr::Int(_)
+            | Expr::Fixed(_)
+            | Expr::Bool(_)
+            | Expr::Str(_)
+            | Expr::Prim { prim: _, args: _ }
+            | Expr::Suspension(_)
+            | Expr::Array(_)
+            | Expr::Map(_)
+            | Expr::Task(..)
+            | Expr::Chan(..)
+            | Expr::MatchFailure { .. }
+            | Expr::Var(_)
+            | Expr::Literal {
+                spelling: _,
+                value: _,
+            } => vec![],
+            Expr::Lazy(e)
+            | Expr::Method { dict: e, index: _ }
+            | Expr::Select {
+                record: e,
+                label: _,
+            }
+            | Expr::Remove {
+                record: e,
+                label: _,
+            }
+            | Expr::Perform { op: _, arg: e }
+            | Expr::Assert { cond: e, pos: _ }
+            | Expr::Fn { arg: _, body: e }
+            | Expr::Cast { expr: e, .. } => Self::tests(e),
+            Expr::OptArg(arg) => arg.as_ref().map_or_else(Vec::new, |arg| Self::tests(arg)),
+            Expr::Dict(methods) => methods.iter().flat_map(Self::tests).collect(),
+            Expr::Record(fields) => fields
+                .iter()
+                .flat_map(|(_, value)| Self::tests(value))
+                .collect(),
+            Expr::Extend {
+                label: _,
+                value: e1,
+                record: e2,
+            }
+            | Expr::OrDefault {
+                arg: e1,
+                default: e2,
+            }
+            | Expr::Let { x: _, e1, e2 }
+            | Expr::Bin { op: _, e1, e2 }
+            | Expr::Apply { func: e1, arg: e2 } => [Self::tests(e1), Self::tests(e2)].concat(),
+            Expr::If { guard, e1, e2 } => {
+                [Self::tests(guard), Self::tests(e1), Self::tests(e2)].concat()
+            }
+            Expr::Test { name, pos, e, body } => {
+                let mut tests = vec![(name.clone(), *pos)];
+                tests.extend(Self::tests(e));
+                tests.extend(Self::tests(body));
+                tests
+            }
+            Expr::Handle { body, clauses, ret } => {
+                let mut tests = Self::tests(body);
+                for clause in clauses {
+                    tests.extend(Self::tests(&clause.body));
+                }
+                tests.extend(Self::tests(&ret.1));
+                tests
+            }
+            Expr::Class { .. }
+            | Expr::Instance { .. }
+            | Expr::Effect { .. }
+            | Expr::LabeledFn { .. }
+            | Expr::LabeledApply { .. }
+            | Expr::BindingOp { .. }
+            | Expr::Ascribe { .. }
+            | Expr::Measured { .. } => unreachable!(),
+        }
+    }
+
+    /// Evaluate `expr`, where `context` rebuilds the expression it is part
+    /// of around its value. An operation performed in `expr` resumes in
+    /// `context` as well.
+    fn eval_in(
+        &mut self,
+        expr: &Expr,
+        context: impl FnOnce(Expr) -> Expr,
+    ) -> Result<Expr, InterpretError> {
+        match self.eval_expr(expr) {
+            Err(InterpretError::Perform { op, arg, k }) => {
+                let x = self.var_name_generator.next();
+                let resumed = Expr::Apply {
+                    func: k,
+                    arg: Box::new(Expr::Var(x.clone())),
+                };
+                Err(InterpretError::Perform {
+                    op,
+                    arg,
+                    k: Box::new(Expr::Fn {
+                        arg: x,
+                        body: Box::new(context(resumed)),
+                    }),
+                })
+            }
+            result => result,
+        }
+    }
+
+    fn eval_handle(
+        &mut self,
+        body: &Expr,
+        clauses: &[OpClause],
+        ret: &(String, Box<Expr>),
+    ) -> Result<Expr, InterpretError> {
+        match self.eval_expr(body) {
+            Ok(value) => self.substitute(&ret.1, &value, &ret.0),
+            Err(InterpretError::Perform { op, arg, k }) => {
+                // Resuming reinstalls the handler around the rest of `body`.
+                let x = self.var_name_generator.next();
+                let k = Box::new(Expr::Fn {
+                    arg: x.clone(),
+                    body: Box::new(Expr::Handle {
+                        body: Box::new(Expr::Apply {
+                            func: k,
+                            arg: Box::new(Expr::Var(x)),
+                        }),
+                        clauses: clauses.to_vec(),
+                        ret: ret.clone(),
+                    }),
+                });
+                match clauses.iter().find(|clause| clause.op == op) {
+                    Some(clause) => {
+                        let e = self.substitute(&clause.body, &arg, &clause.arg)?;
+                        self.substitute(&e, &k, &clause.k)
+                    }
+                    None => Err(InterpretError::Perform { op, arg, k }),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     fn freevars(&self, expr: &Expr) -> HashSet<String> {
         match expr {
-            Expr::Int(_) | Expr::Bool(_) => HashSet::new(),
+            Expr::Unit
+            | Expr::Int(_)
+            | Expr::Fixed(_)
+            | Expr::Bool(_)
+            | Expr::Str(_)
+            | Expr::Prim { prim: _, args: _ }
+            | Expr::Suspension(_)
+            | Expr::Array(_)
+            | Expr::Map(_)
+            | Expr::Task(..)
+            | Expr::Chan(..)
+            | Expr::MatchFailure { .. }
+            | Expr::Literal {
+                spelling: _,
+                value: _,
+            } => HashSet::new(),
+            Expr::Lazy(e) => self.freevars(e),
+            Expr::Dict(methods) => methods
+                .iter()
+                .flat_map(|method| self.freevars(method))
+                .collect(),
+            Expr::Method { dict, index: _ } => self.freevars(dict),
+            Expr::Record(fields) => fields
+                .iter()
+                .flat_map(|(_, value)| self.freevars(value))
+                .collect(),
+            Expr::Extend {
+                label: _,
+                value,
+                record,
+            } => &self.freevars(value) | &self.freevars(record),
+            Expr::Select { record, label: _ } | Expr::Remove { record, label: _ } => {
+                self.freevars(record)
+            }
+            Expr::Perform { op: _, arg } => self.freevars(arg),
+            Expr::Assert { cond, pos: _ } => self.freevars(cond),
+            Expr::Test { e, body, .. } => &self.freevars(e) | &self.freevars(body),
+            Expr::OptArg(arg) => arg
+                .as_ref()
+                .map_or_else(HashSet::new, |arg| self.freevars(arg)),
+            Expr::OrDefault { arg, default } => &self.freevars(arg) | &self.freevars(default),
+            Expr::Cast { expr, .. } => self.freevars(expr),
+            Expr::Handle { body, clauses, ret } => {
+                let mut freevars = self.freevars(body);
+                for clause in clauses {
+                    freevars.extend(
+                        &self.freevars(&clause.body)
+                            - &[clause.arg.clone(), clause.k.clone()].into(),
+                    );
+                }
+                freevars.extend(&self.freevars(&ret.1) - &[ret.0.clone()].into());
+                freevars
+            }
+            Expr::Class { .. }
+            | Expr::Instance { .. }
+            | Expr::Effect { .. }
+            | Expr::LabeledFn { .. }
+            | Expr::LabeledApply { .. }
+            | Expr::BindingOp { .. }
+            | Expr::Ascribe { .. }
+            | Expr::Measured { .. } => unreachable!(),
             Expr::Var(x) => [x.clone()].into(),
             Expr::Let { x, e1, e2 } => {
                 &self.freevars(e1) | &(&(self.freevars(e2)) ^ &[x.clone()].into())
@@ -75,7 +736,125 @@ impl Interpreter {
         new_varname: &str,
     ) -> Result<Expr, InterpretError> {
         match expr {
-            Expr::Int(_) | Expr::Bool(_) => Ok(expr.clone()),
+            Expr::Unit
+            | Expr::Int(_)
+            | Expr::Fixed(_)
+            | Expr::Bool(_)
+            | Expr::Str(_)
+            | Expr::Prim { prim: _, args: _ }
+            | Expr::Suspension(_)
+            | Expr::Array(_)
+            | Expr::Map(_)
+            | Expr::Task(..)
+            | Expr::Chan(..)
+            | Expr::MatchFailure { .. }
+            | Expr::Literal {
+                spelling: _,
+                value: _,
+            } => Ok(expr.clone()),
+            Expr::Lazy(e) => Ok(Expr::Lazy(Box::new(self.replace(
+                e,
+                old_varname,
+                new_varname,
+            )?))),
+            Expr::Dict(methods) => Ok(Expr::Dict(
+                methods
+                    .iter()
+                    .map(|method| self.replace(method, old_varname, new_varname))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Expr::Method { dict, index } => Ok(Expr::Method {
+                dict: Box::new(self.replace(dict, old_varname, new_varname)?),
+                index: *index,
+            }),
+            Expr::Record(fields) => Ok(Expr::Record(
+                fields
+                    .iter()
+                    .map(|(label, value)| {
+                        Ok((
+                            label.clone(),
+                            self.replace(value, old_varname, new_varname)?,
+                        ))
+                    })
+                    .collect::<Result<_, _>>()?,
+            )),
+            Expr::Extend {
+                label,
+                value,
+                record,
+            } => Ok(Expr::Extend {
+                label: label.clone(),
+                value: Box::new(self.replace(value, old_varname, new_varname)?),
+                record: Box::new(self.replace(record, old_varname, new_varname)?),
+            }),
+            Expr::Select { record, label } => Ok(Expr::Select {
+                record: Box::new(self.replace(record, old_varname, new_varname)?),
+                label: label.clone(),
+            }),
+            Expr::Remove { record, label } => Ok(Expr::Remove {
+                record: Box::new(self.replace(record, old_varname, new_varname)?),
+                label: label.clone(),
+            }),
+            Expr::Perform { op, arg } => Ok(Expr::Perform {
+                op: op.clone(),
+                arg: Box::new(self.replace(arg, old_varname, new_varname)?),
+            }),
+            Expr::Assert { cond, pos } => Ok(Expr::Assert {
+                cond: Box::new(self.replace(cond, old_varname, new_varname)?),
+                pos: *pos,
+            }),
+            Expr::Test { name, pos, e, body } => Ok(Expr::Test {
+                name: name.clone(),
+                pos: *pos,
+                e: Box::new(self.replace(e, old_varname, new_varname)?),
+                body: Box::new(self.replace(body, old_varname, new_varname)?),
+            }),
+            Expr::OptArg(arg) => Ok(Expr::OptArg(
+                arg.as_ref()
+                    .map(|arg| Ok(Box::new(self.replace(arg, old_varname, new_varname)?)))
+                    .transpose()?,
+            )),
+            Expr::OrDefault { arg, default } => Ok(Expr::OrDefault {
+                arg: Box::new(self.replace(arg, old_varname, new_varname)?),
+                default: Box::new(self.replace(default, old_varname, new_varname)?),
+            }),
+            Expr::Cast { expr, from, to } => Ok(Expr::Cast {
+                expr: Box::new(self.replace(expr, old_varname, new_varname)?),
+                from: from.clone(),
+                to: to.clone(),
+            }),
+            Expr::Handle { body, clauses, ret } => {
+                let rename = |x: &String| match x == old_varname {
+                    true => new_varname.to_owned(),
+                    false => x.clone(),
+                };
+                Ok(Expr::Handle {
+                    body: Box::new(self.replace(body, old_varname, new_varname)?),
+                    clauses: clauses
+                        .iter()
+                        .map(|clause| {
+                            Ok(OpClause {
+                                op: clause.op.clone(),
+                                arg: rename(&clause.arg),
+                                k: rename(&clause.k),
+                                body: self.replace(&clause.body, old_varname, new_varname)?,
+                            })
+                        })
+                        .collect::<Result<_, _>>()?,
+                    ret: (
+                        rename(&ret.0),
+                        Box::new(self.replace(&ret.1, old_varname, new_varname)?),
+                    ),
+                })
+            }
+            Expr::Class { .. }
+            | Expr::Instance { .. }
+            | Expr::Effect { .. }
+            | Expr::LabeledFn { .. }
+            | Expr::LabeledApply { .. }
+            | Expr::BindingOp { .. }
+            | Expr::Ascribe { .. }
+            | Expr::Measured { .. } => unreachable!(),
             Expr::Var(varname) => {
                 if varname == old_varname {
                     Ok(Expr::Var(new_varname.to_owned()))
@@ -104,7 +883,7 @@ impl Interpreter {
                 e2: Box::new(self.replace(e2, old_varname, new_varname)?),
             }),
             Expr::Bin { op, e1, e2 } => Ok(Expr::Bin {
-                op: op.clone(),
+                op: *op,
                 e1: Box::new(self.replace(e1, old_varname, new_varname)?),
                 e2: Box::new(self.replace(e2, old_varname, new_varname)?),
             }),
@@ -138,7 +917,119 @@ impl Interpreter {
         let value = value.clone();
         match value.is_value() {
             true => match expr {
-                Expr::Int(_) | Expr::Bool(_) => Ok(expr),
+                Expr::Unit
+                | Expr::Int(_)
+                | Expr::Fixed(_)
+                | Expr::Bool(_)
+                | Expr::Str(_)
+                | Expr::Prim { prim: _, args: _ }
+                | Expr::Suspension(_)
+                | Expr::Array(_)
+                | Expr::Map(_)
+                | Expr::Task(..)
+                | Expr::Chan(..)
+                | Expr::MatchFailure { .. }
+                | Expr::Literal {
+                    spelling: _,
+                    value: _,
+                } => Ok(expr),
+                Expr::Lazy(e) => Ok(Expr::Lazy(Box::new(self.substitute(&e, &value, varname)?))),
+                Expr::Dict(methods) => Ok(Expr::Dict(
+                    methods
+                        .iter()
+                        .map(|method| self.substitute(method, &value, varname))
+                        .collect::<Result<_, _>>()?,
+                )),
+                Expr::Method { dict, index } => Ok(Expr::Method {
+                    dict: Box::new(self.substitute(&dict, &value, varname)?),
+                    index,
+                }),
+                Expr::Record(fields) => Ok(Expr::Record(
+                    fields
+                        .into_iter()
+                        .map(|(label, e)| Ok((label, self.substitute(&e, &value, varname)?)))
+                        .collect::<Result<_, _>>()?,
+                )),
+                Expr::Extend {
+                    label,
+                    value: e,
+                    record,
+                } => Ok(Expr::Extend {
+                    label,
+                    value: Box::new(self.substitute(&e, &value, varname)?),
+                    record: Box::new(self.substitute(&record, &value, varname)?),
+                }),
+                Expr::Select { record, label } => Ok(Expr::Select {
+                    record: Box::new(self.substitute(&record, &value, varname)?),
+                    label,
+                }),
+                Expr::Remove { record, label } => Ok(Expr::Remove {
+                    record: Box::new(self.substitute(&record, &value, varname)?),
+                    label,
+                }),
+                Expr::Perform { op, arg } => Ok(Expr::Perform {
+                    op,
+                    arg: Box::new(self.substitute(&arg, &value, varname)?),
+                }),
+                Expr::Assert { cond, pos } => Ok(Expr::Assert {
+                    cond: Box::new(self.substitute(&cond, &value, varname)?),
+                    pos,
+                }),
+                Expr::Test { name, pos, e, body } => Ok(Expr::Test {
+                    name,
+                    pos,
+                    e: Box::new(self.substitute(&e, &value, varname)?),
+                    body: Box::new(self.substitute(&body, &value, varname)?),
+                }),
+                Expr::OptArg(arg) => Ok(Expr::OptArg(
+                    arg.map(|arg| Ok(Box::new(self.substitute(&arg, &value, varname)?)))
+                        .transpose()?,
+                )),
+                Expr::OrDefault { arg, default } => Ok(Expr::OrDefault {
+                    arg: Box::new(self.substitute(&arg, &value, varname)?),
+                    default: Box::new(self.substitute(&default, &value, varname)?),
+                }),
+                Expr::Cast { expr, from, to } => Ok(Expr::Cast {
+                    expr: Box::new(self.substitute(&expr, &value, varname)?),
+                    from,
+                    to,
+                }),
+                Expr::Handle { body, clauses, ret } => {
+                    let body = Box::new(self.substitute(&body, &value, varname)?);
+                    let clauses = clauses
+                        .into_iter()
+                        .map(|clause| {
+                            let (binders, body) = self.substitute_under(
+                                vec![clause.arg, clause.k],
+                                &clause.body,
+                                &value,
+                                varname,
+                            )?;
+                            let [arg, k] = <[String; 2]>::try_from(binders).unwrap();
+                            Ok(OpClause {
+                                op: clause.op,
+                                arg,
+                                k,
+                                body,
+                            })
+                        })
+                        .collect::<Result<_, _>>()?;
+                    let (mut binders, ret_body) =
+                        self.substitute_under(vec![ret.0], &ret.1, &value, varname)?;
+                    Ok(Expr::Handle {
+                        body,
+                        clauses,
+                        ret: (binders.remove(0), Box::new(ret_body)),
+                    })
+                }
+                Expr::Class { .. }
+                | Expr::Instance { .. }
+                | Expr::Effect { .. }
+                | Expr::LabeledFn { .. }
+                | Expr::LabeledApply { .. }
+                | Expr::BindingOp { .. }
+                | Expr::Ascribe { .. }
+                | Expr::Measured { .. } => unreachable!(),
                 Expr::Var(x) => {
                     if x == varname {
                         Ok(value)
@@ -168,7 +1059,7 @@ impl Interpreter {
                     e2: Box::new(self.substitute(&e2, &value, varname)?),
                 }),
                 Expr::Bin { op, e1, e2 } => Ok(Expr::Bin {
-                    op: op,
+                    op,
                     e1: Box::new(self.substitute(&e1, &value, varname)?),
                     e2: Box::new(self.substitute(&e2, &value, varname)?),
                 }),
@@ -205,20 +1096,54 @@ impl Interpreter {
         }
     }
 
+    /// `body`, in which `binders` are bound, with `value` substituted for
+    /// `varname`. Binders that would capture a free variable of `value` are
+    /// renamed, and returned together with the new body.
+    fn substitute_under(
+        &mut self,
+        mut binders: Vec<String>,
+        body: &Expr,
+        value: &Expr,
+        varname: &str,
+    ) -> Result<(Vec<String>, Expr), InterpretError> {
+        if binders.iter().any(|binder| binder == varname) {
+            return Ok((binders, body.clone()));
+        }
+        let freevars = self.freevars(value);
+        let mut body = body.clone();
+        for binder in binders.iter_mut() {
+            if freevars.contains(binder) {
+                let fresh = self.var_name_generator.next();
+                body = self.replace(&body, binder, &fresh)?;
+                *binder = fresh;
+            }
+        }
+        let body = self.substitute(&body, value, varname)?;
+        Ok((binders, body))
+    }
+
     fn eval_let(&mut self, x: &str, e1: &Expr, e2: &Expr) -> Result<Expr, InterpretError> {
-        let v1 = self.eval(e1)?;
+        let v1 = self.eval_in(e1, |hole| Expr::Let {
+            x: x.to_owned(),
+            e1: Box::new(hole),
+            e2: Box::new(e2.clone()),
+        })?;
         let expr = self.substitute(e2, &v1, x)?;
-        self.eval(&expr)
+        self.eval_expr(&expr)
     }
 
     fn eval_if(&mut self, guard: &Expr, e1: &Expr, e2: &Expr) -> Result<Expr, InterpretError> {
-        let guard_value = self.eval(guard)?;
+        let guard_value = self.eval_in(guard, |hole| Expr::If {
+            guard: Box::new(hole),
+            e1: Box::new(e1.clone()),
+            e2: Box::new(e2.clone()),
+        })?;
         match guard_value {
             Expr::Bool(b) => {
-                if b == true {
-                    Ok(self.eval(e1)?)
+                if b {
+                    Ok(self.eval_expr(e1)?)
                 } else {
-                    Ok(self.eval(e2)?)
+                    Ok(self.eval_expr(e2)?)
                 }
             }
             _ => unreachable!(),
@@ -226,24 +1151,1338 @@ impl Interpreter {
     }
 
     fn eval_bin(&mut self, op: BinOp, e1: &Expr, e2: &Expr) -> Result<Expr, InterpretError> {
-        let v1 = self.eval(e1)?;
-        let v2 = self.eval(e2)?;
-        match (v1, v2) {
-            (Expr::Int(v1), Expr::Int(v2)) => match op {
-                BinOp::Plus => Ok(Expr::Int(v1 + v2)),
-                BinOp::Times => Ok(Expr::Int(v1 * v2)),
+        let v1 = self.eval_in(e1, |hole| Expr::Bin {
+            op,
+            e1: Box::new(hole),
+            e2: Box::new(e2.clone()),
+        })?;
+        let v2 = self.eval_in(e2, |hole| Expr::Bin {
+            op,
+            e1: Box::new(v1.clone()),
+            e2: Box::new(hole),
+        })?;
+        match (op, v1, v2) {
+            (BinOp::Plus, Expr::Int(v1), Expr::Int(v2)) => {
+                let result = &v1 + &v2;
+                self.apply_overflow_policy(op, v1, v2, result)
+            }
+            (BinOp::Times, Expr::Int(v1), Expr::Int(v2)) => {
+                let result = &v1 * &v2;
+                self.apply_overflow_policy(op, v1, v2, result)
+            }
+            (BinOp::Le, Expr::Int(v1), Expr::Int(v2)) => Ok(Expr::Bool(v1 <= v2)),
+            (BinOp::Eq, v1, v2) => Ok(Expr::Bool(Self::equal(&v1, &v2)?)),
+            // Fixed-width arithmetic always wraps, whatever the overflow policy.
+            (op, Expr::Fixed(v1), Expr::Fixed(v2)) => match op {
+                BinOp::Plus => Ok(Expr::Fixed(v1.wrapping_add(&v2))),
+                BinOp::Times => Ok(Expr::Fixed(v1.wrapping_mul(&v2))),
                 BinOp::Le => Ok(Expr::Bool(v1 <= v2)),
+                BinOp::Land => Ok(Expr::Fixed(v1.land(&v2))),
+                BinOp::Lor => Ok(Expr::Fixed(v1.lor(&v2))),
+                BinOp::Lxor => Ok(Expr::Fixed(v1.lxor(&v2))),
+                BinOp::Lsl => Ok(Expr::Fixed(v1.lsl(&v2))),
+                BinOp::Lsr => Ok(Expr::Fixed(v1.lsr(&v2))),
+                // The pipeline and composition operators are elaborated into
+                // functions by the type checker.
+                BinOp::Eq
+                | BinOp::Concat
+                | BinOp::Pipe
+                | BinOp::PipeBack
+                | BinOp::Compose
+                | BinOp::ComposeBack => unreachable!(),
             },
+            (BinOp::Concat, Expr::Str(v1), Expr::Str(v2)) => Ok(Expr::Str(v1 + &v2)),
+            // Values cast from a `Dyn` to a type variable are only compared
+            // here.
+            (BinOp::Le, v1, v2) => Ok(Expr::Bool(Self::compare(&v1, &v2)?.is_le())),
             _ => unreachable!(),
         }
     }
 
+    /// Structural equality of two values of the same type. Functions and
+    /// suspensions cannot be compared, and neither can values of different
+    /// shapes that came out of a `Dyn`.
+    fn equal(v1: &Expr, v2: &Expr) -> Result<bool, InterpretError> {
+        match (v1, v2) {
+            (Expr::Int(v1), Expr::Int(v2)) => Ok(v1 == v2),
+            (Expr::Fixed(v1), Expr::Fixed(v2)) => Ok(v1 == v2),
+            (Expr::Bool(v1), Expr::Bool(v2)) => Ok(v1 == v2),
+            (Expr::Unit, Expr::Unit) => Ok(true),
+            (Expr::Str(v1), Expr::Str(v2)) => Ok(v1 == v2),
+            // Records of the same type have the same labels, though not
+            // necessarily in the same order.
+            (Expr::Record(fields1), Expr::Record(fields2)) => {
+                let sorted = |fields: &[(String, Expr)]| {
+                    let mut fields = fields.to_vec();
+                    fields.sort_by(|(l1, _), (l2, _)| l1.cmp(l2));
+                    fields
+                };
+                for ((_, v1), (_, v2)) in sorted(fields1).iter().zip(sorted(fields2).iter()) {
+                    if !Self::equal(v1, v2)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            (Expr::Array(_), _) | (Expr::Map(_), _) => {
+                Ok(Self::compare(v1, v2)? == Ordering::Equal)
+            }
+            (Expr::Fn { .. } | Expr::Prim { .. } | Expr::Suspension(_), _) => {
+                Err(InterpretError::Incomparable(v1.clone()))
+            }
+            _ => Err(InterpretError::Incomparable(v2.clone())),
+        }
+    }
+
+    /// A total order on values of the same type, which agrees with `equal`.
+    /// The keys of maps are kept in this order.
+    fn compare(v1: &Expr, v2: &Expr) -> Result<Ordering, InterpretError> {
+        fn lexicographic<'a>(
+            pairs: impl Iterator<Item = (&'a Expr, &'a Expr)>,
+        ) -> Result<Ordering, InterpretError> {
+            for (v1, v2) in pairs {
+                match Interpreter::compare(v1, v2)? {
+                    Ordering::Equal => {}
+                    ordering => return Ok(ordering),
+                }
+            }
+            Ok(Ordering::Equal)
+        }
+
+        match (v1, v2) {
+            (Expr::Int(v1), Expr::Int(v2)) => Ok(v1.cmp(v2)),
+            (Expr::Fixed(v1), Expr::Fixed(v2)) => Ok(v1.cmp(v2)),
+            (Expr::Bool(v1), Expr::Bool(v2)) => Ok(v1.cmp(v2)),
+            (Expr::Unit, Expr::Unit) => Ok(Ordering::Equal),
+            (Expr::Str(v1), Expr::Str(v2)) => Ok(v1.cmp(v2)),
+            (Expr::Record(fields1), Expr::Record(fields2)) => {
+                let sorted = |fields: &[(String, Expr)]| {
+                    let mut fields = fields.to_vec();
+                    fields.sort_by(|(l1, _), (l2, _)| l1.cmp(l2));
+                    fields
+                };
+                let (fields1, fields2) = (sorted(fields1), sorted(fields2));
+                lexicographic(
+                    fields1
+                        .iter()
+                        .zip(fields2.iter())
+                        .map(|((_, v1), (_, v2))| (v1, v2)),
+                )
+            }
+            (Expr::Array(values1), Expr::Array(values2)) => {
+                Ok(lexicographic(values1.iter().zip(values2.iter()))?
+                    .then(values1.len().cmp(&values2.len())))
+            }
+            (Expr::Map(map1), Expr::Map(map2)) => {
+                let (entries1, entries2) = (map1.entries(), map2.entries());
+                let pairs = entries1
+                    .iter()
+                    .zip(entries2.iter())
+                    .flat_map(|((k1, v1), (k2, v2))| [(*k1, *k2), (*v1, *v2)]);
+                Ok(lexicographic(pairs)?.then(map1.len().cmp(&map2.len())))
+            }
+            (Expr::Fn { .. } | Expr::Prim { .. } | Expr::Suspension(_), _) => {
+                Err(InterpretError::Incomparable(v1.clone()))
+            }
+            _ => Err(InterpretError::Incomparable(v2.clone())),
+        }
+    }
+
+    /// `index` as an index into an array of `length` elements.
+    fn index(index: &Int, length: usize) -> Result<usize, InterpretError> {
+        match index {
+            Int::Small(i) if (0..length as i64).contains(i) => Ok(*i as usize),
+            _ => Err(InterpretError::IndexOutOfBounds {
+                index: index.clone(),
+                length,
+            }),
+        }
+    }
+
+    fn apply_overflow_policy(
+        &self,
+        op: BinOp,
+        lhs: Int,
+        rhs: Int,
+        result: Int,
+    ) -> Result<Expr, InterpretError> {
+        let result = match (self.overflow_policy, result) {
+            (OverflowPolicy::Unbounded, result) | (_, result @ Int::Small(_)) => result,
+            (OverflowPolicy::Checked, Int::Big(_)) => {
+                Err(InterpretError::Overflow { op, lhs, rhs })?
+            }
+            (OverflowPolicy::Wrapping, result) => Int::from(result.wrapping_i64()),
+            (OverflowPolicy::Saturating, result) => Int::from(result.saturating_i64()),
+        };
+        Ok(Expr::Int(result))
+    }
+
     fn eval_apply(&mut self, func: &Expr, arg: &Expr) -> Result<Expr, InterpretError> {
-        let func_final = self.eval(func)?;
-        let val = self.eval(arg)?;
+        let func_final = self.eval_in(func, |hole| Expr::Apply {
+            func: Box::new(hole),
+            arg: Box::new(arg.clone()),
+        })?;
+        let val = self.eval_in(arg, |hole| Expr::Apply {
+            func: Box::new(func_final.clone()),
+            arg: Box::new(hole),
+        })?;
         match func_final {
             Expr::Fn { arg, body } => self.substitute(&body, &val, &arg),
+            Expr::Prim { prim, mut args } => {
+                args.push(val);
+                if args.len() == prim.arity() {
+                    self.eval_prim(prim, args)
+                } else {
+                    Ok(Expr::Prim { prim, args })
+                }
+            }
             _ => unreachable!(),
         }
     }
+
+    /// Cast the value `v` of type `from` to type `to`. Only a value cast
+    /// from a `Dyn` is checked. Casts then go on to the parts of the
+    /// value, and a function is wrapped so that its argument and result
+    /// are cast whenever it is applied.
+    fn cast(&mut self, v: Expr, from: &MonoType, to: &MonoType) -> Result<Expr, InterpretError> {
+        match (from, to) {
+            // A value going into a `Dyn` is cast to its shape first, so that
+            // e.g. a function checks the arguments it is later applied to.
+            (_, MonoType::Dyn(pos)) => match Self::ground(from, *pos) {
+                Some(ground) => self.cast(v, from, &ground),
+                None => Ok(v),
+            },
+            (_, MonoType::TypeVariable(_)) => Ok(v),
+            (MonoType::Dyn(pos), _) => self.project(v, to, *pos),
+            (
+                MonoType::Fn(a1, r1) | MonoType::Labeled(_, a1, r1),
+                MonoType::Fn(a2, r2) | MonoType::Labeled(_, a2, r2),
+            ) => {
+                let x = self.var_name_generator.next();
+                // An optional argument is passed wrapped, and left alone.
+                let arg = match to {
+                    MonoType::Labeled(Label::Optional(_), _, _) => Expr::Var(x.clone()),
+                    _ => Expr::Cast {
+                        expr: Box::new(Expr::Var(x.clone())),
+                        from: *a2.clone(),
+                        to: *a1.clone(),
+                    },
+                };
+                Ok(Expr::Fn {
+                    arg: x,
+                    body: Box::new(Expr::Cast {
+                        expr: Box::new(Expr::Apply {
+                            func: Box::new(v),
+                            arg: Box::new(arg),
+                        }),
+                        from: *r1.clone(),
+                        to: *r2.clone(),
+                    }),
+                })
+            }
+            (MonoType::Lazy(t1), MonoType::Lazy(t2)) => Ok(Expr::Suspension(Rc::new(
+                RefCell::new(Thunk::Delayed(Expr::Cast {
+                    expr: Box::new(Expr::Apply {
+                        func: Box::new(Expr::Prim {
+                            prim: Prim::Force,
+                            args: vec![],
+                        }),
+                        arg: Box::new(v),
+                    }),
+                    from: *t1.clone(),
+                    to: *t2.clone(),
+                })),
+            ))),
+            // Tasks and channels are shared, so they are not copied: the
+            // cast is kept with them, and made where a value comes out or
+            // goes in.
+            (MonoType::Task(t1), MonoType::Task(t2)) => match v {
+                Expr::Task(id, mut casts) => {
+                    casts.push((*t1.clone(), *t2.clone()));
+                    Ok(Expr::Task(id, casts))
+                }
+                _ => unreachable!(),
+            },
+            (MonoType::Chan(t1), MonoType::Chan(t2)) => match v {
+                Expr::Chan(id, mut casts) => {
+                    casts.push((*t1.clone(), *t2.clone()));
+                    Ok(Expr::Chan(id, casts))
+                }
+                _ => unreachable!(),
+            },
+            (MonoType::Array(t1), MonoType::Array(t2)) => match v {
+                Expr::Array(values) => Ok(Expr::Array(Rc::new(
+                    values
+                        .iter()
+                        .map(|value| self.cast(value.clone(), t1, t2))
+                        .collect::<Result<_, _>>()?,
+                ))),
+                _ => unreachable!(),
+            },
+            (MonoType::Map(k1, v1), MonoType::Map(k2, v2)) => match v {
+                Expr::Map(map) => {
+                    let mut cast = Map::new();
+                    for (key, value) in map.entries() {
+                        let key = self.cast(key.clone(), k1, k2)?;
+                        let value = self.cast(value.clone(), v1, v2)?;
+                        cast = cast.insert(key, value, &mut Self::compare)?;
+                    }
+                    Ok(Expr::Map(cast))
+                }
+                _ => unreachable!(),
+            },
+            // Only the first of the fields with the same label has the type
+            // the row gives it.
+            (MonoType::Record(r1), MonoType::Record(r2)) => match v {
+                Expr::Record(fields) => {
+                    let (from_fields, _) = MonoType::row_fields(r1);
+                    let (to_fields, _) = MonoType::row_fields(r2);
+                    fn find<'a>(
+                        fields: &[(&String, &'a MonoType)],
+                        label: &str,
+                    ) -> Option<&'a MonoType> {
+                        fields.iter().find(|(l, _)| *l == label).map(|(_, t)| *t)
+                    }
+                    let mut seen = HashSet::new();
+                    let mut cast = vec![];
+                    for (label, value) in fields {
+                        let types = (find(&from_fields, &label), find(&to_fields, &label));
+                        let value = match (seen.insert(label.clone()), types) {
+                            (true, (Some(t1), Some(t2))) => self.cast(value, t1, t2)?,
+                            _ => value,
+                        };
+                        cast.push((label, value));
+                    }
+                    Ok(Expr::Record(cast))
+                }
+                _ => unreachable!(),
+            },
+            _ => Ok(v),
+        }
+    }
+
+    /// Check that the value `v`, cast from the `Dyn` at `pos`, has the
+    /// shape of `to`, and cast its parts from `Dyn` in turn.
+    fn project(
+        &mut self,
+        v: Expr,
+        to: &MonoType,
+        pos: (usize, usize),
+    ) -> Result<Expr, InterpretError> {
+        let from = match (to, &v) {
+            // Units of measure are not known at runtime.
+            (MonoType::Measured(base, _), _) => return self.project(v, base, pos),
+            (MonoType::Unit, Expr::Unit)
+            | (MonoType::Int, Expr::Int(_))
+            | (MonoType::Bool, Expr::Bool(_))
+            | (MonoType::String, Expr::Str(_)) => return Ok(v),
+            (MonoType::Fixed(width), Expr::Fixed(i)) if i.width() == *width => return Ok(v),
+            (
+                MonoType::Fn(_, _) | MonoType::Labeled(_, _, _),
+                Expr::Fn { .. } | Expr::Prim { .. },
+            )
+            | (MonoType::Lazy(_), Expr::Suspension(_))
+            | (MonoType::Array(_), Expr::Array(_))
+            | (MonoType::Task(_), Expr::Task(..))
+            | (MonoType::Chan(_), Expr::Chan(..))
+            | (MonoType::Map(_, _), Expr::Map(_)) => match Self::ground(to, pos) {
+                Some(ground) => ground,
+                None => return Ok(v),
+            },
+            (MonoType::Record(row), Expr::Record(fields)) if Self::has_fields(fields, row) => {
+                MonoType::Record(Box::new(fields.iter().rev().fold(
+                    MonoType::RowEmpty,
+                    |row, (label, _)| {
+                        MonoType::RowExtend(
+                            label.clone(),
+                            Box::new(MonoType::Dyn(pos)),
+                            Box::new(row),
+                        )
+                    },
+                )))
+            }
+            _ => {
+                return Err(InterpretError::Blame {
+                    pos,
+                    expected: to.clone(),
+                    found: Box::new(v),
+                })
+            }
+        };
+        self.cast(v, &from, to)
+    }
+
+    /// `typ` with its parts replaced by the `Dyn` at `pos`, e.g. `Dyn ->
+    /// Dyn` for a function type, unless they already are `Dyn`s.
+    fn ground(typ: &MonoType, pos: (usize, usize)) -> Option<MonoType> {
+        let dynamic = || Box::new(MonoType::Dyn(pos));
+        let is_dyn = |t: &MonoType| matches!(t, MonoType::Dyn(_));
+        match typ {
+            MonoType::Fn(t1, t2) if !(is_dyn(t1) && is_dyn(t2)) => {
+                Some(MonoType::Fn(dynamic(), dynamic()))
+            }
+            MonoType::Labeled(label, t1, t2) if !(is_dyn(t1) && is_dyn(t2)) => {
+                Some(MonoType::Labeled(label.clone(), dynamic(), dynamic()))
+            }
+            MonoType::Map(t1, t2) if !(is_dyn(t1) && is_dyn(t2)) => {
+                Some(MonoType::Map(dynamic(), dynamic()))
+            }
+            MonoType::Lazy(t) if !is_dyn(t) => Some(MonoType::Lazy(dynamic())),
+            MonoType::Array(t) if !is_dyn(t) => Some(MonoType::Array(dynamic())),
+            MonoType::Task(t) if !is_dyn(t) => Some(MonoType::Task(dynamic())),
+            MonoType::Chan(t) if !is_dyn(t) => Some(MonoType::Chan(dynamic())),
+            MonoType::Record(row) => {
+                let (fields, rest) = MonoType::row_fields(row);
+                if fields.iter().all(|(_, t)| is_dyn(t)) {
+                    return None;
+                }
+                let rest = rest.map_or(MonoType::RowEmpty, |x| MonoType::TypeVariable(x.clone()));
+                Some(MonoType::Record(Box::new(
+                    fields.into_iter().rev().fold(rest, |row, (label, _)| {
+                        MonoType::RowExtend(label.clone(), dynamic(), Box::new(row))
+                    }),
+                )))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether a record has every field of `row`, and no others unless the
+    /// row may have more.
+    fn has_fields(fields: &[(String, Expr)], row: &MonoType) -> bool {
+        let (expected, rest) = MonoType::row_fields(row);
+        expected
+            .iter()
+            .all(|(l, _)| fields.iter().any(|(label, _)| label == *l))
+            && (rest.is_some()
+                || fields
+                    .iter()
+                    .all(|(label, _)| expected.iter().any(|(l, _)| *l == label)))
+    }
+
+    fn eval_prim(&mut self, prim: Prim, args: Vec<Expr>) -> Result<Expr, InterpretError> {
+        match (prim, args.as_slice()) {
+            (Prim::Show, [Expr::Str(s)]) => Ok(Expr::Str(s.clone())),
+            (Prim::Show, [v]) => Ok(Expr::Str(v.to_string())),
+            (Prim::ToInt, [Expr::Int(i)]) => Ok(Expr::Int(i.clone())),
+            (Prim::ToInt, [Expr::Fixed(i)]) => Ok(Expr::Int(i.to_int())),
+            (Prim::ToFixed(width), [Expr::Int(i)]) => {
+                Ok(Expr::Fixed(FixedInt::wrapping_from(width, i)))
+            }
+            (Prim::ToFixed(width), [Expr::Fixed(i)]) => {
+                Ok(Expr::Fixed(FixedInt::wrapping_from(width, &i.to_int())))
+            }
+            // The delayed expression is evaluated as the argument of
+            // `$memoize`, so that an operation performed in it resumes into
+            // memoizing the suspension.
+            (Prim::Force, [suspension @ Expr::Suspension(thunk)]) => match &*thunk.borrow() {
+                Thunk::Delayed(e) => Ok(Expr::Apply {
+                    func: Box::new(Expr::Prim {
+                        prim: Prim::Memoize,
+                        args: vec![suspension.clone()],
+                    }),
+                    arg: Box::new(e.clone()),
+                }),
+                Thunk::Forced(value) => Ok(value.clone()),
+            },
+            // The suspension may have been forced while its expression was
+            // evaluated, e.g. by resuming twice. The first value is kept.
+            (Prim::Memoize, [Expr::Suspension(thunk), value]) => {
+                let mut thunk = thunk.borrow_mut();
+                match &*thunk {
+                    Thunk::Forced(forced) => Ok(forced.clone()),
+                    Thunk::Delayed(_) => {
+                        *thunk = Thunk::Forced(value.clone());
+                        Ok(value.clone())
+                    }
+                }
+            }
+            (Prim::Print, [Expr::Str(s)]) => {
+                let mut output = self.output.borrow_mut();
+                writeln!(output, "{s}").map_err(|e| InterpretError::Io(e.to_string()))?;
+                Ok(Expr::Unit)
+            }
+            (Prim::ReadLine, [Expr::Unit]) => {
+                let mut line = String::new();
+                match &self.input {
+                    Some(input) => input.borrow_mut().read_line(&mut line),
+                    None => io::stdin().lock().read_line(&mut line),
+                }
+                .map_err(|e| InterpretError::Io(e.to_string()))?;
+                let line = line.strip_suffix('\n').unwrap_or(&line);
+                Ok(Expr::Str(
+                    line.strip_suffix('\r').unwrap_or(line).to_owned(),
+                ))
+            }
+            (Prim::ReadFile, [Expr::Str(path)]) => fs::read_to_string(path)
+                .map(Expr::Str)
+                .map_err(|e| InterpretError::Io(format!("{path}: {e}"))),
+            (Prim::WriteFile, [Expr::Str(path), Expr::Str(contents)]) => fs::write(path, contents)
+                .map(|_| Expr::Unit)
+                .map_err(|e| InterpretError::Io(format!("{path}: {e}"))),
+            // Each call of `f` is the argument of the rest of the loop, so
+            // that an operation performed in it resumes the loop as well.
+            (Prim::ArrayInit, [Expr::Int(n), f]) => Ok(Self::array_init_step(n, f, vec![])),
+            (Prim::ArrayInitFrom, [Expr::Int(n), f, Expr::Array(values), value]) => {
+                let mut values = values.to_vec();
+                values.push(value.clone());
+                Ok(Self::array_init_step(n, f, values))
+            }
+            (Prim::ArrayGet, [Expr::Array(values), Expr::Int(i)]) => {
+                Ok(values[Self::index(i, values.len())?].clone())
+            }
+            (Prim::ArraySet, [Expr::Array(values), Expr::Int(i), value]) => {
+                let i = Self::index(i, values.len())?;
+                let mut values = values.to_vec();
+                values[i] = value.clone();
+                Ok(Expr::Array(Rc::new(values)))
+            }
+            (Prim::ArrayLength, [Expr::Array(values)]) => {
+                Ok(Expr::Int((values.len() as i64).into()))
+            }
+            (Prim::ArrayFold, [f, init, array]) => {
+                let i = Expr::Int(0.into());
+                let args = vec![f.clone(), array.clone(), i, init.clone()];
+                self.eval_prim(Prim::ArrayFoldFrom, args)
+            }
+            (Prim::ArrayFoldFrom, [f, array @ Expr::Array(values), Expr::Int(i), acc]) => {
+                let i = i.saturating_i64() as usize;
+                let Some(value) = values.get(i) else {
+                    return Ok(acc.clone());
+                };
+                let step = Expr::Apply {
+                    func: Box::new(Expr::Apply {
+                        func: Box::new(f.clone()),
+                        arg: Box::new(acc.clone()),
+                    }),
+                    arg: Box::new(value.clone()),
+                };
+                Ok(Expr::Apply {
+                    func: Box::new(Expr::Prim {
+                        prim: Prim::ArrayFoldFrom,
+                        args: vec![f.clone(), array.clone(), Expr::Int((i as i64 + 1).into())],
+                    }),
+                    arg: Box::new(step),
+                })
+            }
+            (Prim::MapEmpty, [Expr::Unit]) => Ok(Expr::Map(Map::new())),
+            (Prim::MapGet, [Expr::Map(map), key]) => match map.get(key, &mut Self::compare)? {
+                Some(value) => Ok(value.clone()),
+                None => Err(InterpretError::KeyNotFound(key.clone())),
+            },
+            (Prim::MapSet, [Expr::Map(map), key, value]) => Ok(Expr::Map(map.insert(
+                key.clone(),
+                value.clone(),
+                &mut Self::compare,
+            )?)),
+            (Prim::MapRemove, [Expr::Map(map), key]) => {
+                Ok(Expr::Map(map.remove(key, &mut Self::compare)?))
+            }
+            (Prim::MapHas, [Expr::Map(map), key]) => {
+                Ok(Expr::Bool(map.get(key, &mut Self::compare)?.is_some()))
+            }
+            (Prim::MapSize, [Expr::Map(map)]) => Ok(Expr::Int((map.len() as i64).into())),
+            (Prim::MapKeys, [Expr::Map(map)]) => Ok(Expr::Array(Rc::new(
+                map.entries()
+                    .into_iter()
+                    .map(|(key, _)| key.clone())
+                    .collect(),
+            ))),
+            // A cast task or channel is replaced by the one it was cast
+            // from, with the casts around what goes in and out.
+            (Prim::Await, [Expr::Task(id, casts)]) if !casts.is_empty() => {
+                let task = Expr::Task(*id, vec![]);
+                Ok(Self::cast_through(
+                    Self::apply_prim(Prim::Await, vec![task]),
+                    casts,
+                ))
+            }
+            (Prim::Recv, [Expr::Chan(id, casts)]) if !casts.is_empty() => {
+                let chan = Expr::Chan(*id, vec![]);
+                Ok(Self::cast_through(
+                    Self::apply_prim(Prim::Recv, vec![chan]),
+                    casts,
+                ))
+            }
+            (Prim::Send, [Expr::Chan(id, casts), message]) if !casts.is_empty() => {
+                let inverses: Vec<_> = casts
+                    .iter()
+                    .rev()
+                    .map(|(from, to)| (to.clone(), from.clone()))
+                    .collect();
+                let chan = Expr::Chan(*id, vec![]);
+                let message = Self::cast_through(message.clone(), &inverses);
+                Ok(Self::apply_prim(Prim::Send, vec![chan, message]))
+            }
+            (Prim::Spawn, [f]) => self.yield_to_scheduler("$spawn", f.clone()),
+            (Prim::Await, [task]) => self.yield_to_scheduler("$await", task.clone()),
+            (Prim::Chan, [_]) => self.yield_to_scheduler("$chan", Expr::Unit),
+            (Prim::Send, [chan, message]) => {
+                self.yield_to_scheduler("$send", Expr::Dict(vec![chan.clone(), message.clone()]))
+            }
+            (Prim::Recv, [chan]) => self.yield_to_scheduler("$recv", chan.clone()),
+            _ => unreachable!(),
+        }
+    }
+
+    /// `expr` cast through each of `casts` in turn.
+    fn cast_through(expr: Expr, casts: &[(MonoType, MonoType)]) -> Expr {
+        casts.iter().fold(expr, |expr, (from, to)| Expr::Cast {
+            expr: Box::new(expr),
+            from: from.clone(),
+            to: to.clone(),
+        })
+    }
+
+    /// `prim` applied to `args`, one at a time.
+    fn apply_prim(prim: Prim, args: Vec<Expr>) -> Expr {
+        args.into_iter()
+            .fold(Expr::Prim { prim, args: vec![] }, |func, arg| Expr::Apply {
+                func: Box::new(func),
+                arg: Box::new(arg),
+            })
+    }
+
+    /// The rest of `array_init(n, f)` once `values` have been built: the
+    /// array if it is long enough, or else the call of `f` for the next
+    /// index, followed by `$array_init_from`.
+    fn array_init_step(n: &Int, f: &Expr, values: Vec<Expr>) -> Expr {
+        let i = values.len() as i64;
+        if i >= n.saturating_i64() {
+            return Expr::Array(Rc::new(values));
+        }
+        Expr::Apply {
+            func: Box::new(Expr::Prim {
+                prim: Prim::ArrayInitFrom,
+                args: vec![
+                    Expr::Int(n.clone()),
+                    f.clone(),
+                    Expr::Array(Rc::new(values)),
+                ],
+            }),
+            arg: Box::new(Expr::Apply {
+                func: Box::new(f.clone()),
+                arg: Box::new(Expr::Int(i.into())),
+            }),
+        }
+    }
+
+    /// Hand `op` over to the scheduler in `eval`, as an operation that no
+    /// handler in the program can catch, since its name is not an
+    /// identifier.
+    fn yield_to_scheduler(&mut self, op: &str, arg: Expr) -> Result<Expr, InterpretError> {
+        let x = self.var_name_generator.next();
+        Err(InterpretError::Perform {
+            op: op.into(),
+            arg: Box::new(arg),
+            k: Box::new(Expr::Fn {
+                arg: x.clone(),
+                body: Box::new(Expr::Var(x)),
+            }),
+        })
+    }
+}
+
+/// The tasks and channels of one run of a program. Tasks and channels are
+/// numbered in the order they are made, and the main task is `0`.
+struct Scheduler {
+    /// The state of the splitmix64 generator that picks the next task.
+    state: u64,
+    /// The tasks that can run, with what each of them runs next.
+    ready: Vec<(usize, Expr)>,
+    /// The result of every task that has finished.
+    results: Vec<Option<Expr>>,
+    /// `(awaited, waiter, k)`: `waiter` resumes in `k` once `awaited` has
+    /// finished.
+    awaiting: Vec<(usize, usize, Expr)>,
+    channels: Vec<Channel>,
+}
+
+/// Messages sent on a channel and not received yet, or the tasks waiting
+/// to receive one, with where they resume. At most one of them is not
+/// empty, as sending never waits.
+#[derive(Default)]
+struct Channel {
+    messages: VecDeque<Expr>,
+    receivers: VecDeque<(usize, Expr)>,
+}
+
+impl Scheduler {
+    fn new(seed: u64) -> Self {
+        Self {
+            state: seed,
+            ready: vec![],
+            results: vec![],
+            awaiting: vec![],
+            channels: vec![],
+        }
+    }
+
+    /// Take a task that can run out of `ready`.
+    fn pick(&mut self) -> (usize, Expr) {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        self.ready
+            .swap_remove((z % self.ready.len() as u64) as usize)
+    }
+
+    /// Make `task` ready to run again, continuing in `k` with `value`.
+    fn resume(&mut self, task: usize, k: Expr, value: Expr) {
+        let next = Expr::Apply {
+            func: Box::new(k),
+            arg: Box::new(value),
+        };
+        self.ready.push((task, next));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InterpretError, Interpreter, OverflowPolicy};
+    use crate::ast::{Expr, Prim, Thunk};
+    use crate::parser::RainParser;
+    use crate::typecheck::TypeChecker;
+    use crate::types::MonoType;
+    use std::{cell::RefCell, io::Cursor, rc::Rc};
+
+    fn eval(program: &str, overflow_policy: OverflowPolicy) -> Result<String, InterpretError> {
+        let expr = RainParser::parse_program(program).unwrap();
+        let (_, expr) = TypeChecker::new().elaborate(&expr).unwrap();
+        let mut interpreter = Interpreter::new().with_overflow_policy(overflow_policy);
+        interpreter.eval(&expr).map(|value| value.to_string())
+    }
+
+    #[test]
+    fn test_overflow_policy() {
+        let program = "9223372036854775807 + 1";
+        assert!(matches!(
+            eval(program, OverflowPolicy::Checked),
+            Err(InterpretError::Overflow { .. })
+        ));
+        assert_eq!(
+            eval(program, OverflowPolicy::Wrapping).unwrap(),
+            "-9223372036854775808"
+        );
+        assert_eq!(
+            eval(program, OverflowPolicy::Saturating).unwrap(),
+            "9223372036854775807"
+        );
+        assert_eq!(
+            eval(program, OverflowPolicy::Unbounded).unwrap(),
+            "9223372036854775808"
+        );
+
+        // Overflow is an error unless another policy is chosen.
+        assert!(matches!(
+            eval(program, OverflowPolicy::default()),
+            Err(InterpretError::Overflow { .. })
+        ));
+
+        let program = "-9223372036854775808 * 2";
+        assert_eq!(eval(program, OverflowPolicy::Wrapping).unwrap(), "0");
+        assert_eq!(
+            eval(program, OverflowPolicy::Saturating).unwrap(),
+            "-9223372036854775808"
+        );
+    }
+
+    #[test]
+    fn test_fixed_width() {
+        let eval = |program| eval(program, OverflowPolicy::Checked).unwrap();
+        assert_eq!(eval("255u8 + 1u8"), "0");
+        assert_eq!(eval("127i8 * 2i8"), "-2");
+        assert_eq!(eval("int(255u8) + 1"), "256");
+        assert_eq!(eval("uint8(-1)"), "255");
+        assert_eq!(eval("int8(200u8)"), "-56");
+        assert_eq!(eval("12u8 land 10u8 lor 1u8 lsl 4u8"), "24");
+        assert_eq!(eval("-128i8 lsr 7i8"), "1");
+    }
+
+    #[test]
+    fn test_lazy() {
+        let program = "let s = lazy (20 + 1) in force(s) * 2";
+        assert_eq!(eval(program, OverflowPolicy::Checked).unwrap(), "42");
+
+        // Forcing a suspension caches its value in every copy of it.
+        let mut interpreter = Interpreter::new();
+        let suspension = interpreter
+            .eval(&RainParser::parse_program("lazy (1 + 2)").unwrap())
+            .unwrap();
+        let Expr::Suspension(ref thunk) = suspension else {
+            panic!("{suspension:?} is not a suspension");
+        };
+        assert!(matches!(*thunk.borrow(), Thunk::Delayed(_)));
+        let force = Expr::Apply {
+            func: Box::new(Expr::Prim {
+                prim: Prim::Force,
+                args: vec![],
+            }),
+            arg: Box::new(suspension.clone()),
+        };
+        assert_eq!(interpreter.eval(&force).unwrap().to_string(), "3");
+        assert!(matches!(*thunk.borrow(), Thunk::Forced(Expr::Int(_))));
+
+        // An infinite stream of the natural numbers, of which only the cells
+        // that are forced are built, once each. Functions call themselves
+        // through a `Dyn`, as the type of a stream would contain itself.
+        let program = "let from = fn (from, n) ->
+              let u = print(show(n)) in
+              { head = n, tail = lazy ((from : Dyn)(from, n + 1)) } in
+            let nats = from((from : Dyn), 0) in
+            let take = fn (take, k, s) ->
+              if 5 <= k then \"\" else \"${s.head} \" ^ (take : Dyn)(take, k + 1, force(s.tail)) in
+            take((take : Dyn), 0, nats) ^ take((take : Dyn), 0, nats)";
+        let expr = RainParser::parse_program(program).unwrap();
+        let (_, expr) = TypeChecker::new().elaborate(&expr).unwrap();
+        let output = Rc::new(RefCell::new(vec![]));
+        let value = Interpreter::new()
+            .with_output(output.clone())
+            .eval(&expr)
+            .unwrap();
+        assert_eq!(value.to_string(), "\"0 1 2 3 4 0 1 2 3 4 \"");
+        assert_eq!(
+            String::from_utf8(output.take()).unwrap(),
+            "0\n1\n2\n3\n4\n5\n"
+        );
+    }
+
+    #[test]
+    fn test_dictionary_passing() {
+        let program = "class Monoid 'a { empty : 'a, (<>) : 'a -> 'a -> 'a }
+            instance Monoid String { empty = \"\", (<>) = fn (x, y) -> x ^ y }
+            instance Monoid Int { empty = 0, (<>) = (+) }
+            let twice = fn (x) -> x <> x <> empty in
+            \"${twice(21)} ${twice(\"ab\")}\"";
+        assert_eq!(
+            eval(program, OverflowPolicy::Checked).unwrap(),
+            "\"42 abab\""
+        );
+    }
+
+    #[test]
+    fn test_structural_equality() {
+        let eval = |program| eval(program, OverflowPolicy::Checked).unwrap();
+        assert_eq!(eval("1 + 1 == 2"), "true");
+        assert_eq!(eval("\"ab\" == \"a\" ^ \"b\""), "true");
+        assert_eq!(eval("255u8 + 1u8 == 0u8"), "true");
+        assert_eq!(eval("(1 <= 2) == false"), "false");
+        assert_eq!(eval("99999999999999999999 == 99999999999999999999"), "true");
+
+        // Only reachable by skipping the type checker.
+        let expr = RainParser::parse_program("let f = fn (x) -> x in f == f").unwrap();
+        assert!(matches!(
+            Interpreter::new().eval(&expr),
+            Err(InterpretError::Incomparable(Expr::Fn { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_records() {
+        let eval = |program| eval(program, OverflowPolicy::Checked).unwrap();
+        assert_eq!(eval("{ x = 1, y = \"a\" }.y"), "\"a\"");
+        assert_eq!(eval("{ x = 1 | { y = true } }"), "{ x = 1, y = true }");
+        assert_eq!(eval("{ x = 2 | { x = 1 } }.x"), "2");
+        assert_eq!(eval("({ x = 2 | { x = 1 } } \\ x).x"), "1");
+        assert_eq!(eval("{ x = 1, y = 2 } \\ x"), "{ y = 2 }");
+        assert_eq!(
+            eval("let p = fn (r) -> { sum = r.x + r.y | r } in p({ x = 1, y = 2 })"),
+            "{ sum = 3, x = 1, y = 2 }"
+        );
+        assert_eq!(eval("{ x = 1, y = true } == { y = true, x = 1 }"), "true");
+        assert_eq!(eval("{ x = 1, y = true } == { y = false, x = 1 }"), "false");
+        assert_eq!(eval("let r = { f = fn (x) -> x + 1 } in r.f(1)"), "2");
+        // Without the type checker, a missing field is an error rather than
+        // a panic.
+        for program in ["{ x = 1 }.y", "{ x = 1 } \\ y"] {
+            let expr = RainParser::parse_program(program).unwrap();
+            assert!(matches!(
+                Interpreter::new().eval(&expr),
+                Err(InterpretError::MissingField(ref label)) if label == "y"
+            ));
+        }
+    }
+
+    #[test]
+    fn test_io() {
+        let expr = RainParser::parse_program(
+            "let name = read_line(), u = print(\"hello, ${name}\") in print(read_line())",
+        )
+        .unwrap();
+        let (_, expr) = TypeChecker::new().elaborate(&expr).unwrap();
+        let input = Rc::new(RefCell::new(Cursor::new("rain\r\nbye")));
+        let output = Rc::new(RefCell::new(vec![]));
+        let mut interpreter = Interpreter::new()
+            .with_input(input)
+            .with_output(output.clone());
+        assert!(matches!(interpreter.eval(&expr), Ok(Expr::Unit)));
+        assert_eq!(output.borrow().as_slice(), b"hello, rain\nbye\n");
+
+        // A later `let print` does not capture the `print` in `g`.
+        let expr =
+            RainParser::parse_program("let g = fn (u) -> print(u) in let print = 5 in g(\"hi\")")
+                .unwrap();
+        let (_, expr) = TypeChecker::new().elaborate(&expr).unwrap();
+        let output = Rc::new(RefCell::new(vec![]));
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        assert!(matches!(interpreter.eval(&expr), Ok(Expr::Unit)));
+        assert_eq!(output.borrow().as_slice(), b"hi\n");
+
+        let path = std::env::temp_dir().join(format!("rain-test-io-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let program =
+            format!("let u = write_file({path:?}, \"a\" ^ \"b\") in read_file({path:?}) == \"ab\"");
+        assert_eq!(eval(&program, OverflowPolicy::Checked).unwrap(), "true");
+        std::fs::remove_file(path).unwrap();
+        assert!(matches!(
+            eval(&format!("read_file({path:?})"), OverflowPolicy::Checked),
+            Err(InterpretError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_run_tests() {
+        let program = "let add = fn (x, y) -> x + y in
+            test \"adds\" = add(1, 2) == 3 in
+            let double =
+              let two = 2 in test \"nested\" = add(two, 1) == 3 in fn (x) -> add(x, x) in
+            test \"doubles\" = double(2) == 5 in
+            test \"asserts\" = let u = assert double(1) == 3 in true in
+            double(21)";
+        let expr = RainParser::parse_program(program).unwrap();
+        let (_, expr) = TypeChecker::new().elaborate(&expr).unwrap();
+        let (outcomes, result) = Interpreter::new().run_tests(&expr);
+        let outcomes: Vec<_> = outcomes
+            .iter()
+            .map(|outcome| (outcome.name.as_str(), outcome.pos, &outcome.result))
+            .collect();
+        assert!(matches!(
+            outcomes.as_slice(),
+            [
+                ("adds", (2, 13), Ok(true)),
+                ("nested", (4, 30), Ok(true)),
+                ("doubles", (5, 13), Ok(false)),
+                (
+                    "asserts",
+                    (6, 13),
+                    Err(InterpretError::AssertionFailed { pos: (6, 38) })
+                ),
+            ]
+        ));
+        assert_eq!(result.unwrap().to_string(), "42");
+        // Outside of `rain test`, tests are skipped.
+        assert_eq!(eval(program, OverflowPolicy::Checked).unwrap(), "42");
+
+        // The program is evaluated once, running the tests it reaches, and
+        // reporting those it does not.
+        let program = "let u = print(\"once\") in
+            let check = fn (x) -> test \"in fn\" = 1 <= x in x in
+            let never = fn (x) -> test \"never\" = false in x in
+            test \"first\" = true in
+            if check(1) == 1 then test \"then\" = true in 1 else test \"else\" = true in 2";
+        let expr = RainParser::parse_program(program).unwrap();
+        let (_, expr) = TypeChecker::new().elaborate(&expr).unwrap();
+        let output = Rc::new(RefCell::new(vec![]));
+        let (outcomes, result) = Interpreter::new()
+            .with_output(output.clone())
+            .run_tests(&expr);
+        let outcomes: Vec<_> = outcomes
+            .iter()
+            .map(|outcome| (outcome.name.as_str(), &outcome.result))
+            .collect();
+        assert!(matches!(
+            outcomes.as_slice(),
+            [
+                ("first", Ok(true)),
+                ("in fn", Ok(true)),
+                ("then", Ok(true)),
+                ("never", Err(InterpretError::TestNotReached)),
+                ("else", Err(InterpretError::TestNotReached)),
+            ]
+        ));
+        assert_eq!(result.unwrap().to_string(), "1");
+        assert_eq!(String::from_utf8(output.take()).unwrap(), "once\n");
+        assert!(matches!(
+            eval("assert 1 <= 0", OverflowPolicy::Checked),
+            Err(InterpretError::AssertionFailed { pos: (1, 1) })
+        ));
+    }
+
+    #[test]
+    fn test_effects() {
+        let eval = |program| eval(program, OverflowPolicy::Checked);
+
+        // A generator, summing what it yields.
+        let program = "effect Yield : Int -> Bool
+            handle (let a = perform Yield(1), b = perform Yield(2) in perform Yield(3)) with
+            | Yield(x, k) -> x + k(true)
+            | return b -> 0";
+        assert_eq!(eval(program).unwrap(), "6");
+
+        // State, threaded through the handler.
+        let program = "effect Get : Int -> Int
+            effect Put : Int -> Int
+            let run = fn (f) ->
+                handle f(0) with
+                | Get(u, k) -> fn (s) -> let g = k(s) in g(s)
+                | Put(s, k) -> fn (old) -> let g = k(old) in g(s)
+                | return x -> fn (s) -> { result = x, state = s } in
+            let prog = fn (u) ->
+                let a = perform Get(0), b = perform Put(a + 10), c = perform Get(0) in a + c in
+            let r = run(prog) in r(5)";
+        assert_eq!(eval(program).unwrap(), "{ result = 20, state = 15 }");
+
+        // Early exit, and operations passing through an inner handler.
+        let program = "effect Abort : String -> Int
+            effect Ask : Int -> Int
+            handle
+                handle 1 + perform Ask(2) + perform Abort(\"stop\") with
+                | Ask(x, k) -> k(x * 10)
+            with
+            | Abort(msg, k) -> 0";
+        assert_eq!(eval(program).unwrap(), "0");
+        let program = "effect Abort : String -> Int
+            effect Ask : Int -> Int
+            handle
+                handle 1 + perform Ask(2) + perform Abort(\"stop\") with
+                | Ask(x, k) -> k(x * 10)
+            with
+            | Abort(msg, k) -> k(100)";
+        assert_eq!(eval(program).unwrap(), "121");
+
+        let expr =
+            RainParser::parse_program("effect Oops : Int -> Int 1 + perform Oops(1)").unwrap();
+        let (_, expr) = TypeChecker::new().elaborate(&expr).unwrap();
+        assert!(matches!(
+            Interpreter::new().eval(&expr),
+            Err(InterpretError::Perform { ref op, .. }) if op == "Oops"
+        ));
+    }
+
+    #[test]
+    fn test_labeled_arguments() {
+        let eval = |program: &str| eval(program, OverflowPolicy::Checked).unwrap();
+        let sub = "let sub = fn (~x, ~y) -> x + y * -1 in ";
+        assert_eq!(eval(&format!("{sub}sub(~y: 1, ~x: 5)")), "4");
+        assert_eq!(eval(&format!("{sub}sub(5, 1)")), "4");
+        assert_eq!(eval(&format!("{sub}let y = 2 in sub(~y)(7)")), "5");
+        let scale = "let scale = fn (?by = 2, n) -> by * n in ";
+        assert_eq!(eval(&format!("{scale}scale(5)")), "10");
+        assert_eq!(eval(&format!("{scale}scale(~by: 3, 5)")), "15");
+        assert_eq!(eval(&format!("{scale}scale(5, ~by: 4)")), "20");
+    }
+
+    #[test]
+    fn test_patterns() {
+        assert_eq!(
+            eval(
+                "let { x, y = { z } } = { x = 1, y = { z = 2, w = 3 } } in x + z",
+                OverflowPolicy::Checked
+            )
+            .unwrap(),
+            "3"
+        );
+        let swap = "let swap = fn ({ a, b }) -> { a = b, b = a } in ";
+        assert_eq!(
+            eval(
+                &format!("{swap}swap({{ a = 1, b = 2 }})"),
+                OverflowPolicy::Checked
+            )
+            .unwrap(),
+            "{ a = 2, b = 1 }"
+        );
+        let zero = "let zero = fn (0, _) -> true in ";
+        assert_eq!(
+            eval(&format!("{zero}zero(0, ())"), OverflowPolicy::Checked).unwrap(),
+            "true"
+        );
+        assert!(matches!(
+            eval(&format!("{zero}zero(1, ())"), OverflowPolicy::Checked),
+            Err(InterpretError::MatchFailure { pos: (1, 16) })
+        ));
+    }
+
+    #[test]
+    fn test_binding_ops() {
+        let eval = |program: &str| eval(program, OverflowPolicy::Checked).unwrap();
+        // A computation that threads a counter through.
+        let state = "let bind = fn (f, m) -> fn (s) -> let r = m(s) in f(r.value)(r.state),
+            map = fn (f, m) -> fn (s) -> let r = m(s) in { value = f(r.value), state = r.state },
+            tick = fn (s) -> { value = s, state = s + 1 } in ";
+        assert_eq!(
+            eval(&format!(
+                "{state}(let* a = tick in let+ b = tick in a * 10 + b)(1)"
+            )),
+            "{ value = 12, state = 3 }"
+        );
+    }
+
+    #[test]
+    fn test_arrays_and_maps() {
+        let eval = |program: &str| eval(program, OverflowPolicy::Checked);
+        let squares = "let a = array_init(3, fn (i) -> i * i) in ";
+        assert_eq!(
+            eval(&format!(
+                "{squares}{{ a = a, b = array_set(a, 1, 7), x = array_get(a, 2), n = array_length(a) }}"
+            ))
+            .unwrap(),
+            "{ a = [|0, 1, 4|], b = [|0, 7, 4|], x = 4, n = 3 }"
+        );
+        assert_eq!(
+            eval(&format!("{squares}a == array_init(3, fn (i) -> i * i)")).unwrap(),
+            "true"
+        );
+        assert_eq!(eval("array_init(-1, fn (i) -> i)").unwrap(), "[||]");
+        assert!(matches!(
+            eval(&format!("{squares}array_get(a, 3)")),
+            Err(InterpretError::IndexOutOfBounds { ref index, length: 3 }) if *index == 3.into()
+        ));
+        // An operation performed by `f` resumes the rest of the loop.
+        let program = "effect Ask : Int -> Int
+            let a = handle array_init(3, fn (i) -> perform Ask(i)) with | Ask(x, k) -> k(x * 10) in
+            { a = a, n = array_length(a) }";
+        assert_eq!(eval(program).unwrap(), "{ a = [|0, 10, 20|], n = 3 }");
+        // A later `let` that shadows a primitive does not capture it.
+        assert_eq!(
+            eval(
+                "let g = fn (u) -> array_length(u) in let array_length = 5 in \
+                g(array_init(3, fn (i) -> i))"
+            )
+            .unwrap(),
+            "3"
+        );
+
+        let m = "let m = map_set(map_set(map_set(map_empty(), \"b\", 2), \"a\", 1), \"b\", 3) in ";
+        assert_eq!(
+            eval(&format!(
+                "{m}{{ m = m, r = map_remove(m, \"a\"), b = map_get(m, \"b\"), has = map_has(m, \"c\"), n = map_size(m), ks = map_keys(m) }}"
+            ))
+            .unwrap(),
+            "{ m = {|\"a\" => 1, \"b\" => 3|}, r = {|\"b\" => 3|}, b = 3, has = false, n = 2, ks = [|\"a\", \"b\"|] }"
+        );
+        assert!(matches!(
+            eval(&format!("{m}map_get(m, \"c\")")),
+            Err(InterpretError::KeyNotFound(Expr::Str(ref key))) if key.as_str() == "c"
+        ));
+    }
+
+    #[test]
+    fn test_pipelines() {
+        let eval = |program: &str| eval(program, OverflowPolicy::Checked).unwrap();
+        assert_eq!(eval("3 |> (+ 1) |> (2 *)"), "8");
+        assert_eq!(eval("((+ 1) >> (2 *))(3)"), "8");
+        assert_eq!(eval("((+ 1) << (2 *))(3)"), "7");
+        assert_eq!(eval("((|>))(1, show)"), "\"1\"");
+        // The operands of a composition are evaluated once, before it is
+        // applied.
+        let output = Rc::new(RefCell::new(vec![]));
+        let expr = RainParser::parse_program(
+            "let f = (let u = print(\"f\") in show) >> print in let u = f(1) in f(2)",
+        )
+        .unwrap();
+        let (_, expr) = TypeChecker::new().elaborate(&expr).unwrap();
+        Interpreter::new()
+            .with_output(output.clone())
+            .eval(&expr)
+            .unwrap();
+        assert_eq!(String::from_utf8(output.take()).unwrap(), "f\n1\n2\n");
+        // Both pipes evaluate their operands from left to right.
+        for (program, printed) in [
+            (
+                "let u = (let u = print(\"x\") in 1) |> (let u = print(\"f\") in show) in ()",
+                "x\nf\n",
+            ),
+            (
+                "let u = (let u = print(\"f\") in show) <| (let u = print(\"x\") in 1) in ()",
+                "f\nx\n",
+            ),
+        ] {
+            let expr = RainParser::parse_program(program).unwrap();
+            let (_, expr) = TypeChecker::new().elaborate(&expr).unwrap();
+            Interpreter::new()
+                .with_output(output.clone())
+                .eval(&expr)
+                .unwrap();
+            assert_eq!(String::from_utf8(output.take()).unwrap(), printed);
+        }
+    }
+
+    #[test]
+    fn test_dyn() {
+        let eval = |program: &str| eval(program, OverflowPolicy::Checked);
+        assert_eq!(eval("(1 : Dyn) + 1").unwrap(), "2");
+        assert_eq!(
+            eval("let f = (fn (x) -> x + 1 : Dyn) in { a = f(1) + 1, b = show(f) }").unwrap(),
+            "{ a = 3, b = \"<function>\" }"
+        );
+        assert_eq!(
+            eval(
+                "let r = ({ a = array_init(1, fn (i) -> i), b = true } : Dyn) in array_get(r.a, 0)"
+            )
+            .unwrap(),
+            "0"
+        );
+        // A failed check blames the `Dyn` the value was cast from.
+        assert!(matches!(
+            eval("let x = (true : Dyn) in x + 1"),
+            Err(InterpretError::Blame {
+                pos: (1, 17),
+                expected: MonoType::Int,
+                ..
+            })
+        ));
+        assert!(matches!(
+            eval("let f = (fn (x) -> x + 1 : Dyn) in f(true)"),
+            Err(InterpretError::Blame {
+                pos: (1, 28),
+                expected: MonoType::Int,
+                ..
+            })
+        ));
+        assert!(matches!(
+            eval("let r = ({ a = 1 } : Dyn) in r.b"),
+            Err(InterpretError::Blame { pos: (1, 22), .. })
+        ));
+        assert!(matches!(
+            eval("let g = ((fn (x) -> x : Dyn) : Int -> Bool) in g(1)"),
+            Err(InterpretError::Blame {
+                expected: MonoType::Bool,
+                ..
+            })
+        ));
+        // Every place a value is used checks it, not only operators and
+        // applications.
+        assert!(matches!(
+            eval("effect E : Int -> Int handle perform E((true : Dyn)) with | E(x, k) -> k(x + 1)"),
+            Err(InterpretError::Blame {
+                expected: MonoType::Int,
+                ..
+            })
+        ));
+        assert!(matches!(
+            eval("assert (1 : Dyn)"),
+            Err(InterpretError::Blame {
+                expected: MonoType::Bool,
+                ..
+            })
+        ));
+        // Values of different shapes, which only a `Dyn` lets through, are
+        // not compared.
+        for program in [
+            "(1 : Dyn) == (true : Dyn)",
+            "(1 : Dyn) <= (true : Dyn)",
+            "map_size(map_set(map_set(map_empty(), (1 : Dyn), 1), (true : Dyn), 2))",
+        ] {
+            assert!(matches!(
+                eval(program),
+                Err(InterpretError::Incomparable(_))
+            ));
+        }
+        assert_eq!(eval("(\"a\" : Dyn) <= (\"b\" : Dyn)").unwrap(), "true");
+    }
+
+    #[test]
+    fn test_measures() {
+        let eval = |program: &str| eval(program, OverflowPolicy::Checked).unwrap();
+        // Units only exist for the type checker.
+        assert_eq!(eval("5<m> * 3<s> + 1<m*s>"), "16");
+        assert_eq!(
+            eval("{ a = 2<m> == 2<m>, b = show(255u8<kg>) }"),
+            "{ a = true, b = \"255\" }"
+        );
+        assert_eq!(
+            eval("let sq = fn (x) -> (x : Int<'u>) * x in sq(3<m>) + sq(4<m>)"),
+            "25"
+        );
+    }
+
+    #[test]
+    fn test_tasks() {
+        let eval = |program: &str| eval(program, OverflowPolicy::Checked);
+        assert_eq!(
+            eval("let t = spawn(fn (_) -> 6 * 7) in await(t) + 1").unwrap(),
+            "43"
+        );
+        // A reply comes back on a second channel.
+        let ping_pong = "let ping = chan() in let pong = chan() in \
+            let t = spawn(fn (_) -> send(pong, recv(ping) * 2)) in \
+            let u = send(ping, 21) in recv(pong)";
+        assert_eq!(eval(ping_pong).unwrap(), "42");
+        // A later `let` that shadows `send` does not capture the one in `f`.
+        let shadowed = "let c = chan() in let f = fn (_) -> send(c, 1) in \
+            let send = 5 in let t = spawn(f) in recv(c)";
+        assert_eq!(eval(shadowed).unwrap(), "1");
+        assert!(matches!(
+            eval("let c = chan() in recv(c) + 1"),
+            Err(InterpretError::Deadlock)
+        ));
+        // An error in any task ends the program.
+        assert!(matches!(
+            eval("let t = spawn(fn (_) -> assert false) in await(t)"),
+            Err(InterpretError::AssertionFailed { pos: (1, 25) })
+        ));
+
+        // The order tasks print in only depends on the seed.
+        let program = RainParser::parse_program(
+            "let c = chan() in \
+            let worker = fn (s) -> fn (_) -> let u = print(s) in send(c, ()) in \
+            let a = spawn(worker(\"a\")) in let b = spawn(worker(\"b\")) in \
+            let c1 = spawn(worker(\"c\")) in \
+            let u = recv(c) in let v = recv(c) in recv(c)",
+        )
+        .unwrap();
+        let (_, expr) = TypeChecker::new().elaborate(&program).unwrap();
+        let run = |seed: u64| {
+            let output = Rc::new(RefCell::new(vec![]));
+            Interpreter::new()
+                .with_output(output.clone())
+                .with_seed(seed)
+                .eval(&expr)
+                .unwrap();
+            String::from_utf8(output.take()).unwrap()
+        };
+        let orders: Vec<String> = (0..8).map(run).collect();
+        assert_eq!(orders, (0..8).map(run).collect::<Vec<_>>());
+        assert!(orders.iter().any(|order| *order != orders[0]));
+
+        // Tasks can be spawned and awaited from callbacks of primitives.
+        assert_eq!(
+            eval("array_length(array_init(3, fn (i) -> await(spawn(fn (u) -> i))))").unwrap(),
+            "3"
+        );
+        // What comes out of a task or channel cast from a `Dyn` is checked,
+        // and so is what goes into a channel cast to one.
+        assert!(matches!(
+            eval("let d = (spawn(fn (_) -> true) : Dyn) in await(d) + 1"),
+            Err(InterpretError::Blame {
+                pos: (1, 34),
+                expected: MonoType::Int,
+                ..
+            })
+        ));
+        assert!(matches!(
+            eval("let c = (chan() : Dyn) in let u = send(c, true) in recv(c) + 1"),
+            Err(InterpretError::Blame {
+                pos: (1, 19),
+                expected: MonoType::Int,
+                ..
+            })
+        ));
+        assert_eq!(
+            eval("let c = chan() in let u = send((c : Dyn), 2) in recv(c) + 1").unwrap(),
+            "3"
+        );
+        // A suspension whose expression waits for a message is still only
+        // evaluated once.
+        assert_eq!(
+            eval(
+                "let c = chan() in let u = send(c, 1) in let v = send(c, 2) in \
+                let l = lazy (recv(c)) in force(l) + force(l) * 10"
+            )
+            .unwrap(),
+            "11"
+        );
+    }
 }
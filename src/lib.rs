@@ -1,4 +1,8 @@
 pub mod ast;
+pub mod int;
 pub mod interpret;
+pub mod map;
 pub mod parser;
+pub mod prelude;
 pub mod typecheck;
+pub mod types;
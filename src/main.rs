@@ -1,32 +1,104 @@
-use pest::Parser;
-use rain::interpret::Interpreter;
-use rain::parser::{RainParser, Rule};
+use rain::interpret::{InterpretError, Interpreter, OverflowPolicy};
+use rain::parser::{RainParser, Warning};
+use rain::prelude::Prelude;
 use rain::typecheck::TypeChecker;
 use std::env;
 use std::fs;
+use std::process;
 
 fn main() {
-    // Get argument.
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        panic!("Usage: rain <SOURCE CODE FILE>.");
+    // Get arguments.
+    let usage =
+        "Usage: rain [test] [--overflow=checked|wrapping|saturating|unbounded] [--seed=<n>] [--no-prelude] <SOURCE CODE FILE>.";
+    let usage_error = |message: &str| -> ! {
+        eprintln!("{message}");
+        eprintln!("{usage}");
+        process::exit(2)
+    };
+    let mut path = None;
+    let mut overflow_policy = OverflowPolicy::default();
+    let mut test = false;
+    let mut no_prelude = false;
+    let mut seed = 0;
+    for (i, arg) in env::args().skip(1).enumerate() {
+        if let Some(n) = arg.strip_prefix("--seed=") {
+            seed = n
+                .parse()
+                .unwrap_or_else(|_| usage_error(&format!("invalid seed `{n}`")));
+            continue;
+        }
+        match arg.strip_prefix("--overflow=") {
+            Some(policy) => {
+                overflow_policy = policy.parse().unwrap_or_else(|e: String| usage_error(&e))
+            }
+            None if i == 0 && arg == "test" => test = true,
+            None if arg == "--no-prelude" => no_prelude = true,
+            None if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            None => usage_error(&format!("unexpected argument `{arg}`")),
+        }
     }
-    let path = &args[1];
+    let path = path.unwrap_or_else(|| usage_error("missing source code file"));
 
     // Read source code.
-    let program = fs::read_to_string(path).unwrap();
+    let program = fs::read_to_string(&path).unwrap();
 
     // Generate AST.
-    let mut pairs = RainParser::parse(Rule::prog, &program).unwrap();
-    let expr = RainParser::parse_expression(pairs.next().unwrap().into_inner());
+    let (expr, warnings) = RainParser::parse_program_with_warnings(&program).unwrap();
+    for warning in warnings {
+        match warning {
+            Warning::RefutablePattern { pos: (line, col) } => {
+                eprintln!("warning: refutable pattern at {path}:{line}:{col}")
+            }
+        }
+    }
+
+    // Load the prelude, whose bindings every program can use.
+    let prelude = match no_prelude {
+        true => Prelude::empty(),
+        false => Prelude::load().unwrap(),
+    };
 
-    // Type check.
-    let mut type_checker = TypeChecker::new();
-    let expr_type = type_checker.type_check(&expr).unwrap();
+    // Type check, and elaborate type classes into dictionary passing.
+    let mut type_checker = TypeChecker::new().with_prelude(&prelude);
+    let (expr_type, expr) = type_checker.elaborate(&expr).unwrap();
+
+    let mut interpreter = Interpreter::new()
+        .with_overflow_policy(overflow_policy)
+        .with_seed(seed)
+        .with_prelude(&prelude);
+    if test {
+        // Run the program with its tests.
+        let (outcomes, result) = interpreter.run_tests(&expr);
+        let mut failed = 0;
+        for outcome in outcomes.iter() {
+            let (line, col) = outcome.pos;
+            let location = format!("{path}:{line}:{col}");
+            match &outcome.result {
+                Ok(true) => println!("PASS {} ({location})", outcome.name),
+                Ok(false) => println!("FAIL {} ({location}): false", outcome.name),
+                Err(InterpretError::AssertionFailed { pos: (line, col) }) => println!(
+                    "FAIL {} ({location}): assertion failed at {path}:{line}:{col}",
+                    outcome.name
+                ),
+                Err(InterpretError::TestNotReached) => {
+                    println!("FAIL {} ({location}): never reached", outcome.name)
+                }
+                Err(e) => println!("FAIL {} ({location}): {e:?}", outcome.name),
+            }
+            failed += outcome.result.as_ref().map_or(1, |passed| !passed as usize);
+        }
+        println!("{} passed, {failed} failed", outcomes.len() - failed);
+        if let Err(e) = &result {
+            println!("ERROR {path}: {e:?}");
+        }
+        if failed > 0 || result.is_err() {
+            process::exit(1);
+        }
+        return;
+    }
     println!("Type: {expr_type}");
 
     // Interpret.
-    let mut interpreter = Interpreter::new();
     let result = interpreter.eval(&expr).unwrap();
     println!("Value: {result}");
 }
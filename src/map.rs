@@ -0,0 +1,292 @@
+use std::{cmp::Ordering, rc::Rc};
+
+/// A persistent map: an AVL tree whose nodes are shared between versions,
+/// so that adding or removing a key only copies the path to it. Keys are
+/// ordered by the comparison each operation is given, which may fail.
+#[derive(Debug)]
+pub struct Map<K, V> {
+    root: Tree<K, V>,
+    len: usize,
+}
+
+type Tree<K, V> = Option<Rc<Node<K, V>>>;
+
+#[derive(Debug)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    height: usize,
+    left: Tree<K, V>,
+    right: Tree<K, V>,
+}
+
+impl<K, V> Clone for Map<K, V> {
+    fn clone(&self) -> Self {
+        Map {
+            root: self.root.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<K: Clone, V: Clone> Default for Map<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Clone, V: Clone> Map<K, V> {
+    pub fn new() -> Self {
+        Map { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get<E>(
+        &self,
+        key: &K,
+        cmp: &mut impl FnMut(&K, &K) -> Result<Ordering, E>,
+    ) -> Result<Option<&V>, E> {
+        let mut tree = &self.root;
+        while let Some(node) = tree {
+            match cmp(key, &node.key)? {
+                Ordering::Less => tree = &node.left,
+                Ordering::Greater => tree = &node.right,
+                Ordering::Equal => return Ok(Some(&node.value)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// The map with `key` bound to `value`, in place of what it was bound
+    /// to before.
+    pub fn insert<E>(
+        &self,
+        key: K,
+        value: V,
+        cmp: &mut impl FnMut(&K, &K) -> Result<Ordering, E>,
+    ) -> Result<Self, E> {
+        let (root, added) = insert(&self.root, key, value, cmp)?;
+        Ok(Map {
+            root: Some(root),
+            len: self.len + added as usize,
+        })
+    }
+
+    /// The map without `key`.
+    pub fn remove<E>(
+        &self,
+        key: &K,
+        cmp: &mut impl FnMut(&K, &K) -> Result<Ordering, E>,
+    ) -> Result<Self, E> {
+        Ok(match remove(&self.root, key, cmp)? {
+            Some(root) => Map {
+                root,
+                len: self.len - 1,
+            },
+            None => self.clone(),
+        })
+    }
+
+    /// The entries in the order of their keys.
+    pub fn entries(&self) -> Vec<(&K, &V)> {
+        fn walk<'a, K, V>(tree: &'a Tree<K, V>, entries: &mut Vec<(&'a K, &'a V)>) {
+            if let Some(node) = tree {
+                walk(&node.left, entries);
+                entries.push((&node.key, &node.value));
+                walk(&node.right, entries);
+            }
+        }
+
+        let mut entries = Vec::with_capacity(self.len);
+        walk(&self.root, &mut entries);
+        entries
+    }
+}
+
+fn height<K, V>(tree: &Tree<K, V>) -> usize {
+    tree.as_ref().map_or(0, |node| node.height)
+}
+
+fn node<K, V>(key: K, value: V, left: Tree<K, V>, right: Tree<K, V>) -> Rc<Node<K, V>> {
+    Rc::new(Node {
+        key,
+        value,
+        height: 1 + height(&left).max(height(&right)),
+        left,
+        right,
+    })
+}
+
+/// A node whose subtrees differ in height by at most two, rotated so that
+/// they differ by at most one.
+fn balance<K: Clone, V: Clone>(
+    key: K,
+    value: V,
+    left: Tree<K, V>,
+    right: Tree<K, V>,
+) -> Rc<Node<K, V>> {
+    let (hl, hr) = (height(&left), height(&right));
+    if hl > hr + 1 {
+        let l = left.unwrap();
+        if height(&l.left) >= height(&l.right) {
+            let right = node(key, value, l.right.clone(), right);
+            node(l.key.clone(), l.value.clone(), l.left.clone(), Some(right))
+        } else {
+            let lr = l.right.as_ref().unwrap();
+            let left = node(
+                l.key.clone(),
+                l.value.clone(),
+                l.left.clone(),
+                lr.left.clone(),
+            );
+            let right = node(key, value, lr.right.clone(), right);
+            node(lr.key.clone(), lr.value.clone(), Some(left), Some(right))
+        }
+    } else if hr > hl + 1 {
+        let r = right.unwrap();
+        if height(&r.right) >= height(&r.left) {
+            let left = node(key, value, left, r.left.clone());
+            node(r.key.clone(), r.value.clone(), Some(left), r.right.clone())
+        } else {
+            let rl = r.left.as_ref().unwrap();
+            let left = node(key, value, left, rl.left.clone());
+            let right = node(
+                r.key.clone(),
+                r.value.clone(),
+                rl.right.clone(),
+                r.right.clone(),
+            );
+            node(rl.key.clone(), rl.value.clone(), Some(left), Some(right))
+        }
+    } else {
+        node(key, value, left, right)
+    }
+}
+
+/// The tree with `key` bound to `value`, and whether `key` is new.
+fn insert<K: Clone, V: Clone, E>(
+    tree: &Tree<K, V>,
+    key: K,
+    value: V,
+    cmp: &mut impl FnMut(&K, &K) -> Result<Ordering, E>,
+) -> Result<(Rc<Node<K, V>>, bool), E> {
+    let Some(n) = tree else {
+        return Ok((node(key, value, None, None), true));
+    };
+    Ok(match cmp(&key, &n.key)? {
+        Ordering::Less => {
+            let (left, added) = insert(&n.left, key, value, cmp)?;
+            let node = balance(n.key.clone(), n.value.clone(), Some(left), n.right.clone());
+            (node, added)
+        }
+        Ordering::Greater => {
+            let (right, added) = insert(&n.right, key, value, cmp)?;
+            let node = balance(n.key.clone(), n.value.clone(), n.left.clone(), Some(right));
+            (node, added)
+        }
+        Ordering::Equal => (node(key, value, n.left.clone(), n.right.clone()), false),
+    })
+}
+
+/// The tree without `key`, or `None` if it has no `key`.
+fn remove<K: Clone, V: Clone, E>(
+    tree: &Tree<K, V>,
+    key: &K,
+    cmp: &mut impl FnMut(&K, &K) -> Result<Ordering, E>,
+) -> Result<Option<Tree<K, V>>, E> {
+    let Some(n) = tree else {
+        return Ok(None);
+    };
+    Ok(match cmp(key, &n.key)? {
+        Ordering::Less => remove(&n.left, key, cmp)?.map(|left| {
+            Some(balance(
+                n.key.clone(),
+                n.value.clone(),
+                left,
+                n.right.clone(),
+            ))
+        }),
+        Ordering::Greater => remove(&n.right, key, cmp)?.map(|right| {
+            Some(balance(
+                n.key.clone(),
+                n.value.clone(),
+                n.left.clone(),
+                right,
+            ))
+        }),
+        Ordering::Equal => Some(match (&n.left, &n.right) {
+            (None, right) => right.clone(),
+            (left, None) => left.clone(),
+            (left, Some(right)) => {
+                let (key, value, right) = remove_min(right);
+                Some(balance(key, value, left.clone(), right))
+            }
+        }),
+    })
+}
+
+/// The smallest entry of a tree, and the tree without it.
+fn remove_min<K: Clone, V: Clone>(n: &Rc<Node<K, V>>) -> (K, V, Tree<K, V>) {
+    match &n.left {
+        None => (n.key.clone(), n.value.clone(), n.right.clone()),
+        Some(left) => {
+            let (key, value, left) = remove_min(left);
+            let node = balance(n.key.clone(), n.value.clone(), left, n.right.clone());
+            (key, value, Some(node))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{height, Map, Tree};
+    use std::convert::Infallible;
+
+    fn balanced<K, V>(tree: &Tree<K, V>) -> bool {
+        match tree {
+            None => true,
+            Some(node) => {
+                height(&node.left).abs_diff(height(&node.right)) <= 1
+                    && balanced(&node.left)
+                    && balanced(&node.right)
+            }
+        }
+    }
+
+    #[test]
+    fn test_insert_and_remove() {
+        let mut cmp = |a: &i32, b: &i32| Ok::<_, Infallible>(a.cmp(b));
+        let mut map = Map::new();
+        for i in 0..100 {
+            map = map.insert((i * 37) % 100, i, &mut cmp).unwrap();
+        }
+        assert_eq!(map.len(), 100);
+        assert!(balanced(&map.root));
+        let keys: Vec<i32> = map.entries().into_iter().map(|(k, _)| *k).collect();
+        assert_eq!(keys, (0..100).collect::<Vec<_>>());
+
+        // Older versions are left as they were.
+        let old = map.clone();
+        let map = map.insert(37, -1, &mut cmp).unwrap();
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.get(&37, &mut cmp).unwrap(), Some(&-1));
+        assert_eq!(old.get(&37, &mut cmp).unwrap(), Some(&1));
+
+        let mut map = map;
+        for i in (0..100).step_by(2) {
+            map = map.remove(&i, &mut cmp).unwrap();
+        }
+        assert_eq!(map.len(), 50);
+        assert!(balanced(&map.root));
+        assert_eq!(map.get(&4, &mut cmp).unwrap(), None);
+        assert_eq!(map.remove(&4, &mut cmp).unwrap().len(), 50);
+        assert_eq!(old.len(), 100);
+    }
+}
@@ -1,98 +1,1555 @@
-use crate::ast::{BinOp, Expr};
+use crate::ast::{BinOp, Expr, OpClause, Prim};
+use crate::int::{FixedInt, Int, Width};
+use crate::types::MonoType;
 use lazy_static::lazy_static;
-use pest::{iterators::Pairs, pratt_parser::PrattParser};
+use pest::{
+    error::ErrorVariant,
+    iterators::{Pair, Pairs},
+    Parser,
+};
 use pest_derive::Parser;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap},
+    iter::Peekable,
+};
 
-lazy_static! {
-    static ref PRATT_PARSER: PrattParser<Rule> = {
-        use pest::pratt_parser::{Assoc, Op};
-        use Rule::*;
-
-        PrattParser::new()
-            .op(Op::infix(le, Assoc::Left))
-            .op(Op::infix(plus, Assoc::Left))
-            .op(Op::infix(times, Assoc::Left))
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Assoc {
+    Left,
+    Right,
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fixity {
+    pub assoc: Assoc,
+    pub precedence: u8,
+}
+
+impl Fixity {
+    /// Fixity of an operator without a fixity declaration.
+    pub const DEFAULT: Fixity = Fixity {
+        assoc: Assoc::Left,
+        precedence: 9,
     };
+
+    pub fn new(assoc: Assoc, precedence: u8) -> Self {
+        Self { assoc, precedence }
+    }
+}
+
+/// Operator precedence table, keyed by operator symbol so that it can be
+/// extended by fixity declarations.
+#[derive(Clone, Debug)]
+pub struct FixityTable {
+    fixities: HashMap<String, Fixity>,
+}
+
+impl FixityTable {
+    pub fn new() -> Self {
+        Self {
+            fixities: HashMap::new(),
+        }
+    }
+
+    pub fn op(mut self, symbol: &str, fixity: Fixity) -> Self {
+        self.fixities.insert(symbol.into(), fixity);
+        self
+    }
+
+    pub fn fixity(&self, symbol: &str) -> Fixity {
+        self.fixities
+            .get(symbol)
+            .copied()
+            .unwrap_or(Fixity::DEFAULT)
+    }
+}
+
+impl Default for FixityTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+lazy_static! {
+    static ref FIXITY_TABLE: FixityTable = FixityTable::new()
+        .op("<=", Fixity::new(Assoc::Left, 4))
+        .op("==", Fixity::new(Assoc::None, 4))
+        .op("^", Fixity::new(Assoc::Right, 5))
+        .op("+", Fixity::new(Assoc::Left, 6))
+        .op("*", Fixity::new(Assoc::Left, 7))
+        .op("land", Fixity::new(Assoc::Left, 7))
+        .op("lor", Fixity::new(Assoc::Left, 7))
+        .op("lxor", Fixity::new(Assoc::Left, 7))
+        .op("lsl", Fixity::new(Assoc::Right, 8))
+        .op("lsr", Fixity::new(Assoc::Right, 8))
+        .op("|>", Fixity::new(Assoc::Left, 1))
+        .op("<|", Fixity::new(Assoc::Right, 1))
+        .op(">>", Fixity::new(Assoc::Left, 9))
+        .op("<<", Fixity::new(Assoc::Right, 9));
+}
+
+#[derive(Clone, Debug)]
+pub enum ParseError {
+    Syntax(Box<pest::error::Error<Rule>>),
+    /// Operators of the same precedence that do not associate with each
+    /// other, e.g. `a <+> b |> c` with `infixl 1 <+>` and `infixr 1 |>`.
+    AmbiguousOperators {
+        lhs: String,
+        rhs: String,
+        pos: (usize, usize),
+    },
+    /// Built-in operators can neither be rebound nor given another fixity.
+    BuiltinOperator {
+        op: String,
+        pos: (usize, usize),
+    },
+    /// A `cond` without an `else` arm, whose guards may all be false.
+    MissingElse {
+        pos: (usize, usize),
+    },
+}
+
+/// Something suspicious about a program that is still accepted.
+#[derive(Clone, Debug)]
+pub enum Warning {
+    /// A pattern that does not match every value, e.g. `1` in
+    /// `let 1 = x in ...`, which fails at runtime instead.
+    RefutablePattern { pos: (usize, usize) },
+}
+
+impl From<pest::error::Error<Rule>> for ParseError {
+    fn from(error: pest::error::Error<Rule>) -> Self {
+        ParseError::Syntax(Box::new(error))
+    }
 }
 
 #[derive(Parser)]
 #[grammar = "pest/syntax.pest"]
-pub struct RainParser;
+pub struct RainParser {
+    fixity_table: FixityTable,
+    warnings: RefCell<Vec<Warning>>,
+    /// Counter for the names of the variables patterns take apart.
+    pattern_counter: Cell<u32>,
+}
 
 impl RainParser {
-    pub fn parse_expression(pairs: Pairs<Rule>) -> Expr {
-        PRATT_PARSER
-            .map_primary(|primary| match primary.as_rule() {
-                Rule::int => Expr::Int(primary.as_str().parse().unwrap()),
-                Rule::r#true => Expr::Bool(true),
-                Rule::r#false => Expr::Bool(false),
-                Rule::id => Expr::Var(primary.as_str().into()),
-                Rule::let_expr => {
-                    let mut inner = primary.into_inner();
-                    let x = inner.next().unwrap().as_str();
-                    let e1 = Self::parse_expression(inner.next().unwrap().into_inner());
-                    let e2 = Self::parse_expression(inner.next().unwrap().into_inner());
-                    Expr::Let {
-                        x: x.into(),
-                        e1: Box::new(e1),
-                        e2: Box::new(e2),
+    /// Parse a whole program: its fixity declarations extend the built-in
+    /// operator table before the body is parsed.
+    pub fn parse_program(program: &str) -> Result<Expr, ParseError> {
+        Self::parse_program_with_warnings(program).map(|(expr, _)| expr)
+    }
+
+    /// Parse a whole program, and report what looks wrong about it.
+    pub fn parse_program_with_warnings(program: &str) -> Result<(Expr, Vec<Warning>), ParseError> {
+        let mut parser = RainParser {
+            fixity_table: FIXITY_TABLE.clone(),
+            warnings: RefCell::new(vec![]),
+            pattern_counter: Cell::new(0),
+        };
+        let mut decls = vec![];
+        for pair in Self::parse(Rule::prog, program)? {
+            match pair.as_rule() {
+                Rule::fixity_decl => parser.declare_fixity(pair)?,
+                Rule::class_decl | Rule::instance_decl | Rule::effect_decl => decls.push(pair),
+                Rule::expr => {
+                    let body = parser.parse_expression(pair.into_inner())?;
+                    let expr = decls
+                        .into_iter()
+                        .try_rfold(body, |body, decl| parser.parse_decl(decl, body))?;
+                    return Ok((expr, parser.warnings.take()));
+                }
+                rule => unreachable!("rule = {rule:?}"),
+            }
+        }
+        unreachable!("a program always has a body")
+    }
+
+    /// A class, instance or effect declaration scoping over `body`.
+    fn parse_decl(&self, pair: Pair<Rule>, body: Expr) -> Result<Expr, ParseError> {
+        let rule = pair.as_rule();
+        let mut inner = pair.into_inner();
+        let name = inner.next().unwrap().as_str().to_owned();
+        let body = Box::new(body);
+        match rule {
+            Rule::class_decl => {
+                let var = inner.next().unwrap().as_str().to_owned();
+                let methods = inner
+                    .map(|sig| {
+                        let mut sig = sig.into_inner();
+                        let method = Self::parse_binder(sig.next().unwrap())?;
+                        Ok((method, Self::parse_type(sig.next().unwrap())?))
+                    })
+                    .collect::<Result<_, ParseError>>()?;
+                Ok(Expr::Class {
+                    name,
+                    var,
+                    methods,
+                    body,
+                })
+            }
+            Rule::instance_decl => {
+                let typ = Self::parse_type(inner.next().unwrap())?;
+                let methods = inner
+                    .map(|binding| {
+                        let mut binding = binding.into_inner();
+                        let method = Self::parse_binder(binding.next().unwrap())?;
+                        let e = self.parse_expression(binding.next().unwrap().into_inner())?;
+                        Ok((method, e))
+                    })
+                    .collect::<Result<_, ParseError>>()?;
+                Ok(Expr::Instance {
+                    class: name,
+                    typ,
+                    methods,
+                    body,
+                })
+            }
+            Rule::effect_decl => {
+                let arg = Self::parse_type(inner.next().unwrap())?;
+                let result = Self::parse_type(inner.next().unwrap())?;
+                Ok(Expr::Effect {
+                    name,
+                    typ: MonoType::Fn(Box::new(arg), Box::new(result)),
+                    body,
+                })
+            }
+            rule => unreachable!("rule = {rule:?}"),
+        }
+    }
+
+    fn parse_type(pair: Pair<Rule>) -> Result<MonoType, ParseError> {
+        let span = pair.as_span();
+        let mut inner = pair.clone().into_inner();
+        match pair.as_rule() {
+            Rule::type_expr => {
+                let lhs = Self::parse_type(inner.next().unwrap())?;
+                match inner.next() {
+                    Some(rhs) => Ok(MonoType::Fn(
+                        Box::new(lhs),
+                        Box::new(Self::parse_type(rhs)?),
+                    )),
+                    None => Ok(lhs),
+                }
+            }
+            Rule::type_postfix => {
+                let custom = |message: &str| {
+                    ParseError::Syntax(Box::new(pest::error::Error::new_from_span(
+                        ErrorVariant::CustomError {
+                            message: message.into(),
+                        },
+                        span,
+                    )))
+                };
+                let atom = inner.next().unwrap();
+                let mut typ = match atom.as_rule() {
+                    Rule::type_pair => {
+                        let mut types = atom.into_inner();
+                        let k = Self::parse_type(types.next().unwrap())?;
+                        let v = Self::parse_type(types.next().unwrap())?;
+                        match inner.next().map(|postfix| postfix.as_rule()) {
+                            Some(Rule::type_map) => MonoType::Map(Box::new(k), Box::new(v)),
+                            _ => return Err(custom("a pair of types must be followed by `map`")),
+                        }
+                    }
+                    _ => Self::parse_type(atom)?,
+                };
+                for postfix in inner {
+                    typ = match postfix.as_rule() {
+                        Rule::type_lazy => MonoType::Lazy(Box::new(typ)),
+                        Rule::type_array => MonoType::Array(Box::new(typ)),
+                        Rule::type_task => MonoType::Task(Box::new(typ)),
+                        Rule::type_chan => MonoType::Chan(Box::new(typ)),
+                        _ => return Err(custom("`map` takes a key and a value type")),
+                    }
+                }
+                Ok(typ)
+            }
+            Rule::type_var => Ok(MonoType::TypeVariable(pair.as_str().into())),
+            Rule::type_record => {
+                let mut fields = vec![];
+                let mut row = MonoType::RowEmpty;
+                for pair in inner {
+                    match pair.as_rule() {
+                        Rule::type_field => {
+                            let mut field = pair.into_inner();
+                            let label = field.next().unwrap().as_str().to_owned();
+                            fields.push((label, Self::parse_type(field.next().unwrap())?));
+                        }
+                        _ => row = Self::parse_type(pair)?,
                     }
                 }
-                Rule::r#if_expr => {
-                    let mut inner = primary.into_inner();
-                    let guard = Self::parse_expression(inner.next().unwrap().into_inner());
-                    let e1 = Self::parse_expression(inner.next().unwrap().into_inner());
-                    let e2 = Self::parse_expression(inner.next().unwrap().into_inner());
-                    Expr::If {
+                let row = fields.into_iter().rev().fold(row, |row, (label, typ)| {
+                    MonoType::RowExtend(label, Box::new(typ), Box::new(row))
+                });
+                Ok(MonoType::Record(Box::new(row)))
+            }
+            Rule::type_measured => {
+                let base = Self::parse_type(inner.next().unwrap())?;
+                if !matches!(base, MonoType::Int | MonoType::Fixed(_)) {
+                    return Err(ParseError::Syntax(Box::new(
+                        pest::error::Error::new_from_span(
+                            ErrorVariant::CustomError {
+                                message: format!("{base} cannot have a unit"),
+                            },
+                            span,
+                        ),
+                    )));
+                }
+                // The base has no unit yet, so no exponent can overflow.
+                Ok(MonoType::measured(base, Self::parse_measure(inner.next().unwrap())?).unwrap())
+            }
+            Rule::type_name => match pair.as_str() {
+                "Int" => Ok(MonoType::Int),
+                "Bool" => Ok(MonoType::Bool),
+                "String" => Ok(MonoType::String),
+                "Unit" => Ok(MonoType::Unit),
+                "Dyn" => Ok(MonoType::Dyn(pair.line_col())),
+                name => Width::ALL
+                    .into_iter()
+                    .find(|width| width.to_string() == name)
+                    .map(MonoType::Fixed)
+                    .ok_or_else(|| {
+                        ParseError::Syntax(Box::new(pest::error::Error::new_from_span(
+                            ErrorVariant::CustomError {
+                                message: format!("unknown type {name}"),
+                            },
+                            span,
+                        )))
+                    }),
+            },
+            rule => unreachable!("rule = {rule:?}"),
+        }
+    }
+
+    fn declare_fixity(&mut self, pair: Pair<Rule>) -> Result<(), ParseError> {
+        let mut inner = pair.into_inner();
+        let assoc = match inner.next().unwrap().as_rule() {
+            Rule::infixl => Assoc::Left,
+            Rule::infixr => Assoc::Right,
+            Rule::infix => Assoc::None,
+            rule => unreachable!("rule = {rule:?}"),
+        };
+        let precedence = inner.next().unwrap().as_str().parse().unwrap();
+        for op in inner {
+            if BinOp::from_symbol(op.as_str()).is_some() {
+                return Err(ParseError::BuiltinOperator {
+                    op: op.as_str().into(),
+                    pos: op.line_col(),
+                });
+            }
+            self.fixity_table
+                .fixities
+                .insert(op.as_str().into(), Fixity::new(assoc, precedence));
+        }
+        Ok(())
+    }
+
+    pub fn parse_expression(&self, pairs: Pairs<Rule>) -> Result<Expr, ParseError> {
+        let mut pairs = pairs.peekable();
+        let lhs = self.parse_primary(pairs.next().unwrap())?;
+        let expr = self.parse_infix(lhs, &mut pairs, 0)?;
+        match pairs.next() {
+            Some(where_clause) => self.parse_bindings(where_clause.into_inner(), expr),
+            None => Ok(expr),
+        }
+    }
+
+    /// Desugar the bindings of `let x = e1, y = e2 in body` or
+    /// `body where x = e1 and y = e2` into nested `let`s. Each binding is in
+    /// scope for the ones after it and is generalized on its own, exactly as
+    /// if the `let`s had been written out by hand.
+    fn parse_bindings(&self, bindings: Pairs<Rule>, body: Expr) -> Result<Expr, ParseError> {
+        let mut parsed = vec![];
+        for binding in bindings {
+            let mut inner = binding.into_inner();
+            let lhs = inner.next().unwrap();
+            let e1 = self.parse_expression(inner.next().unwrap().into_inner())?;
+            parsed.push((lhs, e1));
+        }
+        parsed.into_iter().try_rfold(body, |e2, (lhs, e1)| {
+            let (x, e2) = match lhs.as_rule() {
+                Rule::binder => (Self::parse_binder(lhs)?, e2),
+                _ => {
+                    let x = self.fresh_var();
+                    let e2 = self.bind_pattern(lhs, &x, e2)?;
+                    (x, e2)
+                }
+            };
+            Ok(Expr::Let {
+                x,
+                e1: Box::new(e1),
+                e2: Box::new(e2),
+            })
+        })
+    }
+
+    /// A variable for a pattern to take apart, which no identifier can
+    /// shadow.
+    fn fresh_var(&self) -> String {
+        self.pattern_counter.set(self.pattern_counter.get() + 1);
+        format!("$pat{}", self.pattern_counter.get())
+    }
+
+    /// `fn (pattern) -> body`.
+    fn pattern_fn(&self, pattern: Pair<Rule>, body: Expr) -> Result<Expr, ParseError> {
+        let inner = pattern.clone().into_inner().next().unwrap();
+        if inner.as_rule() == Rule::id {
+            return Ok(Expr::Fn {
+                arg: inner.as_str().into(),
+                body: Box::new(body),
+            });
+        }
+        let x = self.fresh_var();
+        Ok(Expr::Fn {
+            body: Box::new(self.bind_pattern(pattern, &x, body)?),
+            arg: x,
+        })
+    }
+
+    /// `body` in the scope of the variables `pattern` binds to the parts of
+    /// the value of `x`. Both `fn` parameters and `let` bindings are
+    /// desugared into this.
+    fn bind_pattern(&self, pattern: Pair<Rule>, x: &str, body: Expr) -> Result<Expr, ParseError> {
+        let pattern = match pattern.as_rule() {
+            Rule::pattern => pattern.into_inner().next().unwrap(),
+            _ => pattern,
+        };
+        let pos = pattern.line_col();
+        let value = Expr::Var(x.into());
+        Ok(match pattern.as_rule() {
+            Rule::wildcard => body,
+            Rule::id => Expr::Let {
+                x: pattern.as_str().into(),
+                e1: Box::new(value),
+                e2: Box::new(body),
+            },
+            Rule::record_pattern => pattern.into_inner().rev().try_fold(
+                body,
+                |body, field| -> Result<Expr, ParseError> {
+                    let mut inner = field.into_inner();
+                    let label = inner.next().unwrap().as_str().to_owned();
+                    let y = self.fresh_var();
+                    let body = match inner.next() {
+                        Some(pattern) => self.bind_pattern(pattern, &y, body)?,
+                        None => Expr::Let {
+                            x: label.clone(),
+                            e1: Box::new(Expr::Var(y.clone())),
+                            e2: Box::new(body),
+                        },
+                    };
+                    Ok(Expr::Let {
+                        x: y,
+                        e1: Box::new(Expr::Select {
+                            record: Box::new(value.clone()),
+                            label,
+                        }),
+                        e2: Box::new(body),
+                    })
+                },
+            )?,
+            // `()` always matches, but the check makes it a `Unit`.
+            rule => {
+                if rule != Rule::unit {
+                    self.warnings
+                        .borrow_mut()
+                        .push(Warning::RefutablePattern { pos });
+                }
+                Expr::If {
+                    guard: Box::new(Expr::Bin {
+                        op: BinOp::Eq,
+                        e1: Box::new(value),
+                        e2: Box::new(self.parse_primary(pattern)?),
+                    }),
+                    e1: Box::new(body),
+                    e2: Box::new(Expr::MatchFailure { pos }),
+                }
+            }
+        })
+    }
+
+    /// Precedence climbing over `operand (binop operand)*`, folding every
+    /// operator that binds at least as tight as `min_precedence` into `lhs`.
+    fn parse_infix(
+        &self,
+        mut lhs: Expr,
+        pairs: &mut Peekable<Pairs<Rule>>,
+        min_precedence: u8,
+    ) -> Result<Expr, ParseError> {
+        while let Some(op) = pairs.next_if(|op| {
+            op.as_rule() == Rule::binop
+                && self.fixity_table.fixity(op.as_str()).precedence >= min_precedence
+        }) {
+            let fixity = self.fixity_table.fixity(op.as_str());
+            let mut rhs = self.parse_primary(pairs.next().unwrap())?;
+            while let Some(next) = pairs.peek().filter(|next| next.as_rule() == Rule::binop) {
+                let next_fixity = self.fixity_table.fixity(next.as_str());
+                if next_fixity.precedence > fixity.precedence {
+                    rhs = self.parse_infix(rhs, pairs, fixity.precedence + 1)?;
+                } else if next_fixity.precedence < fixity.precedence {
+                    break;
+                } else if fixity.assoc != next_fixity.assoc || fixity.assoc == Assoc::None {
+                    return Err(ParseError::AmbiguousOperators {
+                        lhs: op.as_str().into(),
+                        rhs: next.as_str().into(),
+                        pos: next.line_col(),
+                    });
+                } else if fixity.assoc == Assoc::Right {
+                    rhs = self.parse_infix(rhs, pairs, fixity.precedence)?;
+                } else {
+                    break;
+                }
+            }
+            lhs = Self::binary(op.as_str(), lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn parse_primary(&self, primary: Pair<Rule>) -> Result<Expr, ParseError> {
+        let expr = match primary.as_rule() {
+            Rule::int => Self::parse_int(primary)?,
+            Rule::string => self.parse_string(primary.into_inner())?,
+            Rule::r#true => Expr::Bool(true),
+            Rule::r#false => Expr::Bool(false),
+            Rule::id => Expr::Var(primary.as_str().into()),
+            Rule::let_expr => {
+                let mut inner = primary.into_inner();
+                let body = inner.next_back().unwrap();
+                let body = self.parse_expression(body.into_inner())?;
+                self.parse_bindings(inner, body)?
+            }
+            // `let* x = e1 in e2` is `bind(fn (x) -> e2, e1)`, and `let+`
+            // is the same with `map`.
+            Rule::binding_op_expr => {
+                let mut inner = primary.into_inner();
+                let op = inner.next().unwrap();
+                let pattern = inner.next().unwrap();
+                let e1 = self.parse_expression(inner.next().unwrap().into_inner())?;
+                let e2 = self.parse_expression(inner.next().unwrap().into_inner())?;
+                Expr::BindingOp {
+                    op: op.as_str().into(),
+                    pos: op.line_col(),
+                    e1: Box::new(e1),
+                    f: Box::new(self.pattern_fn(pattern, e2)?),
+                }
+            }
+            Rule::r#if_expr => {
+                let mut inner = primary.into_inner();
+                let guard = self.parse_expression(inner.next().unwrap().into_inner())?;
+                let e1 = self.parse_expression(inner.next().unwrap().into_inner())?;
+                let e2 = self.parse_expression(inner.next().unwrap().into_inner())?;
+                Expr::If {
+                    guard: Box::new(guard),
+                    e1: Box::new(e1),
+                    e2: Box::new(e2),
+                }
+            }
+            Rule::perform_expr => {
+                let mut inner = primary.into_inner();
+                let op = inner.next().unwrap().as_str().to_owned();
+                let arg = self.parse_expression(inner.next().unwrap().into_inner())?;
+                Expr::Perform {
+                    op,
+                    arg: Box::new(arg),
+                }
+            }
+            Rule::handle_expr => {
+                let mut inner = primary.into_inner();
+                let body = self.parse_expression(inner.next().unwrap().into_inner())?;
+                let mut clauses = vec![];
+                let mut ret = ("x".to_owned(), Box::new(Expr::Var("x".into())));
+                for arm in inner {
+                    let rule = arm.as_rule();
+                    let mut ids = vec![];
+                    let mut e = None;
+                    for pair in arm.into_inner() {
+                        match pair.as_rule() {
+                            Rule::id => ids.push(pair.as_str().to_owned()),
+                            _ => e = Some(self.parse_expression(pair.into_inner())?),
+                        }
+                    }
+                    let body = e.unwrap();
+                    match (rule, ids.as_slice()) {
+                        (Rule::op_arm, [op, arg, k]) => clauses.push(OpClause {
+                            op: op.clone(),
+                            arg: arg.clone(),
+                            k: k.clone(),
+                            body,
+                        }),
+                        (Rule::return_arm, [x]) => ret = (x.clone(), Box::new(body)),
+                        (rule, _) => unreachable!("rule = {rule:?}"),
+                    }
+                }
+                Expr::Handle {
+                    body: Box::new(body),
+                    clauses,
+                    ret,
+                }
+            }
+            Rule::cond_expr => {
+                let pos = primary.line_col();
+                let mut arms = vec![];
+                let mut otherwise = None;
+                for arm in primary.into_inner() {
+                    let rule = arm.as_rule();
+                    let mut inner = arm.into_inner();
+                    match rule {
+                        Rule::cond_arm => {
+                            let guard =
+                                self.parse_expression(inner.next().unwrap().into_inner())?;
+                            let e = self.parse_expression(inner.next().unwrap().into_inner())?;
+                            arms.push((guard, e));
+                        }
+                        Rule::else_arm => {
+                            otherwise =
+                                Some(self.parse_expression(inner.next().unwrap().into_inner())?);
+                        }
+                        rule => unreachable!("rule = {rule:?}"),
+                    }
+                }
+                // A final `| true -> e` arm covers every case as well as `else` does.
+                let otherwise = match otherwise {
+                    Some(e) => e,
+                    None => match arms.pop() {
+                        Some((Expr::Bool(true), e)) => e,
+                        _ => return Err(ParseError::MissingElse { pos }),
+                    },
+                };
+                arms.into_iter()
+                    .rev()
+                    .fold(otherwise, |e2, (guard, e1)| Expr::If {
                         guard: Box::new(guard),
                         e1: Box::new(e1),
                         e2: Box::new(e2),
+                    })
+            }
+            Rule::binop_expr => self.parse_expression(primary.into_inner())?,
+            Rule::fn_expr => {
+                let mut inner = primary.into_inner();
+                let args = inner.next().unwrap().into_inner();
+                let body = self.parse_expression(inner.next().unwrap().into_inner())?;
+                let mut fn_expr = body;
+                for param in args.rev() {
+                    fn_expr = match param.as_rule() {
+                        Rule::labeled_param => Expr::LabeledFn {
+                            label: param.into_inner().as_str().into(),
+                            default: None,
+                            body: Box::new(fn_expr),
+                        },
+                        Rule::optional_param => {
+                            let mut inner = param.into_inner();
+                            let label = inner.next().unwrap().as_str().into();
+                            let default =
+                                self.parse_expression(inner.next().unwrap().into_inner())?;
+                            Expr::LabeledFn {
+                                label,
+                                default: Some(Box::new(default)),
+                                body: Box::new(fn_expr),
+                            }
+                        }
+                        _ => self.pattern_fn(param, fn_expr)?,
                     }
                 }
-                Rule::binop_expr => Self::parse_expression(primary.into_inner()),
-                Rule::fn_expr => {
-                    let mut inner = primary.into_inner();
-                    let args = inner.next().unwrap().into_inner();
-                    let body = Self::parse_expression(inner.next().unwrap().into_inner());
-                    let mut fn_expr = body;
-                    for arg in args.rev() {
-                        fn_expr = Expr::Fn {
-                            arg: arg.as_str().into(),
-                            body: Box::new(fn_expr),
+                fn_expr
+            }
+            Rule::apply_expr => {
+                let mut inner = primary.into_inner();
+                let mut apply_expr = self.parse_primary(inner.next().unwrap())?;
+                for call in inner {
+                    let args = match call.into_inner().next() {
+                        Some(args) => args
+                            .into_inner()
+                            .map(|arg| self.parse_arg(arg))
+                            .collect::<Result<_, _>>()?,
+                        None => vec![(None, Expr::Unit)],
+                    };
+                    for (label, arg) in args {
+                        apply_expr = match label {
+                            Some(label) => Expr::LabeledApply {
+                                func: Box::new(apply_expr),
+                                label,
+                                arg: Box::new(arg),
+                            },
+                            None => Expr::Apply {
+                                func: Box::new(apply_expr),
+                                arg: Box::new(arg),
+                            },
+                        }
+                    }
+                }
+                apply_expr
+            }
+            Rule::paren_expr => {
+                let mut inner = primary.into_inner();
+                let first = inner.next().unwrap();
+                match (first.as_rule(), inner.next()) {
+                    (Rule::expr, None) => self.parse_expression(first.into_inner())?,
+                    (Rule::expr, Some(ascription))
+                        if ascription.as_rule() == Rule::type_ascription =>
+                    {
+                        Expr::Ascribe {
+                            expr: Box::new(self.parse_expression(first.into_inner())?),
+                            typ: Self::parse_type(ascription.into_inner().next().unwrap())?,
                         }
                     }
-                    fn_expr
-                }
-                Rule::apply_expr => {
-                    let mut inner = primary.into_inner();
-                    let func = Self::parse_expression(inner.next().unwrap().into_inner());
-                    let args = inner.next().unwrap().into_inner();
-                    let mut apply_expr = func;
-                    for arg in args {
-                        apply_expr = Expr::Apply {
-                            func: Box::new(apply_expr),
-                            arg: Box::new(Self::parse_expression(arg.into_inner())),
+                    (Rule::binop, None) => Self::section(first.as_str(), None, None),
+                    (Rule::binop, Some(rhs)) => Self::section(
+                        first.as_str(),
+                        None,
+                        Some(self.parse_expression(rhs.into_inner())?),
+                    ),
+                    (Rule::expr, Some(op)) => Self::section(
+                        op.as_str(),
+                        Some(self.parse_expression(first.into_inner())?),
+                        None,
+                    ),
+                    (rule, _) => unreachable!("rule = {rule:?}"),
+                }
+            }
+            Rule::unit => Expr::Unit,
+            Rule::assert_expr => {
+                let pos = primary.line_col();
+                let cond =
+                    self.parse_expression(primary.into_inner().next().unwrap().into_inner())?;
+                Expr::Assert {
+                    cond: Box::new(cond),
+                    pos,
+                }
+            }
+            Rule::test_expr => {
+                let pos = primary.line_col();
+                let mut inner = primary.into_inner();
+                let name = inner.next().unwrap().into_inner().next();
+                let name = Self::unescape(name.map_or("", |text| text.as_str()));
+                let e = self.parse_expression(inner.next().unwrap().into_inner())?;
+                let body = self.parse_expression(inner.next().unwrap().into_inner())?;
+                Expr::Test {
+                    name,
+                    pos,
+                    e: Box::new(e),
+                    body: Box::new(body),
+                }
+            }
+            Rule::lazy_expr => {
+                let e = self.parse_primary(primary.into_inner().next().unwrap())?;
+                Expr::Lazy(Box::new(e))
+            }
+            Rule::atom => {
+                let mut inner = primary.into_inner();
+                let atom = self.parse_primary(inner.next().unwrap())?;
+                inner.fold(atom, |record, field_op| {
+                    let rule = field_op.as_rule();
+                    let label = field_op.into_inner().next().unwrap().as_str().to_owned();
+                    let record = Box::new(record);
+                    match rule {
+                        Rule::select => Expr::Select { record, label },
+                        Rule::remove => Expr::Remove { record, label },
+                        rule => unreachable!("rule = {rule:?}"),
+                    }
+                })
+            }
+            Rule::record_expr => {
+                let mut fields = vec![];
+                let mut rest = None;
+                for pair in primary.into_inner() {
+                    match pair.as_rule() {
+                        Rule::field => {
+                            let mut inner = pair.into_inner();
+                            let label = inner.next().unwrap().as_str().to_owned();
+                            let value =
+                                self.parse_expression(inner.next().unwrap().into_inner())?;
+                            fields.push((label, value));
                         }
+                        _ => rest = Some(self.parse_expression(pair.into_inner())?),
                     }
-                    apply_expr
                 }
-                Rule::atom => Self::parse_expression(primary.into_inner()),
-                Rule::expr => Self::parse_expression(primary.into_inner()),
+                match rest {
+                    None => Expr::Record(fields),
+                    Some(rest) => fields
+                        .into_iter()
+                        .rev()
+                        .fold(rest, |record, (label, value)| Expr::Extend {
+                            label,
+                            value: Box::new(value),
+                            record: Box::new(record),
+                        }),
+                }
+            }
+            Rule::expr | Rule::tail_expr => self.parse_expression(primary.into_inner())?,
+            rule => unreachable!("rule = {rule:?}"),
+        };
+        Ok(expr)
+    }
+
+    fn parse_int(primary: Pair<Rule>) -> Result<Expr, ParseError> {
+        let span = primary.as_span();
+        let error = |message: String| {
+            ParseError::Syntax(Box::new(pest::error::Error::new_from_span(
+                ErrorVariant::CustomError { message },
+                span,
+            )))
+        };
+        let mut digits = String::new();
+        let mut radix = 10;
+        let mut width = None;
+        let mut measure = None;
+        for part in primary.clone().into_inner() {
+            match part.as_rule() {
+                Rule::sign => digits.push('-'),
+                Rule::int_suffix => width = Width::from_suffix(part.as_str()),
+                Rule::measure => measure = Some(part),
+                rule => {
+                    let (prefix_len, part_radix) = match rule {
+                        Rule::hex_int => (2, 16),
+                        Rule::oct_int => (2, 8),
+                        Rule::bin_int => (2, 2),
+                        _ => (0, 10),
+                    };
+                    radix = part_radix;
+                    digits.extend(part.as_str()[prefix_len..].chars().filter(|&c| c != '_'));
+                }
+            }
+        }
+        let i = Int::from_str_radix(&digits, radix)
+            .ok_or_else(|| error("invalid integer literal".into()))?;
+        let value = match width {
+            None => Expr::Int(i),
+            Some(width) => match FixedInt::checked_from(width, &i) {
+                Some(fixed) => Expr::Fixed(fixed),
+                None => Err(error(format!("literal out of range for {width}")))?,
+            },
+        };
+        let spelling = primary.as_str();
+        let literal = Expr::Literal {
+            spelling: spelling[..spelling.len() - measure.as_ref().map_or(0, |m| m.as_str().len())]
+                .to_owned(),
+            value: Box::new(value),
+        };
+        match measure {
+            Some(measure) if measure.as_str().contains('\'') => {
+                Err(error("a literal cannot have a unit variable".into()))
+            }
+            Some(measure) => Ok(Expr::Measured {
+                expr: Box::new(literal),
+                measure: Self::parse_measure(measure)?,
+            }),
+            None => Ok(literal),
+        }
+    }
+
+    /// A unit of measure, e.g. `<kg*m/s^2>`, as a `MonoType::Measure`.
+    fn parse_measure(pair: Pair<Rule>) -> Result<MonoType, ParseError> {
+        let mut measure = MonoType::Measure(BTreeMap::new());
+        let mut sign = 1;
+        for part in pair.into_inner() {
+            if part.as_rule() == Rule::measure_op {
+                sign = if part.as_str() == "/" { -1 } else { 1 };
+                continue;
+            }
+            let span = part.as_span();
+            let mut inner = part.into_inner();
+            let unit = match inner.next().unwrap() {
+                one if one.as_rule() == Rule::measure_one => BTreeMap::new(),
+                unit => BTreeMap::from([(unit.as_str().to_owned(), 1)]),
+            };
+            let out_of_range = || {
+                ParseError::Syntax(Box::new(pest::error::Error::new_from_span(
+                    ErrorVariant::CustomError {
+                        message: "exponent out of range".into(),
+                    },
+                    span,
+                )))
+            };
+            let exponent = inner
+                .next()
+                .map_or(Some(1), |e| e.as_str().parse::<i32>().ok());
+            let exponent = exponent
+                .and_then(|e| e.checked_mul(sign))
+                .ok_or_else(out_of_range)?;
+            measure = MonoType::measure_product(&measure, &MonoType::Measure(unit), exponent)
+                .map_err(|_| out_of_range())?;
+        }
+        Ok(measure)
+    }
+
+    /// Desugar a string literal into concatenations, converting every
+    /// interpolated expression with `show`, e.g. `"x = ${x}!"` becomes
+    /// `"x = " ^ (show(x) ^ "!")`.
+    fn parse_string(&self, pieces: Pairs<Rule>) -> Result<Expr, ParseError> {
+        let mut parsed = vec![];
+        for piece in pieces {
+            parsed.push(match piece.as_rule() {
+                Rule::string_text => Expr::Str(Self::unescape(piece.as_str())),
+                Rule::interpolation => Expr::Apply {
+                    func: Box::new(Expr::Prim {
+                        prim: Prim::Show,
+                        args: vec![],
+                    }),
+                    arg: Box::new(self.parse_expression(piece.into_inner())?),
+                },
                 rule => unreachable!("rule = {rule:?}"),
-            })
-            .map_infix(|lhs, op, rhs| {
-                let op = match op.as_rule() {
-                    Rule::plus => BinOp::Plus,
-                    Rule::times => BinOp::Times,
-                    Rule::le => BinOp::Le,
-                    _ => unreachable!(),
-                };
-                Expr::Bin {
-                    op,
-                    e1: Box::new(lhs),
-                    e2: Box::new(rhs),
+            });
+        }
+        let last = parsed.pop().unwrap_or(Expr::Str(String::new()));
+        Ok(parsed.into_iter().rev().fold(last, |rhs, lhs| Expr::Bin {
+            op: BinOp::Concat,
+            e1: Box::new(lhs),
+            e2: Box::new(rhs),
+        }))
+    }
+
+    fn unescape(text: &str) -> String {
+        let mut unescaped = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next() {
+                    Some('n') => unescaped.push('\n'),
+                    Some('t') => unescaped.push('\t'),
+                    Some(c) => unescaped.push(c),
+                    None => unreachable!("the grammar only allows complete escapes"),
                 }
-            })
-            .parse(pairs)
+            } else {
+                unescaped.push(c);
+            }
+        }
+        unescaped
+    }
+
+    /// The name bound by `let`: an identifier, or an operator in parentheses.
+    fn parse_binder(pair: Pair<Rule>) -> Result<String, ParseError> {
+        let name = pair.into_inner().next().unwrap();
+        if name.as_rule() == Rule::binop && BinOp::from_symbol(name.as_str()).is_some() {
+            return Err(ParseError::BuiltinOperator {
+                op: name.as_str().into(),
+                pos: name.line_col(),
+            });
+        }
+        Ok(name.as_str().into())
+    }
+
+    /// An argument of a call, with its label if it has one. `~width` alone
+    /// passes the variable `width`.
+    fn parse_arg(&self, arg: Pair<Rule>) -> Result<(Option<String>, Expr), ParseError> {
+        if arg.as_rule() != Rule::labeled_arg {
+            return Ok((None, self.parse_expression(arg.into_inner())?));
+        }
+        let mut inner = arg.into_inner();
+        let label = inner.next().unwrap().as_str().to_owned();
+        let value = match inner.next() {
+            Some(value) => self.parse_expression(value.into_inner())?,
+            None => Expr::Var(label.clone()),
+        };
+        Ok((Some(label), value))
+    }
+
+    /// `lhs op rhs`. Built-in operators become `Expr::Bin`, user-defined ones
+    /// are applications of the function bound to the operator's symbol.
+    fn binary(op: &str, lhs: Expr, rhs: Expr) -> Expr {
+        match BinOp::from_symbol(op) {
+            Some(op) => Expr::Bin {
+                op,
+                e1: Box::new(lhs),
+                e2: Box::new(rhs),
+            },
+            None => Expr::Apply {
+                func: Box::new(Expr::Apply {
+                    func: Box::new(Expr::Var(op.into())),
+                    arg: Box::new(lhs),
+                }),
+                arg: Box::new(rhs),
+            },
+        }
+    }
+
+    /// Desugar an operator section into a function, e.g. `(+ 1)` becomes
+    /// `fn ($lhs) -> $lhs + 1`. The parameter names cannot clash with user
+    /// identifiers, which consist of letters, digits and `_`, nor with the
+    /// names generated elsewhere, which all end in a number except for the
+    /// type checker's `$dict`. An operand is outside the function, so it
+    /// cannot refer to the parameters either.
+    fn section(op: &str, lhs: Option<Expr>, rhs: Option<Expr>) -> Expr {
+        let mut params = vec![];
+        let e1 = lhs.unwrap_or_else(|| {
+            params.push("$lhs");
+            Expr::Var("$lhs".into())
+        });
+        let e2 = rhs.unwrap_or_else(|| {
+            params.push("$rhs");
+            Expr::Var("$rhs".into())
+        });
+        let mut fn_expr = Self::binary(op, e1, e2);
+        for param in params.into_iter().rev() {
+            fn_expr = Expr::Fn {
+                arg: param.into(),
+                body: Box::new(fn_expr),
+            }
+        }
+        fn_expr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ParseError, RainParser, Warning};
+    use crate::ast::{BinOp, Expr};
+    use crate::types::MonoType;
+    use pest::error::LineColLocation;
+
+    fn parse(program: &str) -> Expr {
+        RainParser::parse_program(program).unwrap()
+    }
+
+    #[test]
+    fn test_sections() {
+        let e = parse("(+)");
+        assert!(matches!(
+            e,
+            Expr::Fn { ref body, .. } if matches!(**body, Expr::Fn { .. })
+        ));
+
+        let e = parse("(+ 1)");
+        assert!(matches!(
+            e,
+            Expr::Fn { ref body, .. } if matches!(**body, Expr::Bin { op: BinOp::Plus, .. })
+        ));
+
+        let e = parse("(2 *)");
+        assert!(matches!(
+            e,
+            Expr::Fn { ref body, .. } if matches!(**body, Expr::Bin { op: BinOp::Times, .. })
+        ));
+
+        let e = parse("(1)");
+        assert!(matches!(e, Expr::Literal { ref spelling, .. } if spelling == "1"));
+
+        // The parameters of a section are not identifiers a program can use.
+        assert!(RainParser::parse_program("fn ($lhs) -> $lhs").is_err());
+        assert!(RainParser::parse_program("(+ $lhs)").is_err());
+    }
+
+    #[test]
+    fn test_precedence() {
+        // (2 * 3) + 1
+        let e = parse("2 * 3 + 1");
+        assert!(matches!(
+            e,
+            Expr::Bin {
+                op: BinOp::Plus,
+                ..
+            }
+        ));
+
+        // 1 + (2 * 3)
+        let e = parse("1 + 2 * 3");
+        assert!(matches!(
+            e,
+            Expr::Bin {
+                op: BinOp::Plus,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_identifiers() {
+        assert!(matches!(parse("get_or2"), Expr::Var(ref x) if x == "get_or2"));
+        // A keyword followed by `_` starts an identifier.
+        assert!(matches!(parse("in_range"), Expr::Var(ref x) if x == "in_range"));
+        assert!(matches!(
+            parse("{ max_len = 1 }.max_len"),
+            Expr::Select { ref label, .. } if label == "max_len"
+        ));
+        assert!(RainParser::parse_program("_x").is_err());
+    }
+
+    #[test]
+    fn test_fixity_decl() {
+        // (x |>> f) |>> g
+        let e = parse("infixl 1 |>>\nx |>> f |>> g");
+        assert!(matches!(
+            e,
+            Expr::Apply { ref func, .. } if matches!(
+                **func,
+                Expr::Apply { ref arg, .. } if matches!(**arg, Expr::Apply { .. })
+            )
+        ));
+
+        // f <<| (g <<| x)
+        let e = parse("infixr 1 <<|\nf <<| g <<| x");
+        assert!(matches!(
+            e,
+            Expr::Apply { ref func, ref arg } if matches!(**arg, Expr::Apply { .. })
+                && matches!(**func, Expr::Apply { ref arg, .. } if matches!(**arg, Expr::Var(_)))
+        ));
+
+        let e = RainParser::parse_program("infixl 1 |>>\ninfixr 1 <<|\nf <<| x |>> g");
+        assert!(matches!(e, Err(ParseError::AmbiguousOperators { .. })));
+
+        let e = RainParser::parse_program("a == b == c");
+        assert!(matches!(e, Err(ParseError::AmbiguousOperators { .. })));
+
+        let e = RainParser::parse_program("infixr 1 +\n1 + 2");
+        assert!(matches!(e, Err(ParseError::BuiltinOperator { .. })));
+
+        let e = RainParser::parse_program("let (+) = fn (x, y) -> x in 1 + 2");
+        assert!(matches!(e, Err(ParseError::BuiltinOperator { .. })));
+
+        // Operators are lexed greedily, so `x+-1` applies an operator `+-`
+        // rather than adding `-1`.
+        let e = parse("x+-1");
+        assert!(matches!(
+            e,
+            Expr::Apply { ref func, .. } if matches!(
+                **func,
+                Expr::Apply { ref func, .. } if matches!(**func, Expr::Var(ref op) if op == "+-")
+            )
+        ));
+        let e = parse("x + -1");
+        assert!(matches!(
+            e,
+            Expr::Bin { op: BinOp::Plus, ref e2, .. } if matches!(**e2, Expr::Literal { .. })
+        ));
+    }
+
+    #[test]
+    fn test_multi_binding_let_and_where() {
+        for program in ["let a = 1, b = 2 in a + b", "a + b where a = 1 and b = 2"] {
+            let e = parse(program);
+            assert!(matches!(
+                e,
+                Expr::Let { ref x, ref e2, .. } if x == "a" && matches!(
+                    **e2,
+                    Expr::Let { ref x, ref e2, .. } if x == "b"
+                        && matches!(**e2, Expr::Bin { op: BinOp::Plus, .. })
+                )
+            ));
+        }
+    }
+
+    #[test]
+    fn test_cond() {
+        let e = parse("cond | x <= 0 -> 0 | x <= 10 -> 1 | else -> 2");
+        assert!(matches!(
+            e,
+            Expr::If { ref e2, .. } if matches!(
+                **e2,
+                Expr::If { ref e2, .. } if matches!(**e2, Expr::Literal { ref spelling, .. } if spelling == "2")
+            )
+        ));
+
+        let e = parse("cond | x <= 0 -> 0 | true -> 1");
+        assert!(
+            matches!(e, Expr::If { ref e2, .. } if matches!(**e2, Expr::Literal { ref spelling, .. } if spelling == "1"))
+        );
+
+        let e = RainParser::parse_program("cond | x <= 0 -> 0 | x <= 10 -> 1");
+        assert!(matches!(e, Err(ParseError::MissingElse { pos: (1, 1) })));
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let e = parse(r#""a\"b\n""#);
+        assert!(matches!(e, Expr::Str(ref s) if s == "a\"b\n"));
+
+        let e = parse(r#""""#);
+        assert!(matches!(e, Expr::Str(ref s) if s.is_empty()));
+
+        // "total: " ^ show(x + y)
+        let e = parse(r#""total: ${ x + y }""#);
+        assert!(matches!(
+            e,
+            Expr::Bin { op: BinOp::Concat, ref e2, .. } if matches!(
+                **e2,
+                Expr::Apply { ref func, ref arg } if matches!(**func, Expr::Prim { .. })
+                    && matches!(**arg, Expr::Bin { op: BinOp::Plus, .. })
+            )
+        ));
+
+        // Nested string literals inside an interpolation.
+        let e = parse(r#""${ "${1}" }""#);
+        assert!(matches!(e, Expr::Apply { .. }));
+    }
+
+    #[test]
+    fn test_fixed_width_literals() {
+        let e = parse("255u8 land 15u8");
+        assert!(matches!(
+            e,
+            Expr::Bin { op: BinOp::Land, ref e1, .. } if matches!(**e1, Expr::Literal { .. })
+        ));
+
+        assert!(RainParser::parse_program("256u8").is_err());
+        assert!(RainParser::parse_program("-1u8").is_err());
+        assert!(RainParser::parse_program("-128i8").is_ok());
+    }
+
+    #[test]
+    fn test_int_literals() {
+        let value = |program: &str| match parse(program) {
+            Expr::Literal { spelling, value } => {
+                assert_eq!(spelling, program);
+                value.to_string()
+            }
+            other => panic!("{other:?} is not a literal"),
+        };
+        assert_eq!(value("0xFF"), "255");
+        assert_eq!(value("-0o17"), "-15");
+        assert_eq!(value("0b1010_1010u8"), "170");
+        assert_eq!(value("1_000_000"), "1000000");
+        assert_eq!(value("0x8000_0000_0000_0000"), "9223372036854775808");
+
+        assert!(RainParser::parse_program("1__0").is_err());
+        assert!(RainParser::parse_program("0b102").is_err());
+        match RainParser::parse_program("1 + 0x100u8") {
+            Err(ParseError::Syntax(e)) => {
+                assert_eq!(e.line_col, LineColLocation::Span((1, 5), (1, 12)))
+            }
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_class_decl() {
+        let program = "class Size 'a { size : 'a lazy -> Int, (<+>) : 'a -> 'a -> 'a }
+            instance Size Bool { size = fn (x) -> 1, (<+>) = fn (x, y) -> x }
+            size";
+        let e = parse(program);
+        let Expr::Class {
+            ref methods,
+            ref body,
+            ..
+        } = e
+        else {
+            panic!("{e:?} is not a class declaration");
+        };
+        assert_eq!(methods[0].0, "size");
+        assert_eq!(methods[0].1.to_string(), "'a lazy -> Int");
+        assert_eq!(methods[1].0, "<+>");
+        assert!(matches!(
+            **body,
+            Expr::Instance { ref typ, ref methods, .. }
+                if *typ == MonoType::Bool && methods.len() == 2
+        ));
+
+        assert!(RainParser::parse_program("class C 'a { m : Float } 1").is_err());
+    }
+
+    #[test]
+    fn test_records() {
+        let e = parse("{ x = 1, y = 2 | r }.x \\ y");
+        let Expr::Remove {
+            ref record,
+            ref label,
+        } = e
+        else {
+            panic!("{e:?} is not a field removal");
+        };
+        assert_eq!(label, "y");
+        let Expr::Select {
+            ref record,
+            ref label,
+        } = **record
+        else {
+            panic!("{record:?} is not a field selection");
+        };
+        assert_eq!(label, "x");
+        assert!(matches!(
+            **record,
+            Expr::Extend { ref label, ref record, .. }
+                if label == "x" && matches!(**record, Expr::Extend { .. })
+        ));
+        assert!(matches!(parse("{}"), Expr::Record(ref fields) if fields.is_empty()));
+
+        let e = parse("class C 'a { m : { name : String | 'r } -> 'a } 1");
+        let Expr::Class { ref methods, .. } = e else {
+            panic!("{e:?} is not a class declaration");
+        };
+        assert_eq!(methods[0].1.to_string(), "{ name : String | 'r } -> 'a");
+    }
+
+    #[test]
+    fn test_field_call() {
+        // A field can be called like any other function.
+        assert!(matches!(
+            parse("r.f(1)"),
+            Expr::Apply { ref func, .. } if matches!(**func, Expr::Select { ref label, .. } if label == "f")
+        ));
+    }
+
+    #[test]
+    fn test_effects() {
+        let program = "effect Yield : Int -> Bool
+            handle perform Yield(1) with | Yield(x, k) -> k(true) | return b -> 0";
+        let e = parse(program);
+        let Expr::Effect {
+            ref typ, ref body, ..
+        } = e
+        else {
+            panic!("{e:?} is not an effect declaration");
+        };
+        assert_eq!(typ.to_string(), "Int -> Bool");
+        let Expr::Handle {
+            ref body,
+            ref clauses,
+            ref ret,
+        } = **body
+        else {
+            panic!("{body:?} is not a handler");
+        };
+        assert!(matches!(**body, Expr::Perform { ref op, .. } if op == "Yield"));
+        assert_eq!(clauses.len(), 1);
+        assert_eq!((clauses[0].arg.as_str(), clauses[0].k.as_str()), ("x", "k"));
+        assert_eq!(ret.0, "b");
+
+        // Without a `return` clause, the handler returns the value as it is.
+        let e = parse("handle 1 with | Yield(x, k) -> 2");
+        assert!(matches!(
+            e,
+            Expr::Handle { ref ret, .. } if matches!(*ret.1, Expr::Var(ref x) if *x == ret.0)
+        ));
+        assert!(RainParser::parse_program("handle 1 with").is_err());
+    }
+
+    #[test]
+    fn test_unit() {
+        assert!(matches!(parse("()"), Expr::Unit));
+        assert!(matches!(
+            parse("read_line()"),
+            Expr::Apply { ref func, ref arg }
+                if matches!(**func, Expr::Var(ref f) if f == "read_line")
+                    && matches!(**arg, Expr::Unit)
+        ));
+    }
+
+    #[test]
+    fn test_nesting() {
+        // Nested `let`s are parsed in linear time.
+        let program = "let x = 1 in ".repeat(10) + "x";
+        assert!(RainParser::parse_program(&program).is_ok());
+        assert!(matches!(
+            parse("f(1)(2)"),
+            Expr::Apply { ref func, .. } if matches!(**func, Expr::Apply { .. })
+        ));
+        assert!(matches!(parse("f()(2)"), Expr::Apply { ref func, .. }
+            if matches!(**func, Expr::Apply { ref arg, .. } if matches!(**arg, Expr::Unit))));
+    }
+
+    #[test]
+    fn test_assert_and_test() {
+        let e = parse("let x = 1 in\ntest \"one\\n\" = x == 1 in\n  assert x <= 2");
+        let Expr::Let { ref e2, .. } = e else {
+            panic!("{e:?} is not a let");
+        };
+        let Expr::Test {
+            ref name,
+            pos,
+            ref body,
+            ..
+        } = **e2
+        else {
+            panic!("{e2:?} is not a test");
+        };
+        assert_eq!((name.as_str(), pos), ("one\n", (2, 1)));
+        assert!(matches!(
+            **body,
+            Expr::Assert { ref cond, pos: (3, 3) } if matches!(**cond, Expr::Bin { .. })
+        ));
+        assert!(RainParser::parse_program("test \"${x}\" = true in 1").is_err());
+
+        // Chains of tests and `let`s are parsed in linear time.
+        let program = "let x = 1 in test \"x\" = x == 1 in ".repeat(5) + "x";
+        assert!(RainParser::parse_program(&program).is_ok());
+    }
+
+    #[test]
+    fn test_labeled_arguments() {
+        let e = parse("fn (~width, ?size = 1, x) -> x");
+        let Expr::LabeledFn {
+            ref label,
+            ref default,
+            ref body,
+        } = e
+        else {
+            panic!("{e:?} is not a labeled function");
+        };
+        assert!(label == "width" && default.is_none());
+        assert!(matches!(
+            **body,
+            Expr::LabeledFn { ref label, default: Some(_), ref body }
+                if label == "size" && matches!(**body, Expr::Fn { .. })
+        ));
+        // `~height` passes the variable `height`.
+        assert!(matches!(
+            parse("f(1, ~height)"),
+            Expr::LabeledApply { ref func, ref label, ref arg }
+                if label == "height"
+                    && matches!(**arg, Expr::Var(ref x) if x == "height")
+                    && matches!(**func, Expr::Apply { .. })
+        ));
+    }
+
+    #[test]
+    fn test_patterns() {
+        let (e, warnings) =
+            RainParser::parse_program_with_warnings("fn ({ x, y = _ }) -> x").unwrap();
+        assert!(warnings.is_empty());
+        let Expr::Fn { ref arg, ref body } = e else {
+            panic!("{e:?} is not a function");
+        };
+        // The parameter is taken apart field by field.
+        assert!(matches!(
+            **body,
+            Expr::Let { ref e1, .. }
+                if matches!(**e1, Expr::Select { ref record, ref label }
+                    if label == "x" && matches!(**record, Expr::Var(ref x) if x == arg))
+        ));
+        let (e, warnings) =
+            RainParser::parse_program_with_warnings("let x = 1 in\nlet 1 = x in x").unwrap();
+        assert!(matches!(
+            warnings[..],
+            [Warning::RefutablePattern { pos: (2, 5) }]
+        ));
+        let Expr::Let { ref e2, .. } = e else {
+            panic!("{e:?} is not a let");
+        };
+        assert!(matches!(
+            **e2,
+            Expr::Let { ref e2, .. }
+                if matches!(**e2, Expr::If { ref e2, .. }
+                    if matches!(**e2, Expr::MatchFailure { pos: (2, 5) }))
+        ));
+    }
+
+    #[test]
+    fn test_binding_ops() {
+        let e = parse("let* x = m in\n  let+ { y } = n(x) in y");
+        let Expr::BindingOp {
+            ref op, pos, ref f, ..
+        } = e
+        else {
+            panic!("{e:?} is not a binding operator");
+        };
+        assert_eq!((op.as_str(), pos), ("let*", (1, 1)));
+        assert!(matches!(
+            **f,
+            Expr::Fn { ref arg, ref body }
+                if arg == "x" && matches!(**body, Expr::BindingOp { ref op, pos: (2, 3), .. } if op == "let+")
+        ));
+        // `let` followed by an operator is not a binding operator.
+        assert!(matches!(parse("let x = 1 in x"), Expr::Let { .. }));
+    }
+
+    #[test]
+    fn test_collection_types() {
+        let e = parse("class C 'a { m : (String, 'a array) map -> 'a lazy array } 1");
+        let Expr::Class { ref methods, .. } = e else {
+            panic!("{e:?} is not a class declaration");
+        };
+        assert_eq!(
+            methods[0].1.to_string(),
+            "(String, 'a array) map -> 'a lazy array"
+        );
+        assert!(RainParser::parse_program("class C 'a { m : (Int, 'a) } 1").is_err());
+        assert!(RainParser::parse_program("class C 'a { m : Int map } 1").is_err());
+        // `map` is not a keyword.
+        assert!(matches!(parse("map"), Expr::Var(ref x) if x == "map"));
+    }
+
+    #[test]
+    fn test_pipelines() {
+        // (x |> (f >> g)) |> h
+        let e = parse("x |> f >> g |> h");
+        assert!(matches!(
+            e,
+            Expr::Bin { op: BinOp::Pipe, ref e1, .. } if matches!(
+                **e1,
+                Expr::Bin { op: BinOp::Pipe, ref e2, .. }
+                    if matches!(**e2, Expr::Bin { op: BinOp::Compose, .. })
+            )
+        ));
+        // f <| (g << h <| x)
+        let e = parse("f <| g << h <| x");
+        assert!(matches!(
+            e,
+            Expr::Bin { op: BinOp::PipeBack, ref e2, .. }
+                if matches!(**e2, Expr::Bin { op: BinOp::PipeBack, .. })
+        ));
+
+        let e = RainParser::parse_program("x |> f <| y");
+        assert!(matches!(e, Err(ParseError::AmbiguousOperators { .. })));
+        let e = RainParser::parse_program("let (|>) = fn (x, f) -> f(x) in 1");
+        assert!(matches!(e, Err(ParseError::BuiltinOperator { .. })));
+    }
+
+    #[test]
+    fn test_ascription() {
+        let e = parse("(x : Dyn -> Int)");
+        assert!(matches!(
+            e,
+            Expr::Ascribe { ref expr, typ: MonoType::Fn(ref arg, _) }
+                if matches!(**expr, Expr::Var(_)) && *arg == Box::new(MonoType::Dyn((1, 6)))
+        ));
+        // Still a section: `fn ($rhs) -> x + $rhs`.
+        let e = parse("(x +)");
+        let Expr::Fn { ref arg, ref body } = e else {
+            panic!("not a section: {e:?}")
+        };
+        assert_eq!(arg, "$rhs");
+        assert!(matches!(
+            **body,
+            Expr::Bin { op: BinOp::Plus, ref e1, ref e2 }
+                if matches!(**e1, Expr::Var(ref x) if x == "x")
+                    && matches!(**e2, Expr::Var(ref x) if x == "$rhs")
+        ));
+        assert!(RainParser::parse_program("(x : dyn)").is_err());
+    }
+
+    #[test]
+    fn test_measures() {
+        let measure = |units: &[(&str, i32)]| {
+            MonoType::Measure(units.iter().map(|(x, e)| (x.to_string(), *e)).collect())
+        };
+        let e = parse("9<m/s^2>");
+        assert!(matches!(
+            e,
+            Expr::Measured { ref expr, .. }
+                if matches!(**expr, Expr::Literal { ref spelling, .. } if spelling == "9")
+        ));
+        if let Expr::Measured { measure: m, .. } = e {
+            assert_eq!(m, measure(&[("m", 1), ("s", -2)]));
+        }
+        // Units cancel out, and `Int<1>` is `Int`.
+        let e = parse("(x : Int<'u*m/m> -> Int<1>)");
+        assert!(matches!(
+            e,
+            Expr::Ascribe { typ: MonoType::Fn(ref arg, ref result), .. }
+                if **arg == MonoType::Measured(Box::new(MonoType::Int), Box::new(measure(&[("'u", 1)])))
+                    && **result == MonoType::Int
+        ));
+        assert!(RainParser::parse_program("5<'u>").is_err());
+        assert!(RainParser::parse_program("(x : Bool<m>)").is_err());
+        // Exponents can always be negated, for `1/m^...`.
+        assert!(RainParser::parse_program("1<m^-2147483648>").is_err());
+        assert!(RainParser::parse_program("1<m^2147483647*m>").is_err());
+        assert!(matches!(
+            parse("(x : Int<m^-2147483647>)"),
+            Expr::Ascribe { ref typ, .. } if typ.to_string() == "Int<1/m^2147483647>"
+        ));
+    }
+
+    #[test]
+    fn test_task_types() {
+        let e = parse("(f : Int task -> String chan array)");
+        let task = MonoType::Task(Box::new(MonoType::Int));
+        let chans = MonoType::Array(Box::new(MonoType::Chan(Box::new(MonoType::String))));
+        assert!(matches!(
+            e,
+            Expr::Ascribe { typ: MonoType::Fn(ref arg, ref result), .. }
+                if **arg == task && **result == chans
+        ));
+        // `chan` is still the name of a primitive.
+        assert!(matches!(parse("chan(())"), Expr::Apply { .. }));
     }
 }
@@ -0,0 +1,155 @@
+use crate::ast::Expr;
+use crate::interpret::{InterpretError, Interpreter};
+use crate::parser::{ParseError, RainParser};
+use crate::typecheck::{Type, TypeChecker, TypeError};
+
+/// The source of the prelude, a chain of `let` bindings.
+const SOURCE: &str = include_str!("prelude.rain");
+
+/// The standard prelude: `id`, `const`, `compose`, `flip`, `fold`, `map`
+/// and `filter` on arrays, and options encoded as functions, `some(x)` and
+/// `none`, with `option`, `get_or`, `is_some`, `option_map` and
+/// `option_bind`. It is loaded once, and then handed to both the
+/// `TypeChecker` and the `Interpreter`.
+#[derive(Clone, Debug, Default)]
+pub struct Prelude {
+    types: Vec<(String, Type)>,
+    values: Vec<(String, Expr)>,
+}
+
+#[derive(Debug)]
+pub enum PreludeError {
+    Parse(ParseError),
+    /// A binding of the prelude, by name, that does not type check.
+    Type(String, Box<TypeError>),
+    Interpret(String, Box<InterpretError>),
+}
+
+impl Prelude {
+    /// No bindings at all, as with `--no-prelude`.
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Parse, type check and evaluate the prelude, one binding at a time.
+    pub fn load() -> Result<Self, PreludeError> {
+        let expr = RainParser::parse_program(SOURCE).map_err(PreludeError::Parse)?;
+        let mut type_checker = TypeChecker::new();
+        let mut interpreter = Interpreter::new();
+        let mut prelude = Self::empty();
+        let mut expr = &expr;
+        while let Expr::Let { x, e1, e2 } = expr {
+            let (typ, e1) = type_checker
+                .define(x, e1)
+                .map_err(|e| PreludeError::Type(x.clone(), Box::new(e)))?;
+            let value = interpreter
+                .define(x, &e1)
+                .map_err(|e| PreludeError::Interpret(x.clone(), Box::new(e)))?;
+            prelude.types.push((x.clone(), typ));
+            prelude.values.push((x.clone(), value));
+            expr = e2;
+        }
+        Ok(prelude)
+    }
+
+    pub fn types(&self) -> &[(String, Type)] {
+        &self.types
+    }
+
+    pub fn values(&self) -> &[(String, Expr)] {
+        &self.values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Prelude;
+    use crate::interpret::Interpreter;
+    use crate::parser::RainParser;
+    use crate::typecheck::TypeChecker;
+
+    #[test]
+    fn test_prelude() {
+        let prelude = Prelude::load().unwrap();
+        let run = |program: &str| {
+            let expr = RainParser::parse_program(program).unwrap();
+            let (typ, expr) = TypeChecker::new()
+                .with_prelude(&prelude)
+                .elaborate(&expr)
+                .unwrap();
+            let value = Interpreter::new().with_prelude(&prelude).eval(&expr);
+            (typ.to_string(), value.unwrap().to_string())
+        };
+        let squares = "let a = array_init(5, fn (i) -> i * i) in ";
+        assert_eq!(
+            run(&format!(
+                "{squares}filter(fn (x) -> 4 <= x, map(fn (x) -> x + 1, a))"
+            )),
+            ("Int array".into(), "[|5, 10, 17|]".into())
+        );
+        assert_eq!(
+            run(&format!(
+                "{squares}fold(flip(fn (x, acc) -> x + acc), 0, a)"
+            ))
+            .1,
+            "30"
+        );
+        // Operations performed by the functions passed to `map`, `fold` and
+        // `filter` resume where they were performed.
+        assert_eq!(
+            run(&format!(
+                "effect Ask : Int -> Int {squares}handle \
+                {{ m = map(fn (x) -> perform Ask(x), a), \
+                s = fold(fn (acc, x) -> acc + perform Ask(x), 0, a), \
+                f = filter(fn (x) -> 50 <= perform Ask(x), a) }} with \
+                | Ask(x, k) -> k(x * 10)"
+            )),
+            (
+                "{ m : Int array, s : Int, f : Int array }".into(),
+                "{ m = [|0, 10, 40, 90, 160|], s = 300, f = [|9, 16|] }".into()
+            )
+        );
+        assert_eq!(
+            run(
+                "{ a = get_or(0, option_map((+ 1), some(1))), b = is_some(none), \
+                c = option(\"none\", show, option_bind(fn (x) -> none, some(2))) }"
+            ),
+            (
+                "{ a : Int, b : Bool, c : String }".into(),
+                "{ a = 2, b = false, c = \"none\" }".into()
+            )
+        );
+        // `let+` and `let*` call whichever `map` and `bind` are in scope,
+        // which take the function first like the prelude's.
+        assert_eq!(
+            run(&format!(
+                "{squares}let bind = option_bind in \
+                {{ a = let+ x = a in x + 1, \
+                b = get_or(0, let* x = some(1) in let* y = some(x + 1) in some(x * y)) }}"
+            )),
+            (
+                "{ a : Int array, b : Int }".into(),
+                "{ a = [|1, 2, 5, 10, 17|], b = 2 }".into()
+            )
+        );
+        // A program can shadow the prelude's bindings, and the prelude's
+        // own references to them are left alone.
+        assert_eq!(
+            run(&format!(
+                "{squares}let fold = 1, map = 2 in filter(fn (x) -> x == 16, a)"
+            ))
+            .1,
+            "[|16|]"
+        );
+        // A function that calls the prelude's `id` keeps calling it after a
+        // later `let` shadows `id`.
+        assert_eq!(
+            run("let g = fn (u) -> id(u) in let id = 5 in g(3)"),
+            ("Int".into(), "3".into())
+        );
+
+        // Without the prelude, its bindings are unbound.
+        let expr = RainParser::parse_program("compose").unwrap();
+        assert!(TypeChecker::new().type_check(&expr).is_err());
+    }
+}
@@ -1,40 +1,118 @@
-use crate::ast::{BinOp, Expr};
+use crate::{
+    ast::{BinOp, Expr, OpClause, Prim, Thunk, VariableNameGenerator},
+    prelude::Prelude,
+    types::{Label, MonoType},
+};
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     fmt::Display,
 };
 
+impl MonoType {
+    /// `base<measure>`, which is `base` itself when `measure` is `1`.
+    pub(crate) fn measured(base: MonoType, measure: MonoType) -> Result<MonoType, TypeError> {
+        match (base, measure) {
+            (base, MonoType::Measure(m)) if m.is_empty() => Ok(base),
+            (MonoType::Measured(base, m1), m2) => {
+                MonoType::measured(*base, MonoType::measure_product(&m1, &m2, 1)?)
+            }
+            (base, measure) => Ok(MonoType::Measured(Box::new(base), Box::new(measure))),
+        }
+    }
+
+    /// `m1 * m2^n`. Exponents stay within `±i32::MAX`, so that they can
+    /// always be negated.
+    pub(crate) fn measure_product(
+        m1: &MonoType,
+        m2: &MonoType,
+        n: i32,
+    ) -> Result<MonoType, TypeError> {
+        let mut product = match m1 {
+            MonoType::Measure(m1) => m1.clone(),
+            _ => BTreeMap::new(),
+        };
+        if let MonoType::Measure(measure) = m2 {
+            for (x, e) in measure {
+                let exponent = product.entry(x.clone()).or_default();
+                *exponent = e
+                    .checked_mul(n)
+                    .and_then(|e| e.checked_add(*exponent))
+                    .filter(|e| *e != i32::MIN)
+                    .ok_or_else(|| TypeError::ExponentOverflow(m1.clone(), m2.clone()))?;
+            }
+        }
+        product.retain(|_, e| *e != 0);
+        Ok(MonoType::Measure(product))
+    }
+
+    /// The unit variables in the measures of the type.
+    fn unit_vars(&self) -> HashSet<String> {
+        match self {
+            MonoType::Measure(measure) => measure
+                .keys()
+                .filter(|x| x.starts_with('\''))
+                .cloned()
+                .collect(),
+            MonoType::Lazy(t)
+            | MonoType::Array(t)
+            | MonoType::Task(t)
+            | MonoType::Chan(t)
+            | MonoType::Record(t) => t.unit_vars(),
+            MonoType::Map(t1, t2)
+            | MonoType::Fn(t1, t2)
+            | MonoType::Labeled(_, t1, t2)
+            | MonoType::RowExtend(_, t1, t2)
+            | MonoType::Measured(t1, t2) => &t1.unit_vars() | &t2.unit_vars(),
+            _ => HashSet::new(),
+        }
+    }
+
+    fn contains_dyn(&self) -> bool {
+        match self {
+            MonoType::Dyn(_) => true,
+            MonoType::Lazy(t)
+            | MonoType::Array(t)
+            | MonoType::Task(t)
+            | MonoType::Chan(t)
+            | MonoType::Record(t) => t.contains_dyn(),
+            MonoType::Map(t1, t2)
+            | MonoType::Fn(t1, t2)
+            | MonoType::Labeled(_, t1, t2)
+            | MonoType::RowExtend(_, t1, t2) => t1.contains_dyn() || t2.contains_dyn(),
+            MonoType::Unit
+            | MonoType::Int
+            | MonoType::Bool
+            | MonoType::String
+            | MonoType::Fixed(_)
+            | MonoType::TypeVariable(_)
+            | MonoType::RowEmpty
+            | MonoType::Measure(_) => false,
+            MonoType::Measured(t, _) => t.contains_dyn(),
+        }
+    }
+}
+
+/// A type class constraint such as `Show 'a`, i.e. `'a` must be a type that
+/// can be converted to a string.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
-pub enum MonoType {
-    Int,
-    Bool,
-    Fn(Box<MonoType>, Box<MonoType>), // t1 -> t2
-    TypeVariable(String),             // '1, '2, '3...
+pub struct Predicate {
+    pub class: String,
+    pub typ: MonoType,
 }
 
-impl Display for MonoType {
+impl Display for Predicate {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        fn helper(f: &mut std::fmt::Formatter<'_>, monotype: &MonoType) -> std::fmt::Result {
-            let monotype = monotype.clone();
-            match monotype {
-                MonoType::Int => write!(f, "Int"),
-                MonoType::Bool => write!(f, "Bool"),
-                MonoType::TypeVariable(x) => write!(f, "{x}"),
-                MonoType::Fn(i, o) => {
-                    helper(f, &i)?;
-                    write!(f, " -> ")?;
-                    helper(f, &o)
-                }
-            }
+        match self.typ {
+            MonoType::Fn(_, _) => write!(f, "{} ({})", self.class, self.typ),
+            _ => write!(f, "{} {}", self.class, self.typ),
         }
-
-        helper(f, self)
     }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct PolyType {
     bounded_type_vars: Vec<String>,
+    predicates: Vec<Predicate>,
     typ: MonoType,
 }
 
@@ -46,8 +124,10 @@ impl Display for PolyType {
                 write!(f, "{bounded} ")?;
             }
             write!(f, ". ")?;
-            println!("{}", polytype.typ);
-            Ok(())
+            for predicate in polytype.predicates {
+                write!(f, "{predicate} => ")?;
+            }
+            write!(f, "{}", polytype.typ)
         }
 
         helper(f, self)
@@ -67,6 +147,59 @@ pub enum TypeError {
     IfBranchError(MonoType, MonoType),
     BinOpError,
     UnsolvableConstraints(MonoType, MonoType),
+    MissingInstance(Predicate),
+    /// A predicate of a declared class on a type the program never
+    /// determines, e.g. that of `empty` in `show(empty)`, so that no
+    /// dictionary can be chosen for it.
+    AmbiguousPredicate(Predicate),
+    UnknownClass(String),
+    /// Built-in classes can be neither redeclared nor given new instances.
+    BuiltinClass(String),
+    MissingMethod {
+        class: String,
+        method: String,
+    },
+    UnknownMethod {
+        class: String,
+        method: String,
+    },
+    OverlappingInstances(Predicate),
+    /// An operation performed or handled without an `effect` declaration.
+    UnknownEffect(String),
+    /// An argument labeled with a label the function has no parameter for.
+    UnknownLabel(String),
+    /// A unit of measure whose exponent does not fit in an `i32`, e.g. that
+    /// of `1<m^2147483647> * 1<m>`.
+    ExponentOverflow(MonoType, MonoType),
+    /// A `let*` or `let+` at `pos` whose `bind` or `map` cannot be called
+    /// as it desugars to.
+    BindingOp {
+        op: String,
+        pos: (usize, usize),
+        error: Box<TypeError>,
+    },
+}
+
+/// Classes whose instances are built into the interpreter, which picks the
+/// right implementation from the values themselves. Their predicates are
+/// checked like those of declared classes, but they are not elaborated into
+/// dictionaries: `==` stays a primitive on whatever values reach it, which
+/// is also what lets it work on `Dyn` values.
+const BUILTIN_CLASSES: [&str; 5] = ["Show", "Eq", "Ord", "Num", "Bits"];
+
+/// A class declared by the program.
+#[derive(Clone, Debug)]
+struct Class {
+    var: String,
+    methods: Vec<(String, MonoType)>,
+}
+
+/// An instance declared by the program, and the variable its dictionary is
+/// bound to.
+#[derive(Clone, Debug)]
+struct Instance {
+    head: Predicate,
+    dict: String,
 }
 
 #[derive(Debug)]
@@ -93,66 +226,902 @@ type Substitutions = VecDeque<(String, MonoType)>;
 pub struct TypeChecker {
     /// Type variable name generator.
     type_var_name_generator: TypeVariableNameGenerator,
+    /// Names of the parameters the checker makes up when it elaborates
+    /// operators and partial applications into functions.
+    var_name_generator: VariableNameGenerator,
+    /// Predicates that are checked once the final substitutions are known.
+    /// Those of declared classes come with the placeholder that stands for
+    /// their dictionary until it is chosen.
+    predicates: Vec<(Predicate, Option<String>)>,
+    classes: HashMap<String, Class>,
+    instances: Vec<Instance>,
+    /// Counter for the names of dictionaries and their placeholders.
+    dict_counter: u32,
+    /// The generalized types of the prelude's bindings, in order.
+    prelude: Vec<(String, Type)>,
+    /// The type variables that stand for a `Dyn` where it is used.
+    dyn_vars: Vec<String>,
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TypeChecker {
     pub fn new() -> Self {
         Self {
             type_var_name_generator: TypeVariableNameGenerator::new(),
+            var_name_generator: VariableNameGenerator::new("$arg"),
+            predicates: vec![],
+            classes: HashMap::new(),
+            instances: vec![],
+            dict_counter: 0,
+            prelude: vec![],
+            dyn_vars: vec![],
+        }
+    }
+
+    /// Start type checking with the bindings of `prelude` in scope.
+    pub fn with_prelude(mut self, prelude: &Prelude) -> Self {
+        self.prelude = prelude.types().to_vec();
+        self
+    }
+
+    fn fresh_dict_name(&mut self, prefix: &str) -> String {
+        self.dict_counter += 1;
+        format!("{prefix}{}", self.dict_counter)
+    }
+
+    /// Type schemes of the built-in binary operators. Arithmetic works on
+    /// every integer type, but both operands must have the same one.
+    fn operator_scheme(op: BinOp) -> PolyType {
+        let a = MonoType::TypeVariable("'a".into());
+        let (b, c) = (
+            MonoType::TypeVariable("'b".into()),
+            MonoType::TypeVariable("'c".into()),
+        );
+        let fun =
+            |i: &MonoType, o: &MonoType| MonoType::Fn(Box::new(i.clone()), Box::new(o.clone()));
+        let (class, result) = match op {
+            BinOp::Pipe | BinOp::PipeBack | BinOp::Compose | BinOp::ComposeBack => {
+                let typ = match op {
+                    BinOp::Pipe => fun(&a, &fun(&fun(&a, &b), &b)),
+                    BinOp::PipeBack => fun(&fun(&a, &b), &fun(&a, &b)),
+                    BinOp::Compose => fun(&fun(&a, &b), &fun(&fun(&b, &c), &fun(&a, &c))),
+                    _ => fun(&fun(&b, &c), &fun(&fun(&a, &b), &fun(&a, &c))),
+                };
+                return PolyType {
+                    bounded_type_vars: vec!["'a".into(), "'b".into(), "'c".into()],
+                    predicates: vec![],
+                    typ,
+                };
+            }
+            // `*` multiplies the units of its operands:
+            // `Num 'a => 'a<'u> -> 'a<'v> -> 'a<'u*'v>`.
+            BinOp::Times => {
+                let measure = |units: &[&str]| {
+                    MonoType::Measure(units.iter().map(|u| (u.to_string(), 1)).collect())
+                };
+                let measured = |units: &[&str]| {
+                    MonoType::Measured(Box::new(a.clone()), Box::new(measure(units)))
+                };
+                return PolyType {
+                    bounded_type_vars: vec!["'a".into(), "'u".into(), "'v".into()],
+                    predicates: vec![Predicate {
+                        class: "Num".into(),
+                        typ: a.clone(),
+                    }],
+                    typ: fun(
+                        &measured(&["'u"]),
+                        &fun(&measured(&["'v"]), &measured(&["'u", "'v"])),
+                    ),
+                };
+            }
+            BinOp::Plus => ("Num", a.clone()),
+            BinOp::Le => ("Ord", MonoType::Bool),
+            BinOp::Eq => ("Eq", MonoType::Bool),
+            BinOp::Land | BinOp::Lor | BinOp::Lxor | BinOp::Lsl | BinOp::Lsr => ("Bits", a.clone()),
+            BinOp::Concat => {
+                return PolyType {
+                    bounded_type_vars: vec![],
+                    predicates: vec![],
+                    typ: MonoType::Fn(
+                        Box::new(MonoType::String),
+                        Box::new(MonoType::Fn(
+                            Box::new(MonoType::String),
+                            Box::new(MonoType::String),
+                        )),
+                    ),
+                }
+            }
+        };
+        PolyType {
+            bounded_type_vars: vec!["'a".into()],
+            predicates: vec![Predicate {
+                class: class.into(),
+                typ: a.clone(),
+            }],
+            typ: MonoType::Fn(
+                Box::new(a.clone()),
+                Box::new(MonoType::Fn(Box::new(a), Box::new(result))),
+            ),
+        }
+    }
+
+    /// `e1 op e2` in terms of functions for the operators that are only
+    /// sugar for them. Both operands of a composition are evaluated first,
+    /// like those of any operator.
+    fn desugar_bin(&mut self, op: BinOp, e1: Expr, e2: Expr) -> Expr {
+        let apply = |func: Expr, arg: Expr| Expr::Apply {
+            func: Box::new(func),
+            arg: Box::new(arg),
+        };
+        let [x1, x2, x] = [(); 3].map(|_| self.var_name_generator.next());
+        let (v1, v2) = (Expr::Var(x1.clone()), Expr::Var(x2.clone()));
+        let body = match op {
+            // `x |> f` evaluates `x` first, as it reads.
+            BinOp::Pipe => {
+                return Expr::Let {
+                    x: x1,
+                    e1: Box::new(e1),
+                    e2: Box::new(apply(e2, v1)),
+                }
+            }
+            BinOp::PipeBack => return apply(e1, e2),
+            BinOp::Compose => apply(v2, apply(v1, Expr::Var(x.clone()))),
+            BinOp::ComposeBack => apply(v1, apply(v2, Expr::Var(x.clone()))),
+            _ => {
+                return Expr::Bin {
+                    op,
+                    e1: Box::new(e1),
+                    e2: Box::new(e2),
+                }
+            }
+        };
+        Expr::Let {
+            x: x1,
+            e1: Box::new(e1),
+            e2: Box::new(Expr::Let {
+                x: x2,
+                e1: Box::new(e2),
+                e2: Box::new(Expr::Fn {
+                    arg: x,
+                    body: Box::new(body),
+                }),
+            }),
+        }
+    }
+
+    /// Type schemes of the primitives.
+    fn prim_scheme(prim: Prim) -> PolyType {
+        let a = MonoType::TypeVariable("'a".into());
+        let fun = |i, o| MonoType::Fn(Box::new(i), Box::new(o));
+        let mono = |typ| PolyType {
+            bounded_type_vars: vec![],
+            predicates: vec![],
+            typ,
+        };
+        let (k, v) = (
+            MonoType::TypeVariable("'k".into()),
+            MonoType::TypeVariable("'v".into()),
+        );
+        let array = |t: &MonoType| MonoType::Array(Box::new(t.clone()));
+        let map = MonoType::Map(Box::new(k.clone()), Box::new(v.clone()));
+        let array_scheme = |typ| PolyType {
+            bounded_type_vars: vec!["'a".into()],
+            predicates: vec![],
+            typ,
+        };
+        // Keys are ordered consistently with `==`.
+        let map_scheme = |ordered: bool, typ| PolyType {
+            bounded_type_vars: vec!["'k".into(), "'v".into()],
+            predicates: match ordered {
+                true => vec![Predicate {
+                    class: "Eq".into(),
+                    typ: k.clone(),
+                }],
+                false => vec![],
+            },
+            typ,
+        };
+        let (class, result) = match prim {
+            Prim::ArrayInit => {
+                let f = fun(MonoType::Int, a.clone());
+                return array_scheme(fun(MonoType::Int, fun(f, array(&a))));
+            }
+            Prim::ArrayGet => return array_scheme(fun(array(&a), fun(MonoType::Int, a.clone()))),
+            Prim::ArraySet => {
+                let set = fun(MonoType::Int, fun(a.clone(), array(&a)));
+                return array_scheme(fun(array(&a), set));
+            }
+            Prim::ArrayLength => return array_scheme(fun(array(&a), MonoType::Int)),
+            Prim::ArrayFold => {
+                let b = MonoType::TypeVariable("'b".into());
+                let f = fun(b.clone(), fun(a.clone(), b.clone()));
+                return PolyType {
+                    bounded_type_vars: vec!["'a".into(), "'b".into()],
+                    predicates: vec![],
+                    typ: fun(f, fun(b.clone(), fun(array(&a), b))),
+                };
+            }
+            Prim::MapEmpty => return map_scheme(false, fun(MonoType::Unit, map)),
+            Prim::MapGet => return map_scheme(true, fun(map.clone(), fun(k.clone(), v.clone()))),
+            Prim::MapSet => {
+                let set = fun(k.clone(), fun(v.clone(), map.clone()));
+                return map_scheme(true, fun(map.clone(), set));
+            }
+            Prim::MapRemove => return map_scheme(true, fun(map.clone(), fun(k.clone(), map))),
+            Prim::MapHas => return map_scheme(true, fun(map, fun(k.clone(), MonoType::Bool))),
+            Prim::MapSize => return map_scheme(false, fun(map, MonoType::Int)),
+            Prim::MapKeys => return map_scheme(false, fun(map, array(&k))),
+            Prim::Print => return mono(fun(MonoType::String, MonoType::Unit)),
+            Prim::ReadLine => return mono(fun(MonoType::Unit, MonoType::String)),
+            Prim::ReadFile => return mono(fun(MonoType::String, MonoType::String)),
+            Prim::WriteFile => {
+                return mono(fun(MonoType::String, fun(MonoType::String, MonoType::Unit)))
+            }
+            Prim::Show => ("Show", MonoType::String),
+            Prim::ToInt => ("Num", MonoType::Int),
+            Prim::ToFixed(width) => ("Num", MonoType::Fixed(width)),
+            Prim::Force => {
+                return PolyType {
+                    bounded_type_vars: vec!["'a".into()],
+                    predicates: vec![],
+                    typ: MonoType::Fn(Box::new(MonoType::Lazy(Box::new(a.clone()))), Box::new(a)),
+                }
+            }
+            Prim::Spawn => {
+                let task = MonoType::Task(Box::new(a.clone()));
+                return array_scheme(fun(fun(MonoType::Unit, a), task));
+            }
+            Prim::Await => return array_scheme(fun(MonoType::Task(Box::new(a.clone())), a)),
+            Prim::Chan => return array_scheme(fun(MonoType::Unit, MonoType::Chan(Box::new(a)))),
+            Prim::Send => {
+                let chan = MonoType::Chan(Box::new(a.clone()));
+                return array_scheme(fun(chan, fun(a, MonoType::Unit)));
+            }
+            Prim::Recv => return array_scheme(fun(MonoType::Chan(Box::new(a.clone())), a)),
+            Prim::Memoize => {
+                let lazy = MonoType::Lazy(Box::new(a.clone()));
+                return array_scheme(fun(lazy, fun(a.clone(), a)));
+            }
+            Prim::ArrayInitFrom => {
+                let f = fun(MonoType::Int, a.clone());
+                let rest = fun(array(&a), fun(a.clone(), array(&a)));
+                return array_scheme(fun(MonoType::Int, fun(f, rest)));
+            }
+            Prim::ArrayFoldFrom => {
+                let b = MonoType::TypeVariable("'b".into());
+                let f = fun(b.clone(), fun(a.clone(), b.clone()));
+                let rest = fun(MonoType::Int, fun(b.clone(), b.clone()));
+                return PolyType {
+                    bounded_type_vars: vec!["'a".into(), "'b".into()],
+                    predicates: vec![],
+                    typ: fun(f, fun(array(&a), rest)),
+                };
+            }
+        };
+        PolyType {
+            bounded_type_vars: vec!["'a".into()],
+            predicates: vec![Predicate {
+                class: class.into(),
+                typ: a.clone(),
+            }],
+            typ: MonoType::Fn(Box::new(a), Box::new(result)),
+        }
+    }
+
+    /// Whether the instance `predicate` exists, with the predicates its
+    /// context leaves on type variables taken to hold.
+    fn has_instance(&self, predicate: &Predicate) -> bool {
+        self.instance_context(predicate).is_some_and(|context| {
+            context.iter().all(|predicate| {
+                matches!(predicate.typ, MonoType::TypeVariable(_)) || self.has_instance(predicate)
+            })
+        })
+    }
+
+    /// The predicates the instance `predicate` holds under, if it exists.
+    fn instance_context(&self, predicate: &Predicate) -> Option<Vec<Predicate>> {
+        let on = |typ: &MonoType| Predicate {
+            class: predicate.class.clone(),
+            typ: typ.clone(),
+        };
+        match (predicate.class.as_str(), &predicate.typ) {
+            // Records are shown and compared field by field.
+            ("Show" | "Eq", MonoType::Record(row)) => Some(
+                MonoType::row_fields(row)
+                    .0
+                    .into_iter()
+                    .map(|(_, t)| on(t))
+                    .collect(),
+            ),
+            // So are arrays and maps, element by element.
+            ("Show" | "Eq", MonoType::Array(t)) => Some(vec![on(t)]),
+            ("Show" | "Eq", MonoType::Map(k, v)) => Some(vec![on(k), on(v)]),
+            // A number with a unit is still a number.
+            ("Show" | "Eq" | "Ord" | "Num", MonoType::Measured(t, _)) => Some(vec![on(t)]),
+            // Whatever a `Dyn` turns out to be, it can be shown, and compared
+            // like any other value at runtime.
+            ("Show" | "Eq", typ) => matches!(
+                typ,
+                MonoType::Unit
+                    | MonoType::Dyn(_)
+                    | MonoType::Int
+                    | MonoType::Fixed(_)
+                    | MonoType::Bool
+                    | MonoType::String
+            )
+            .then(Vec::new),
+            ("Num" | "Ord", typ) => {
+                matches!(typ, MonoType::Int | MonoType::Fixed(_)).then(Vec::new)
+            }
+            ("Bits", typ) => matches!(typ, MonoType::Fixed(_)).then(Vec::new),
+            _ => self.find_instance(predicate).map(|_| vec![]),
+        }
+    }
+
+    /// The declared instance `predicate` is an instance of.
+    fn find_instance(&self, predicate: &Predicate) -> Option<&Instance> {
+        self.instances.iter().find(|instance| {
+            instance.head.class == predicate.class
+                && Self::match_type(&instance.head.typ, &predicate.typ, &mut HashMap::new())
+        })
+    }
+
+    /// Whether `typ` is `pattern` with its type variables replaced, as
+    /// recorded in `bindings`.
+    fn match_type(
+        pattern: &MonoType,
+        typ: &MonoType,
+        bindings: &mut HashMap<String, MonoType>,
+    ) -> bool {
+        match (pattern, typ) {
+            (MonoType::TypeVariable(x), _) => match bindings.get(x) {
+                Some(bound) => bound == typ,
+                None => {
+                    bindings.insert(x.clone(), typ.clone());
+                    true
+                }
+            },
+            (MonoType::Lazy(p), MonoType::Lazy(t))
+            | (MonoType::Array(p), MonoType::Array(t))
+            | (MonoType::Task(p), MonoType::Task(t))
+            | (MonoType::Chan(p), MonoType::Chan(t)) => Self::match_type(p, t, bindings),
+            (MonoType::Map(pk, pv), MonoType::Map(tk, tv)) => {
+                Self::match_type(pk, tk, bindings) && Self::match_type(pv, tv, bindings)
+            }
+            (MonoType::Record(p), MonoType::Record(t)) => Self::match_type(p, t, bindings),
+            (MonoType::RowExtend(l1, p, pr), MonoType::RowExtend(l2, t, tr)) if l1 == l2 => {
+                Self::match_type(p, t, bindings) && Self::match_type(pr, tr, bindings)
+            }
+            (MonoType::Fn(pi, po), MonoType::Fn(ti, to)) => {
+                Self::match_type(pi, ti, bindings) && Self::match_type(po, to, bindings)
+            }
+            (MonoType::Labeled(l1, pi, po), MonoType::Labeled(l2, ti, to)) if l1 == l2 => {
+                Self::match_type(pi, ti, bindings) && Self::match_type(po, to, bindings)
+            }
+            _ => pattern == typ,
+        }
+    }
+
+    /// The scheme with its bound type variables renamed to `'a`, `'b`...,
+    /// like those of the built-in schemes, which never collide with the
+    /// fresh ones another checker generates when it instantiates it.
+    fn rename_bounded_type_vars(scheme: &PolyType) -> Result<PolyType, TypeError> {
+        let names: Vec<String> = (0..scheme.bounded_type_vars.len())
+            .map(|i| match i / 26 {
+                0 => format!("'{}", (b'a' + i as u8) as char),
+                n => format!("'{}{n}", (b'a' + (i % 26) as u8) as char),
+            })
+            .collect();
+        let substitutions: Substitutions = scheme
+            .bounded_type_vars
+            .iter()
+            .cloned()
+            .zip(names.iter().cloned().map(MonoType::TypeVariable))
+            .collect();
+        Ok(PolyType {
+            bounded_type_vars: names,
+            predicates: scheme
+                .predicates
+                .iter()
+                .map(|predicate| {
+                    Ok(Predicate {
+                        class: predicate.class.clone(),
+                        typ: Self::apply_substitutions_for_monotype(
+                            &predicate.typ,
+                            &substitutions,
+                        )?,
+                    })
+                })
+                .collect::<Result<_, TypeError>>()?,
+            typ: Self::apply_substitutions_for_monotype(&scheme.typ, &substitutions)?,
+        })
+    }
+
+    /// The environment every program is checked in. Operators are bound by
+    /// their symbols, which can never be shadowed by an identifier.
+    fn initial_environment(&self) -> TypeEnvironment {
+        let operators = BinOp::ALL
+            .iter()
+            .map(|op| (op.to_string(), Type::PolyType(Self::operator_scheme(*op))));
+        let prims = Prim::ALL
+            .iter()
+            .map(|prim| (prim.to_string(), Type::PolyType(Self::prim_scheme(*prim))));
+        operators
+            .chain(prims)
+            .chain(self.prelude.iter().cloned())
+            .collect()
+    }
+
+    /// Type check the prelude's binding of `x` to `e1` with those before
+    /// it in scope, and bring it into scope for those after it. Returns its
+    /// type scheme and elaborated expression.
+    pub(crate) fn define(&mut self, x: &str, e1: &Expr) -> Result<(Type, Expr), TypeError> {
+        self.predicates.clear();
+        self.dyn_vars.clear();
+        let env = self.initial_environment();
+        let generalizable = !Self::may_create_channel(e1);
+        let (t1, c1, e1) = self.build_constraints(&env, e1)?;
+        let (mut new_env, _) =
+            self.generalize(&c1, &env, (&x.to_string(), &t1), generalizable, 0)?;
+        // Predicates that were not generalized are on concrete types.
+        let substitutions = self.unify(&c1)?;
+        self.reduce_predicates(&substitutions)?;
+        let dicts = self.check_predicates(&substitutions)?;
+        let e1 = Self::resolve_placeholders(&e1, &dicts, &substitutions)?;
+        let typ = match new_env.remove(x).unwrap() {
+            Type::PolyType(scheme) => Type::PolyType(Self::rename_bounded_type_vars(&scheme)?),
+            typ => typ,
+        };
+        self.prelude.push((x.into(), typ.clone()));
+        Ok((typ, e1))
+    }
+
+    /// Where the signature of the effect `name` is bound in the environment,
+    /// which no identifier can shadow.
+    fn effect_key(name: &str) -> String {
+        format!("effect {name}")
+    }
+
+    /// The argument and result types of the effect `op`.
+    fn effect_signature(
+        env: &TypeEnvironment,
+        op: &str,
+    ) -> Result<(MonoType, MonoType), TypeError> {
+        match env.get(&Self::effect_key(op)) {
+            Some(Type::MonoType(MonoType::Fn(arg, result))) => Ok((*arg.clone(), *result.clone())),
+            _ => Err(TypeError::UnknownEffect(op.into())),
         }
     }
 
     pub fn type_check(&mut self, expr: &Expr) -> Result<MonoType, TypeError> {
-        let (t, c) = self.build_constraints(&HashMap::new(), expr)?;
+        self.elaborate(expr).map(|(typ, _)| typ)
+    }
+
+    /// Type check `expr`, and translate it into an expression the
+    /// interpreter can run: classes and instances become dictionaries, and
+    /// overloaded functions take the dictionaries they need as arguments.
+    pub fn elaborate(&mut self, expr: &Expr) -> Result<(MonoType, Expr), TypeError> {
+        self.predicates.clear();
+        self.dyn_vars.clear();
+        self.classes.clear();
+        self.instances.clear();
+        let (t, c, expr) = self.build_constraints(&self.initial_environment(), expr)?;
         // println!("t = {t}, c = {c:?}");
-        let substitutions = self.unify(&c)?;
+        let mut substitutions = self.unify(&c)?;
         // println!("substitutions = {substitutions:?}");
-        Self::apply_substitutions_for_monotype(&t, &substitutions)
+        self.reduce_predicates(&substitutions)?;
+        substitutions.extend(self.defaults(&substitutions)?);
+        let dicts = self.check_predicates(&substitutions)?;
+        let t = Self::apply_substitutions_for_monotype(&t, &substitutions)?;
+        let t = Self::default_units(&t)?;
+        let expr = Self::resolve_placeholders(&expr, &dicts, &substitutions)?;
+        Ok((t, expr))
     }
 
+    /// `typ` with the unit variables that the checker made up, rather than
+    /// the program named, taken to be `1`. Units are generalized like types,
+    /// so this is only done for the type of the whole program: as in F#,
+    /// `fn (x, y) -> x * y` is shown as `Int -> Int -> Int`.
+    fn default_units(typ: &MonoType) -> Result<MonoType, TypeError> {
+        let substitutions = typ
+            .unit_vars()
+            .into_iter()
+            .filter(|x| x[1..].chars().all(|c| c.is_ascii_digit()))
+            .map(|x| (x, MonoType::Measure(BTreeMap::new())))
+            .collect();
+        Self::apply_substitutions_for_monotype(typ, &substitutions)
+    }
+
+    /// Reduce every predicate of a built-in class to the predicates its
+    /// instance holds under, until only those on type variables are left,
+    /// e.g. `Show { x : 'a }` to `Show 'a`, so that defaulting and checking
+    /// see what the fields need.
+    fn reduce_predicates(&mut self, substitutions: &Substitutions) -> Result<(), TypeError> {
+        let mut pending = VecDeque::from(std::mem::take(&mut self.predicates));
+        while let Some((predicate, placeholder)) = pending.pop_front() {
+            let predicate = Predicate {
+                class: predicate.class,
+                typ: Self::apply_substitutions_for_monotype(&predicate.typ, substitutions)?,
+            };
+            if placeholder.is_some() || matches!(predicate.typ, MonoType::TypeVariable(_)) {
+                self.predicates.push((predicate, placeholder));
+                continue;
+            }
+            match self.instance_context(&predicate) {
+                Some(context) => pending.extend(context.into_iter().map(|p| (p, None))),
+                None => return Err(TypeError::MissingInstance(predicate)),
+            }
+        }
+        Ok(())
+    }
+
+    /// A type variable that is only known to be a number, as in
+    /// `fn (x, y) -> x + y`, defaults to `Int` when `Int` satisfies all of
+    /// its predicates.
+    fn defaults(&self, substitutions: &Substitutions) -> Result<Substitutions, TypeError> {
+        let mut classes: HashMap<String, Vec<String>> = HashMap::new();
+        for (predicate, _) in self.predicates.iter() {
+            if let MonoType::TypeVariable(x) =
+                Self::apply_substitutions_for_monotype(&predicate.typ, substitutions)?
+            {
+                classes.entry(x).or_default().push(predicate.class.clone());
+            }
+        }
+        Ok(classes
+            .into_iter()
+            .filter(|(_, classes)| {
+                classes.iter().any(|class| class == "Num")
+                    && classes.iter().all(|class| {
+                        self.has_instance(&Predicate {
+                            class: class.clone(),
+                            typ: MonoType::Int,
+                        })
+                    })
+            })
+            .map(|(x, _)| (x, MonoType::Int))
+            .collect())
+    }
+
+    /// Predicates left on type variables are not determined by the program,
+    /// e.g. that of the parameter of a function that is never applied, and
+    /// are accepted as they are, unless a dictionary has to be chosen for
+    /// them. Returns the dictionary chosen for every placeholder.
+    fn check_predicates(
+        &mut self,
+        substitutions: &Substitutions,
+    ) -> Result<HashMap<String, String>, TypeError> {
+        let mut dicts = HashMap::new();
+        for (predicate, placeholder) in std::mem::take(&mut self.predicates) {
+            let predicate = Predicate {
+                class: predicate.class,
+                typ: Self::apply_substitutions_for_monotype(&predicate.typ, substitutions)?,
+            };
+            match (placeholder, &predicate.typ) {
+                (Some(_), MonoType::TypeVariable(_)) => {
+                    return Err(TypeError::AmbiguousPredicate(predicate))
+                }
+                (Some(placeholder), _) => match self.find_instance(&predicate) {
+                    Some(instance) => {
+                        dicts.insert(placeholder, instance.dict.clone());
+                    }
+                    None => return Err(TypeError::MissingInstance(predicate)),
+                },
+                (None, MonoType::TypeVariable(_)) => {}
+                (None, _) => {
+                    if !self.has_instance(&predicate) {
+                        return Err(TypeError::MissingInstance(predicate));
+                    }
+                }
+            }
+        }
+        Ok(dicts)
+    }
+
+    /// Replace every placeholder in the elaborated `expr` with the variable
+    /// its dictionary is bound to, and the types of every cast with what
+    /// `substitutions` made of them. Casts that check nothing are dropped.
+    fn resolve_placeholders(
+        expr: &Expr,
+        dicts: &HashMap<String, String>,
+        substitutions: &Substitutions,
+    ) -> Result<Expr, TypeError> {
+        let resolve = |e: &Expr| Self::resolve_placeholders(e, dicts, substitutions).map(Box::new);
+        Ok(match expr {
+            Expr::Var(x) => Expr::Var(dicts.get(x).unwrap_or(x).clone()),
+            Expr::Let { x, e1, e2 } => Expr::Let {
+                x: x.clone(),
+                e1: resolve(e1)?,
+                e2: resolve(e2)?,
+            },
+            Expr::If { guard, e1, e2 } => Expr::If {
+                guard: resolve(guard)?,
+                e1: resolve(e1)?,
+                e2: resolve(e2)?,
+            },
+            Expr::Bin { op, e1, e2 } => Expr::Bin {
+                op: *op,
+                e1: resolve(e1)?,
+                e2: resolve(e2)?,
+            },
+            Expr::Fn { arg, body } => Expr::Fn {
+                arg: arg.clone(),
+                body: resolve(body)?,
+            },
+            Expr::Apply { func, arg } => Expr::Apply {
+                func: resolve(func)?,
+                arg: resolve(arg)?,
+            },
+            Expr::Prim { prim, args } => Expr::Prim {
+                prim: *prim,
+                args: args
+                    .iter()
+                    .map(|arg| Ok(*resolve(arg)?))
+                    .collect::<Result<_, TypeError>>()?,
+            },
+            Expr::Lazy(e) => Expr::Lazy(resolve(e)?),
+            Expr::Record(fields) => Expr::Record(
+                fields
+                    .iter()
+                    .map(|(label, value)| Ok((label.clone(), *resolve(value)?)))
+                    .collect::<Result<_, TypeError>>()?,
+            ),
+            Expr::Extend {
+                label,
+                value,
+                record,
+            } => Expr::Extend {
+                label: label.clone(),
+                value: resolve(value)?,
+                record: resolve(record)?,
+            },
+            Expr::Select { record, label } => Expr::Select {
+                record: resolve(record)?,
+                label: label.clone(),
+            },
+            Expr::Remove { record, label } => Expr::Remove {
+                record: resolve(record)?,
+                label: label.clone(),
+            },
+            Expr::Dict(methods) => Expr::Dict(
+                methods
+                    .iter()
+                    .map(|m| Ok(*resolve(m)?))
+                    .collect::<Result<_, TypeError>>()?,
+            ),
+            Expr::Method { dict, index } => Expr::Method {
+                dict: resolve(dict)?,
+                index: *index,
+            },
+            Expr::Unit
+            | Expr::Int(_)
+            | Expr::Fixed(_)
+            | Expr::Bool(_)
+            | Expr::Str(_)
+            | Expr::Suspension(_)
+            | Expr::Array(_)
+            | Expr::Map(_)
+            | Expr::Task(..)
+            | Expr::Chan(..)
+            | Expr::MatchFailure { .. }
+            | Expr::Literal { .. } => expr.clone(),
+            Expr::Perform { op, arg } => Expr::Perform {
+                op: op.clone(),
+                arg: resolve(arg)?,
+            },
+            Expr::Assert { cond, pos } => Expr::Assert {
+                cond: resolve(cond)?,
+                pos: *pos,
+            },
+            Expr::Test { name, pos, e, body } => Expr::Test {
+                name: name.clone(),
+                pos: *pos,
+                e: resolve(e)?,
+                body: resolve(body)?,
+            },
+            Expr::Handle { body, clauses, ret } => Expr::Handle {
+                body: resolve(body)?,
+                clauses: clauses
+                    .iter()
+                    .map(|clause| {
+                        Ok(OpClause {
+                            body: *resolve(&clause.body)?,
+                            ..clause.clone()
+                        })
+                    })
+                    .collect::<Result<_, TypeError>>()?,
+                ret: (ret.0.clone(), resolve(&ret.1)?),
+            },
+            Expr::OptArg(arg) => Expr::OptArg(arg.as_ref().map(|arg| resolve(arg)).transpose()?),
+            Expr::OrDefault { arg, default } => Expr::OrDefault {
+                arg: resolve(arg)?,
+                default: resolve(default)?,
+            },
+            Expr::Cast { expr, from, to } => {
+                let from = Self::apply_substitutions_for_monotype(from, substitutions)?;
+                let to = Self::apply_substitutions_for_monotype(to, substitutions)?;
+                if from == to || !(from.contains_dyn() || to.contains_dyn()) {
+                    *resolve(expr)?
+                } else {
+                    Expr::Cast {
+                        expr: resolve(expr)?,
+                        from,
+                        to,
+                    }
+                }
+            }
+            Expr::Class { .. }
+            | Expr::Instance { .. }
+            | Expr::Effect { .. }
+            | Expr::LabeledFn { .. }
+            | Expr::LabeledApply { .. }
+            | Expr::BindingOp { .. }
+            | Expr::Ascribe { .. }
+            | Expr::Measured { .. } => unreachable!(),
+        })
+    }
+
+    /// Constraints on the type of `expr`, and `expr` elaborated into
+    /// dictionary passing.
     fn build_constraints(
         &mut self,
         env: &TypeEnvironment,
         expr: &Expr,
-    ) -> Result<(MonoType, TypeConstraints), TypeError> {
+    ) -> Result<(MonoType, TypeConstraints, Expr), TypeError> {
         match expr {
-            Expr::Int(_) => Ok((MonoType::Int, TypeConstraints::new())),
-            Expr::Bool(_) => Ok((MonoType::Bool, TypeConstraints::new())),
+            Expr::Int(_) => Ok((MonoType::Int, TypeConstraints::new(), expr.clone())),
+            Expr::Fixed(i) => Ok((
+                MonoType::Fixed(i.width()),
+                TypeConstraints::new(),
+                expr.clone(),
+            )),
+            Expr::Literal { spelling: _, value } => {
+                let (t, c, _) = self.build_constraints(env, value)?;
+                Ok((t, c, expr.clone()))
+            }
+            Expr::Lazy(e) => {
+                let (t, c, e) = self.build_constraints(env, e)?;
+                Ok((MonoType::Lazy(Box::new(t)), c, Expr::Lazy(Box::new(e))))
+            }
+            Expr::Suspension(thunk) => {
+                let e = match &*thunk.borrow() {
+                    Thunk::Delayed(e) | Thunk::Forced(e) => e.clone(),
+                };
+                let (t, c, _) = self.build_constraints(env, &Expr::Lazy(Box::new(e)))?;
+                Ok((t, c, expr.clone()))
+            }
+            // Arrays and maps are only made by the interpreter, but like
+            // suspensions they may end up in expressions.
+            Expr::Array(values) => {
+                let t = MonoType::TypeVariable(self.type_var_name_generator.next());
+                let mut constraints = TypeConstraints::new();
+                for value in values.iter() {
+                    let (tv, cv, _) = self.build_constraints(env, value)?;
+                    constraints.extend(cv);
+                    constraints.extend([(t.clone(), tv)]);
+                }
+                Ok((MonoType::Array(Box::new(t)), constraints, expr.clone()))
+            }
+            Expr::Map(map) => {
+                let k = MonoType::TypeVariable(self.type_var_name_generator.next());
+                let v = MonoType::TypeVariable(self.type_var_name_generator.next());
+                let mut constraints = TypeConstraints::new();
+                for (key, value) in map.entries() {
+                    let (tk, ck, _) = self.build_constraints(env, key)?;
+                    let (tv, cv, _) = self.build_constraints(env, value)?;
+                    constraints.extend(ck);
+                    constraints.extend(cv);
+                    constraints.extend([(k.clone(), tk), (v.clone(), tv)]);
+                }
+                Ok((
+                    MonoType::Map(Box::new(k), Box::new(v)),
+                    constraints,
+                    expr.clone(),
+                ))
+            }
+            // What a task returns or a channel carries is not kept with it.
+            Expr::Task(..) => {
+                let t = MonoType::TypeVariable(self.type_var_name_generator.next());
+                Ok((
+                    MonoType::Task(Box::new(t)),
+                    TypeConstraints::new(),
+                    expr.clone(),
+                ))
+            }
+            Expr::Chan(..) => {
+                let t = MonoType::TypeVariable(self.type_var_name_generator.next());
+                Ok((
+                    MonoType::Chan(Box::new(t)),
+                    TypeConstraints::new(),
+                    expr.clone(),
+                ))
+            }
+            Expr::Bool(_) => Ok((MonoType::Bool, TypeConstraints::new(), expr.clone())),
+            Expr::Str(_) => Ok((MonoType::String, TypeConstraints::new(), expr.clone())),
+            Expr::Unit => Ok((MonoType::Unit, TypeConstraints::new(), expr.clone())),
+            // A failed match never returns, so it has any type.
+            Expr::MatchFailure { .. } => Ok((
+                MonoType::TypeVariable(self.type_var_name_generator.next()),
+                TypeConstraints::new(),
+                expr.clone(),
+            )),
+            Expr::Prim { prim, args } => {
+                let (mut typ, _) = self.instantiate(&Self::prim_scheme(*prim))?;
+                let mut constraints = TypeConstraints::new();
+                let mut elaborated_args = vec![];
+                for arg in args {
+                    let fresh = self.type_var_name_generator.next();
+                    let (ta, ca, arg) = self.build_used_constraints(env, arg)?;
+                    constraints.extend(ca);
+                    constraints.extend([(
+                        typ,
+                        MonoType::Fn(
+                            Box::new(ta),
+                            Box::new(MonoType::TypeVariable(fresh.clone())),
+                        ),
+                    )]);
+                    typ = MonoType::TypeVariable(fresh);
+                    elaborated_args.push(arg);
+                }
+                Ok((
+                    typ,
+                    constraints,
+                    Expr::Prim {
+                        prim: *prim,
+                        args: elaborated_args,
+                    },
+                ))
+            }
             Expr::Var(x) => {
                 let typ = env
                     .get(x)
                     .cloned()
                     .ok_or(TypeError::UnboundVariable(x.clone()))?;
-                let monotype = match typ {
+                let (monotype, dicts) = match typ {
                     Type::PolyType(polytype) => self.instantiate(&polytype)?,
-                    Type::MonoType(monotype) => monotype,
+                    Type::MonoType(monotype) => (monotype, vec![]),
                 };
-                Ok((monotype, TypeConstraints::new()))
+                let expr = dicts
+                    .into_iter()
+                    .fold(expr.clone(), |func, dict| Expr::Apply {
+                        func: Box::new(func),
+                        arg: Box::new(Expr::Var(dict)),
+                    });
+                Ok((monotype, TypeConstraints::new(), expr))
             }
             Expr::Bin { op, e1, e2 } => {
                 let fresh = self.type_var_name_generator.next();
-                let (t1, c1) = self.build_constraints(env, e1)?;
-                let (t2, c2) = self.build_constraints(env, e2)?;
+                let (top, _, _) = self.build_constraints(env, &Expr::Var(op.to_string()))?;
+                let (t1, c1, e1) = self.build_used_constraints(env, e1)?;
+                let (t2, c2, e2) = self.build_used_constraints(env, e2)?;
                 let mut constraints = TypeConstraints::new();
                 constraints.extend(c1);
                 constraints.extend(c2);
-                // TODO: Let the initial environment has binding of the boolean operators.
-                let result_type = match op {
-                    BinOp::Plus | BinOp::Times => MonoType::Int,
-                    BinOp::Le => MonoType::Bool,
-                };
-                constraints.extend([
-                    (t1, MonoType::Int),
-                    (t2, MonoType::Int),
-                    (MonoType::TypeVariable(fresh.clone()), result_type),
-                ]);
-                Ok((MonoType::TypeVariable(fresh.clone()), constraints))
+                constraints.extend([(
+                    top,
+                    MonoType::Fn(
+                        Box::new(t1),
+                        Box::new(MonoType::Fn(
+                            Box::new(t2),
+                            Box::new(MonoType::TypeVariable(fresh.clone())),
+                        )),
+                    ),
+                )]);
+                Ok((
+                    MonoType::TypeVariable(fresh.clone()),
+                    constraints,
+                    self.desugar_bin(*op, e1, e2),
+                ))
             }
             Expr::If { guard, e1, e2 } => {
                 let fresh = self.type_var_name_generator.next();
-                let (tg, cg) = self.build_constraints(env, guard)?;
-                let (t1, c1) = self.build_constraints(env, e1)?;
-                let (t2, c2) = self.build_constraints(env, e2)?;
+                let (tg, cg, guard) = self.build_used_constraints(env, guard)?;
+                let (t1, c1, e1) = self.build_used_constraints(env, e1)?;
+                let (t2, c2, e2) = self.build_used_constraints(env, e2)?;
                 let mut constraints = VecDeque::new();
                 constraints.extend(cg);
                 constraints.extend(c1);
@@ -162,7 +1131,15 @@ impl TypeChecker {
                     (MonoType::TypeVariable(fresh.clone()), t1),
                     (MonoType::TypeVariable(fresh.clone()), t2),
                 ]);
-                Ok((MonoType::TypeVariable(fresh.clone()), constraints))
+                Ok((
+                    MonoType::TypeVariable(fresh.clone()),
+                    constraints,
+                    Expr::If {
+                        guard: Box::new(guard),
+                        e1: Box::new(e1),
+                        e2: Box::new(e2),
+                    },
+                ))
             }
             Expr::Fn { arg, body } => {
                 let fresh = self.type_var_name_generator.next();
@@ -171,54 +1148,689 @@ impl TypeChecker {
                     arg.clone(),
                     Type::MonoType(MonoType::TypeVariable(fresh.clone())),
                 );
-                let (t, c) = self.build_constraints(&new_env, body)?;
+                let (t, c, body) = self.build_constraints(&new_env, body)?;
                 Ok((
                     MonoType::Fn(Box::new(MonoType::TypeVariable(fresh.clone())), Box::new(t)),
                     c,
+                    Expr::Fn {
+                        arg: arg.clone(),
+                        body: Box::new(body),
+                    },
                 ))
             }
             Expr::Let { x, e1, e2 } => {
-                let (t1, c1) = self.build_constraints(env, e1)?;
-                let new_env = self.generalize(&c1, env, (x, &t1))?;
-                let (t2, c2) = self.build_constraints(&new_env, e2)?;
+                let generalizable = !Self::may_create_channel(e1);
+                let pending = self.predicates.len();
+                let (t1, c1, e1) = self.build_constraints(env, e1)?;
+                let (new_env, dicts) =
+                    self.generalize(&c1, env, (x, &t1), generalizable, pending)?;
+                let (t2, c2, e2) = self.build_constraints(&new_env, e2)?;
                 let mut constraints = TypeConstraints::new();
                 constraints.extend(c1);
                 constraints.extend(c2);
-                Ok((t2, constraints))
+                // An overloaded binding takes a dictionary for each predicate
+                // of a declared class in its type scheme, and every
+                // placeholder of that predicate refers to the dictionary.
+                let e1 = dicts.into_iter().rev().fold(e1, |body, placeholders| {
+                    let (param, aliases) = placeholders.split_first().unwrap();
+                    let body = aliases.iter().fold(body, |body, alias| Expr::Let {
+                        x: alias.clone(),
+                        e1: Box::new(Expr::Var(param.clone())),
+                        e2: Box::new(body),
+                    });
+                    Expr::Fn {
+                        arg: param.clone(),
+                        body: Box::new(body),
+                    }
+                });
+                Ok((
+                    t2,
+                    constraints,
+                    Expr::Let {
+                        x: x.clone(),
+                        e1: Box::new(e1),
+                        e2: Box::new(e2),
+                    },
+                ))
             }
-            Expr::Apply { func, arg } => {
-                let fresh = self.type_var_name_generator.next();
-                let (tf, cf) = self.build_constraints(env, func)?;
-                let (ta, ca) = self.build_constraints(env, arg)?;
+            Expr::Apply { .. } | Expr::LabeledApply { .. } => self.build_application(env, expr),
+            // The call is checked on its own once `e1` and `f` are, so that
+            // an error in it points at the binding operator rather than at
+            // code the program does not contain.
+            Expr::BindingOp { op, pos, e1, f } => {
+                let at = |error| TypeError::BindingOp {
+                    op: op.clone(),
+                    pos: *pos,
+                    error: Box::new(error),
+                };
+                let func = Expr::Var(if op == "let*" { "bind" } else { "map" }.into());
+                let (tfunc, _, func) = self.build_constraints(env, &func).map_err(at)?;
+                let (t1, c1, e1) = self.build_used_constraints(env, e1)?;
+                let (tf, cf, f) = self.build_used_constraints(env, f)?;
                 let mut constraints = TypeConstraints::new();
+                constraints.extend(c1);
                 constraints.extend(cf);
-                constraints.extend(ca);
-                constraints.extend([(
-                    tf,
+                let substitutions = self.unify(&constraints)?;
+                let result = MonoType::TypeVariable(self.type_var_name_generator.next());
+                let call = (
+                    tfunc,
                     MonoType::Fn(
-                        Box::new(ta),
-                        Box::new(MonoType::TypeVariable(fresh.clone())),
+                        Box::new(tf),
+                        Box::new(MonoType::Fn(Box::new(t1), Box::new(result.clone()))),
                     ),
-                )]);
-                Ok((MonoType::TypeVariable(fresh.clone()), constraints))
+                );
+                self.unify(&TypeConstraints::from([(
+                    Self::apply_substitutions_for_monotype(&call.0, &substitutions)?,
+                    Self::apply_substitutions_for_monotype(&call.1, &substitutions)?,
+                )]))
+                .map_err(at)?;
+                constraints.push_back(call);
+                Ok((
+                    result,
+                    constraints,
+                    Expr::Apply {
+                        func: Box::new(Expr::Apply {
+                            func: Box::new(func),
+                            arg: Box::new(f),
+                        }),
+                        arg: Box::new(e1),
+                    },
+                ))
+            }
+            Expr::LabeledFn {
+                label,
+                default,
+                body,
+            } => {
+                let fresh = MonoType::TypeVariable(self.type_var_name_generator.next());
+                let mut constraints = TypeConstraints::new();
+                let default = match default {
+                    Some(default) => {
+                        let (td, cd, default) = self.build_constraints(env, default)?;
+                        constraints.extend(cd);
+                        constraints.extend([(fresh.clone(), td)]);
+                        Some(default)
+                    }
+                    None => None,
+                };
+                let mut new_env = env.clone();
+                new_env.insert(label.clone(), Type::MonoType(fresh.clone()));
+                let (t, c, body) = self.build_constraints(&new_env, body)?;
+                constraints.extend(c);
+                // A function is passed an `OptArg` for an optional parameter.
+                let (typ_label, body) = match default {
+                    Some(default) => (
+                        Label::Optional(label.clone()),
+                        Expr::Let {
+                            x: label.clone(),
+                            e1: Box::new(Expr::OrDefault {
+                                arg: Box::new(Expr::Var(label.clone())),
+                                default: Box::new(default),
+                            }),
+                            e2: Box::new(body),
+                        },
+                    ),
+                    None => (Label::Required(label.clone()), body),
+                };
+                Ok((
+                    MonoType::Labeled(typ_label, Box::new(fresh), Box::new(t)),
+                    constraints,
+                    Expr::Fn {
+                        arg: label.clone(),
+                        body: Box::new(body),
+                    },
+                ))
+            }
+            // Type variables in the signature of an effect stand for types
+            // the program determines once, not for every type, as nothing
+            // would keep a handler from resuming with the wrong one.
+            // Binding the signature in the environment keeps them from
+            // being generalized.
+            Expr::Effect { name, typ, body } => {
+                let fresh: Substitutions = self
+                    .free_type_vars(&Type::MonoType(typ.clone()))
+                    .into_iter()
+                    .map(|x| {
+                        (
+                            x,
+                            MonoType::TypeVariable(self.type_var_name_generator.next()),
+                        )
+                    })
+                    .collect();
+                let typ = Self::apply_substitutions_for_monotype(typ, &fresh)?;
+                let mut new_env = env.clone();
+                new_env.insert(Self::effect_key(name), Type::MonoType(typ));
+                self.build_constraints(&new_env, body)
+            }
+            Expr::Assert { cond, pos } => {
+                let (t, mut c, cond) = self.build_used_constraints(env, cond)?;
+                c.push_back((t, MonoType::Bool));
+                Ok((
+                    MonoType::Unit,
+                    c,
+                    Expr::Assert {
+                        cond: Box::new(cond),
+                        pos: *pos,
+                    },
+                ))
+            }
+            Expr::Test { name, pos, e, body } => {
+                let (te, mut constraints, e) = self.build_used_constraints(env, e)?;
+                constraints.push_back((te, MonoType::Bool));
+                let (t, c, body) = self.build_constraints(env, body)?;
+                constraints.extend(c);
+                Ok((
+                    t,
+                    constraints,
+                    Expr::Test {
+                        name: name.clone(),
+                        pos: *pos,
+                        e: Box::new(e),
+                        body: Box::new(body),
+                    },
+                ))
+            }
+            Expr::Perform { op, arg } => {
+                let (targ, result) = Self::effect_signature(env, op)?;
+                let (t, mut c, arg) = self.build_used_constraints(env, arg)?;
+                c.push_back((t, targ));
+                Ok((
+                    result,
+                    c,
+                    Expr::Perform {
+                        op: op.clone(),
+                        arg: Box::new(arg),
+                    },
+                ))
+            }
+            // Handlers are deep: `k` resumes the computation under the same
+            // handler, so it returns what the handler does.
+            Expr::Handle { body, clauses, ret } => {
+                let (tb, mut constraints, body) = self.build_constraints(env, body)?;
+                let result = MonoType::TypeVariable(self.type_var_name_generator.next());
+                let mut new_env = env.clone();
+                new_env.insert(ret.0.clone(), Type::MonoType(tb));
+                let (tr, cr, ret_body) = self.build_constraints(&new_env, &ret.1)?;
+                constraints.extend(cr);
+                constraints.push_back((tr, result.clone()));
+                let mut elaborated = vec![];
+                for clause in clauses {
+                    let (targ, tresult) = Self::effect_signature(env, &clause.op)?;
+                    let mut new_env = env.clone();
+                    new_env.insert(clause.arg.clone(), Type::MonoType(targ));
+                    new_env.insert(
+                        clause.k.clone(),
+                        Type::MonoType(MonoType::Fn(Box::new(tresult), Box::new(result.clone()))),
+                    );
+                    let (t, c, body) = self.build_constraints(&new_env, &clause.body)?;
+                    constraints.extend(c);
+                    constraints.push_back((t, result.clone()));
+                    elaborated.push(OpClause {
+                        body,
+                        ..clause.clone()
+                    });
+                }
+                Ok((
+                    result,
+                    constraints,
+                    Expr::Handle {
+                        body: Box::new(body),
+                        clauses: elaborated,
+                        ret: (ret.0.clone(), Box::new(ret_body)),
+                    },
+                ))
+            }
+            Expr::Class {
+                name,
+                var,
+                methods,
+                body,
+            } => {
+                if BUILTIN_CLASSES.contains(&name.as_str()) {
+                    return Err(TypeError::BuiltinClass(name.clone()));
+                }
+                self.classes.insert(
+                    name.clone(),
+                    Class {
+                        var: var.clone(),
+                        methods: methods.clone(),
+                    },
+                );
+                let mut new_env = env.clone();
+                for (method, typ) in methods {
+                    let mut bounded_type_vars = vec![var.clone()];
+                    bounded_type_vars.extend(
+                        self.free_type_vars(&Type::MonoType(typ.clone()))
+                            .into_iter()
+                            .filter(|x| x != var),
+                    );
+                    let scheme = PolyType {
+                        bounded_type_vars,
+                        predicates: vec![Predicate {
+                            class: name.clone(),
+                            typ: MonoType::TypeVariable(var.clone()),
+                        }],
+                        typ: typ.clone(),
+                    };
+                    new_env.insert(method.clone(), Type::PolyType(scheme));
+                }
+                let (t, c, body) = self.build_constraints(&new_env, body)?;
+                // A method takes a dictionary and selects itself from it.
+                let body =
+                    methods
+                        .iter()
+                        .enumerate()
+                        .rev()
+                        .fold(body, |body, (index, (method, _))| Expr::Let {
+                            x: method.clone(),
+                            e1: Box::new(Expr::Fn {
+                                arg: "$dict".into(),
+                                body: Box::new(Expr::Method {
+                                    dict: Box::new(Expr::Var("$dict".into())),
+                                    index,
+                                }),
+                            }),
+                            e2: Box::new(body),
+                        });
+                Ok((t, c, body))
+            }
+            Expr::Instance {
+                class,
+                typ,
+                methods,
+                body,
+            } => {
+                if BUILTIN_CLASSES.contains(&class.as_str()) {
+                    return Err(TypeError::BuiltinClass(class.clone()));
+                }
+                let decl = self
+                    .classes
+                    .get(class)
+                    .cloned()
+                    .ok_or(TypeError::UnknownClass(class.clone()))?;
+                let head = Predicate {
+                    class: class.clone(),
+                    typ: typ.clone(),
+                };
+                if self.instances.iter().any(|instance| {
+                    instance.head.class == head.class
+                        && (Self::match_type(&instance.head.typ, typ, &mut HashMap::new())
+                            || Self::match_type(typ, &instance.head.typ, &mut HashMap::new()))
+                }) {
+                    return Err(TypeError::OverlappingInstances(head));
+                }
+                if let Some((method, _)) = methods
+                    .iter()
+                    .find(|(method, _)| decl.methods.iter().all(|(m, _)| m != method))
+                {
+                    return Err(TypeError::UnknownMethod {
+                        class: class.clone(),
+                        method: method.clone(),
+                    });
+                }
+                // Every method must have the type its class declares, with the
+                // class's type variable replaced by the instance type.
+                let (typ, _) = self.instantiate(&PolyType {
+                    bounded_type_vars: Vec::from_iter(
+                        self.free_type_vars(&Type::MonoType(typ.clone())),
+                    ),
+                    predicates: vec![],
+                    typ: typ.clone(),
+                })?;
+                let mut constraints = TypeConstraints::new();
+                let mut dict = vec![];
+                for (method, sig) in decl.methods.iter() {
+                    let (_, e) = methods.iter().find(|(m, _)| m == method).ok_or(
+                        TypeError::MissingMethod {
+                            class: class.clone(),
+                            method: method.clone(),
+                        },
+                    )?;
+                    let mut substitutions: Substitutions = self
+                        .free_type_vars(&Type::MonoType(sig.clone()))
+                        .into_iter()
+                        .filter(|x| *x != decl.var)
+                        .map(|x| {
+                            (
+                                x,
+                                MonoType::TypeVariable(self.type_var_name_generator.next()),
+                            )
+                        })
+                        .collect();
+                    substitutions.push_back((decl.var.clone(), typ.clone()));
+                    let expected = Self::apply_substitutions_for_monotype(sig, &substitutions)?;
+                    let (t, c, e) = self.build_used_constraints(env, e)?;
+                    constraints.extend(c);
+                    constraints.extend([(t, expected)]);
+                    dict.push(e);
+                }
+                let name = self.fresh_dict_name("$inst");
+                self.instances.push(Instance {
+                    head,
+                    dict: name.clone(),
+                });
+                let (t, c, body) = self.build_constraints(env, body)?;
+                constraints.extend(c);
+                Ok((
+                    t,
+                    constraints,
+                    Expr::Let {
+                        x: name,
+                        e1: Box::new(Expr::Dict(dict)),
+                        e2: Box::new(body),
+                    },
+                ))
+            }
+            Expr::Record(fields) => {
+                let mut constraints = TypeConstraints::new();
+                let mut elaborated = vec![];
+                let mut types = vec![];
+                for (label, value) in fields {
+                    let (t, c, value) = self.build_constraints(env, value)?;
+                    constraints.extend(c);
+                    types.push((label.clone(), t));
+                    elaborated.push((label.clone(), value));
+                }
+                let row = types
+                    .into_iter()
+                    .rev()
+                    .fold(MonoType::RowEmpty, |row, (label, t)| {
+                        MonoType::RowExtend(label, Box::new(t), Box::new(row))
+                    });
+                Ok((
+                    MonoType::Record(Box::new(row)),
+                    constraints,
+                    Expr::Record(elaborated),
+                ))
+            }
+            Expr::Extend {
+                label,
+                value,
+                record,
+            } => {
+                let (tv, cv, value) = self.build_constraints(env, value)?;
+                let (tr, cr, record) = self.build_used_constraints(env, record)?;
+                let row = MonoType::TypeVariable(self.type_var_name_generator.next());
+                let mut constraints = cv;
+                constraints.extend(cr);
+                constraints.push_back((tr, MonoType::Record(Box::new(row.clone()))));
+                Ok((
+                    MonoType::Record(Box::new(MonoType::RowExtend(
+                        label.clone(),
+                        Box::new(tv),
+                        Box::new(row),
+                    ))),
+                    constraints,
+                    Expr::Extend {
+                        label: label.clone(),
+                        value: Box::new(value),
+                        record: Box::new(record),
+                    },
+                ))
+            }
+            // Both `r.label` and `r \ label` require `r : { label : 'a | 'r }`.
+            Expr::Select { record, label } | Expr::Remove { record, label } => {
+                let (tr, mut constraints, elaborated) = self.build_used_constraints(env, record)?;
+                let field = MonoType::TypeVariable(self.type_var_name_generator.next());
+                let row = MonoType::TypeVariable(self.type_var_name_generator.next());
+                constraints.push_back((
+                    tr,
+                    MonoType::Record(Box::new(MonoType::RowExtend(
+                        label.clone(),
+                        Box::new(field.clone()),
+                        Box::new(row.clone()),
+                    ))),
+                ));
+                let record = Box::new(elaborated);
+                let label = label.clone();
+                match expr {
+                    Expr::Select { .. } => Ok((field, constraints, Expr::Select { record, label })),
+                    _ => Ok((
+                        MonoType::Record(Box::new(row)),
+                        constraints,
+                        Expr::Remove { record, label },
+                    )),
+                }
+            }
+            // `(e : t)` checks `e` against `t`, where a `Dyn` on either side
+            // is consistent with anything, and casts it to `t`.
+            Expr::Ascribe { expr, typ } => {
+                let (t, mut constraints, expr) = self.build_constraints(env, expr)?;
+                constraints.push_back((t.clone(), typ.clone()));
+                Ok((
+                    typ.clone(),
+                    constraints,
+                    Expr::Cast {
+                        expr: Box::new(expr),
+                        from: t,
+                        to: typ.clone(),
+                    },
+                ))
+            }
+            // `5<m>` is `5`, of type `Int<m>`.
+            Expr::Measured { expr, measure } => {
+                let (t, constraints, expr) = self.build_constraints(env, expr)?;
+                Ok((MonoType::measured(t, measure.clone())?, constraints, expr))
+            }
+            Expr::Cast { .. } => unreachable!("casts only exist in elaborated expressions"),
+            Expr::Dict(_) | Expr::Method { .. } => {
+                unreachable!("dictionaries only exist in elaborated expressions")
+            }
+            Expr::OptArg(_) | Expr::OrDefault { .. } => {
+                unreachable!("optional arguments only exist in elaborated expressions")
+            }
+        }
+    }
+
+    /// Constraints on `expr` where its value is used, e.g. applied or
+    /// added. A `Dyn` in its type stands for whatever type that use needs,
+    /// so it is replaced with a fresh type variable, and `expr` is cast to
+    /// the type the variables are solved to.
+    fn build_used_constraints(
+        &mut self,
+        env: &TypeEnvironment,
+        expr: &Expr,
+    ) -> Result<(MonoType, TypeConstraints, Expr), TypeError> {
+        let (typ, constraints, expr) = self.build_constraints(env, expr)?;
+        if !typ.contains_dyn() {
+            return Ok((typ, constraints, expr));
+        }
+        let to = self.replace_dyn(&typ);
+        Ok((
+            to.clone(),
+            constraints,
+            Expr::Cast {
+                expr: Box::new(expr),
+                from: typ,
+                to,
+            },
+        ))
+    }
+
+    fn replace_dyn(&mut self, typ: &MonoType) -> MonoType {
+        let mut replace = |t: &MonoType| Box::new(self.replace_dyn(t));
+        match typ {
+            MonoType::Dyn(_) => {
+                let fresh = self.type_var_name_generator.next();
+                self.dyn_vars.push(fresh.clone());
+                MonoType::TypeVariable(fresh)
+            }
+            MonoType::Lazy(t) => MonoType::Lazy(replace(t)),
+            MonoType::Array(t) => MonoType::Array(replace(t)),
+            MonoType::Task(t) => MonoType::Task(replace(t)),
+            MonoType::Chan(t) => MonoType::Chan(replace(t)),
+            MonoType::Record(t) => MonoType::Record(replace(t)),
+            MonoType::Map(t1, t2) => MonoType::Map(replace(t1), replace(t2)),
+            MonoType::Fn(t1, t2) => MonoType::Fn(replace(t1), replace(t2)),
+            MonoType::Labeled(label, t1, t2) => {
+                MonoType::Labeled(label.clone(), replace(t1), replace(t2))
+            }
+            MonoType::RowExtend(label, t1, t2) => {
+                MonoType::RowExtend(label.clone(), replace(t1), replace(t2))
+            }
+            _ => typ.clone(),
+        }
+    }
+
+    /// Constraints on an application such as `f(x, ~width: 1)`. Labeled
+    /// arguments are passed to the parameters with their labels, in
+    /// whatever order they are written, and the others to the remaining
+    /// parameters in order. An optional parameter is left out when the call
+    /// passes arguments to later parameters only, and a required one that is
+    /// passed nothing is left for a later call.
+    fn build_application(
+        &mut self,
+        env: &TypeEnvironment,
+        expr: &Expr,
+    ) -> Result<(MonoType, TypeConstraints, Expr), TypeError> {
+        let mut spine = vec![];
+        let mut func = expr;
+        loop {
+            let (f, label, arg) = match func {
+                Expr::Apply { func, arg } => (func, None, arg),
+                Expr::LabeledApply { func, label, arg } => (func, Some(label.clone()), arg),
+                _ => break,
+            };
+            spine.push((label, arg));
+            func = f;
+        }
+        let (mut typ, mut constraints, mut elaborated) = self.build_used_constraints(env, func)?;
+        let mut args = VecDeque::new();
+        for (label, arg) in spine.into_iter().rev() {
+            let (ta, ca, arg) = self.build_used_constraints(env, arg)?;
+            constraints.extend(ca);
+            args.push_back((label, ta, arg));
+        }
+        // Labels can only be matched up once the type of `func` is known.
+        // Each constraint is solved at most once here, however long the
+        // spine, by solving only those added since the last time on top of
+        // what is known already.
+        let labeled = args.iter().any(|(label, _, _)| label.is_some()) || Self::has_labels(&typ);
+        let mut skipped = vec![];
+        let mut substitutions = Substitutions::new();
+        let mut solved = 0;
+        while !args.is_empty() {
+            if labeled && matches!(typ, MonoType::TypeVariable(_)) {
+                let unsolved = constraints
+                    .iter()
+                    .skip(solved)
+                    .map(|(lhs, rhs)| {
+                        Ok((
+                            Self::apply_substitutions_for_monotype(lhs, &substitutions)?,
+                            Self::apply_substitutions_for_monotype(rhs, &substitutions)?,
+                        ))
+                    })
+                    .collect::<Result<_, TypeError>>()?;
+                substitutions.extend(self.unify(&unsolved)?);
+                solved = constraints.len();
+                typ = Self::apply_substitutions_for_monotype(&typ, &substitutions)?;
             }
+            let positional = args.iter().position(|(label, _, _)| label.is_none());
+            let (arg, rest) = match typ {
+                MonoType::Labeled(label, t, rest) => {
+                    let by_label = args
+                        .iter()
+                        .position(|(l, _, _)| l.as_deref() == Some(label.name()));
+                    // A required parameter may be passed an argument without
+                    // its label as well.
+                    let taken = match label {
+                        Label::Required(_) => by_label.or(positional),
+                        Label::Optional(_) => by_label,
+                    };
+                    let arg = match taken {
+                        Some(i) => {
+                            let (_, ta, arg) = args.remove(i).unwrap();
+                            constraints.extend([(*t, ta)]);
+                            match label {
+                                Label::Required(_) => arg,
+                                Label::Optional(_) => Expr::OptArg(Some(Box::new(arg))),
+                            }
+                        }
+                        // The remaining arguments are for later parameters.
+                        None if matches!(label, Label::Optional(_)) && !args.is_empty() => {
+                            Expr::OptArg(None)
+                        }
+                        None => {
+                            let x = self.var_name_generator.next();
+                            skipped.push((label, *t, x.clone()));
+                            Expr::Var(x)
+                        }
+                    };
+                    (arg, *rest)
+                }
+                typ => {
+                    let i = match (positional, &typ) {
+                        (Some(i), _) => i,
+                        (None, MonoType::TypeVariable(_)) => 0,
+                        (None, _) => {
+                            return Err(TypeError::UnknownLabel(args[0].0.clone().unwrap()))
+                        }
+                    };
+                    let (label, ta, arg) = args.remove(i).unwrap();
+                    let result = MonoType::TypeVariable(self.type_var_name_generator.next());
+                    let expected = match label {
+                        Some(label) => MonoType::Labeled(
+                            Label::Required(label),
+                            Box::new(ta),
+                            Box::new(result.clone()),
+                        ),
+                        None => MonoType::Fn(Box::new(ta), Box::new(result.clone())),
+                    };
+                    constraints.extend([(typ, expected)]);
+                    (arg, result)
+                }
+            };
+            elaborated = Expr::Apply {
+                func: Box::new(elaborated),
+                arg: Box::new(arg),
+            };
+            typ = rest;
+        }
+        for (label, t, x) in skipped.into_iter().rev() {
+            typ = MonoType::Labeled(label, Box::new(t), Box::new(typ));
+            elaborated = Expr::Fn {
+                arg: x,
+                body: Box::new(elaborated),
+            };
+        }
+        Ok((typ, constraints, elaborated))
+    }
+
+    /// Whether the function type `typ` has a labeled parameter.
+    fn has_labels(typ: &MonoType) -> bool {
+        match typ {
+            MonoType::Labeled(_, _, _) => true,
+            MonoType::Fn(_, o) => Self::has_labels(o),
+            _ => false,
         }
     }
 
     fn is_appear_in(&self, type_var_name: &str, monotype: &MonoType) -> bool {
         let monotype = monotype.clone();
         match monotype {
-            MonoType::Int | MonoType::Bool => false,
-            MonoType::TypeVariable(x) => {
-                if &x == type_var_name {
-                    true
-                } else {
-                    false
-                }
-            }
-            MonoType::Fn(i, o) => {
+            MonoType::Unit
+            | MonoType::Int
+            | MonoType::Fixed(_)
+            | MonoType::Dyn(_)
+            | MonoType::Bool
+            | MonoType::String => false,
+            MonoType::TypeVariable(x) => x == type_var_name,
+            MonoType::Lazy(t)
+            | MonoType::Array(t)
+            | MonoType::Task(t)
+            | MonoType::Chan(t)
+            | MonoType::Record(t) => self.is_appear_in(type_var_name, &t),
+            MonoType::Fn(i, o)
+            | MonoType::Map(i, o)
+            | MonoType::Labeled(_, i, o)
+            | MonoType::RowExtend(_, i, o) => {
                 self.is_appear_in(type_var_name, &i) || self.is_appear_in(type_var_name, &o)
             }
+            MonoType::Measured(t, measure) => {
+                self.is_appear_in(type_var_name, &t) || self.is_appear_in(type_var_name, &measure)
+            }
+            MonoType::Measure(measure) => measure.contains_key(type_var_name),
+            MonoType::RowEmpty => false,
         }
     }
 
@@ -229,13 +1841,24 @@ impl TypeChecker {
         } else {
             let (lhs, rhs) = &constraints[0];
             match (lhs, rhs) {
-                (MonoType::Int, MonoType::Int) | (MonoType::Bool, MonoType::Bool) => {
-                    self.unify(&constraints.iter().skip(1).map(|c| c.clone()).collect())
+                // `Dyn` is consistent with every type, and says nothing about
+                // it: a type variable is never bound to it.
+                (MonoType::Dyn(_), _) | (_, MonoType::Dyn(_)) => {
+                    self.unify(&constraints.iter().skip(1).cloned().collect())
+                }
+                (MonoType::Unit, MonoType::Unit)
+                | (MonoType::Int, MonoType::Int)
+                | (MonoType::Bool, MonoType::Bool)
+                | (MonoType::String, MonoType::String) => {
+                    self.unify(&constraints.iter().skip(1).cloned().collect())
+                }
+                (MonoType::Fixed(w1), MonoType::Fixed(w2)) if w1 == w2 => {
+                    self.unify(&constraints.iter().skip(1).cloned().collect())
                 }
                 (MonoType::TypeVariable(x), MonoType::TypeVariable(y)) if x == y => {
-                    self.unify(&constraints.iter().skip(1).map(|c| c.clone()).collect())
+                    self.unify(&constraints.iter().skip(1).cloned().collect())
                 }
-                (MonoType::TypeVariable(x), monotype) if !self.is_appear_in(&x, &monotype) => {
+                (MonoType::TypeVariable(x), monotype) if !self.is_appear_in(x, monotype) => {
                     let mut substitutions =
                         Substitutions::from_iter([(x.clone(), monotype.clone())]);
                     substitutions.extend(
@@ -244,20 +1867,23 @@ impl TypeChecker {
                                 .iter()
                                 .skip(1)
                                 .map(|(lhs, rhs)| {
-                                    (
-                                        // FIXME: Do not `unwrap()`.
-                                        Self::apply_substitutions_for_monotype(lhs, &substitutions)
-                                            .unwrap(),
-                                        Self::apply_substitutions_for_monotype(rhs, &substitutions)
-                                            .unwrap(),
-                                    )
+                                    Ok((
+                                        Self::apply_substitutions_for_monotype(
+                                            lhs,
+                                            &substitutions,
+                                        )?,
+                                        Self::apply_substitutions_for_monotype(
+                                            rhs,
+                                            &substitutions,
+                                        )?,
+                                    ))
                                 })
-                                .collect(),
+                                .collect::<Result<_, TypeError>>()?,
                         )?,
                     );
                     Ok(substitutions)
                 }
-                (monotype, MonoType::TypeVariable(x)) if !self.is_appear_in(&x, &monotype) => {
+                (monotype, MonoType::TypeVariable(x)) if !self.is_appear_in(x, monotype) => {
                     let mut substitutions =
                         Substitutions::from_iter([(x.clone(), monotype.clone())]);
                     substitutions.extend(
@@ -266,26 +1892,123 @@ impl TypeChecker {
                                 .iter()
                                 .skip(1)
                                 .map(|(lhs, rhs)| {
-                                    (
-                                        // FIXME: Do not `unwrap()`.
-                                        Self::apply_substitutions_for_monotype(lhs, &substitutions)
-                                            .unwrap(),
-                                        Self::apply_substitutions_for_monotype(rhs, &substitutions)
-                                            .unwrap(),
-                                    )
+                                    Ok((
+                                        Self::apply_substitutions_for_monotype(
+                                            lhs,
+                                            &substitutions,
+                                        )?,
+                                        Self::apply_substitutions_for_monotype(
+                                            rhs,
+                                            &substitutions,
+                                        )?,
+                                    ))
                                 })
-                                .collect(),
+                                .collect::<Result<_, TypeError>>()?,
                         )?,
                     );
                     Ok(substitutions)
                 }
-                (MonoType::Fn(i1, o1), MonoType::Fn(i2, o2)) => {
+                (MonoType::Measure(m1), MonoType::Measure(m2)) => {
+                    let quotient = MonoType::measure_product(lhs, rhs, -1)?;
+                    let substitutions = match quotient {
+                        MonoType::Measure(quotient) => self.unify_measure(quotient),
+                        _ => unreachable!(),
+                    }
+                    .ok_or_else(|| {
+                        TypeError::UnsolvableConstraints(
+                            MonoType::Measure(m1.clone()),
+                            MonoType::Measure(m2.clone()),
+                        )
+                    })?;
+                    let mut new_substitutions = substitutions.clone();
+                    new_substitutions.extend(
+                        self.unify(
+                            &constraints
+                                .iter()
+                                .skip(1)
+                                .map(|(lhs, rhs)| {
+                                    Ok((
+                                        Self::apply_substitutions_for_monotype(
+                                            lhs,
+                                            &substitutions,
+                                        )?,
+                                        Self::apply_substitutions_for_monotype(
+                                            rhs,
+                                            &substitutions,
+                                        )?,
+                                    ))
+                                })
+                                .collect::<Result<_, TypeError>>()?,
+                        )?,
+                    );
+                    Ok(new_substitutions)
+                }
+                (MonoType::Measured(t1, m1), MonoType::Measured(t2, m2)) => {
+                    let mut new_constraints: TypeConstraints =
+                        constraints.iter().skip(1).cloned().collect();
+                    new_constraints.push_front((*m1.clone(), *m2.clone()));
+                    new_constraints.push_front((*t1.clone(), *t2.clone()));
+                    self.unify(&new_constraints)
+                }
+                // A number without a unit has the unit `1`.
+                (MonoType::Measured(t1, m1), t2 @ (MonoType::Int | MonoType::Fixed(_)))
+                | (t2 @ (MonoType::Int | MonoType::Fixed(_)), MonoType::Measured(t1, m1)) => {
+                    let mut new_constraints: TypeConstraints =
+                        constraints.iter().skip(1).cloned().collect();
+                    new_constraints.push_front((*m1.clone(), MonoType::Measure(BTreeMap::new())));
+                    new_constraints.push_front((*t1.clone(), t2.clone()));
+                    self.unify(&new_constraints)
+                }
+                (MonoType::Lazy(t1), MonoType::Lazy(t2))
+                | (MonoType::Array(t1), MonoType::Array(t2))
+                | (MonoType::Task(t1), MonoType::Task(t2))
+                | (MonoType::Chan(t1), MonoType::Chan(t2)) => {
                     let mut new_constraints: TypeConstraints =
-                        constraints.iter().skip(1).map(|c| c.clone()).collect();
+                        constraints.iter().skip(1).cloned().collect();
+                    new_constraints.push_front((*t1.clone(), *t2.clone()));
+                    self.unify(&new_constraints)
+                }
+                (MonoType::Fn(i1, o1), MonoType::Fn(i2, o2))
+                | (MonoType::Map(i1, o1), MonoType::Map(i2, o2)) => {
+                    let mut new_constraints: TypeConstraints =
+                        constraints.iter().skip(1).cloned().collect();
+                    new_constraints.push_front((*o1.clone(), *o2.clone()));
+                    new_constraints.push_front((*i1.clone(), *i2.clone()));
+                    self.unify(&new_constraints)
+                }
+                (MonoType::Labeled(l1, i1, o1), MonoType::Labeled(l2, i2, o2)) if l1 == l2 => {
+                    let mut new_constraints: TypeConstraints =
+                        constraints.iter().skip(1).cloned().collect();
                     new_constraints.push_front((*o1.clone(), *o2.clone()));
                     new_constraints.push_front((*i1.clone(), *i2.clone()));
                     self.unify(&new_constraints)
                 }
+                (MonoType::Record(r1), MonoType::Record(r2)) => {
+                    let mut new_constraints: TypeConstraints =
+                        constraints.iter().skip(1).cloned().collect();
+                    new_constraints.push_front((*r1.clone(), *r2.clone()));
+                    self.unify(&new_constraints)
+                }
+                (MonoType::RowEmpty, MonoType::RowEmpty) => {
+                    self.unify(&constraints.iter().skip(1).cloned().collect())
+                }
+                (MonoType::RowExtend(label, t1, r1), row2 @ MonoType::RowExtend(_, _, _)) => {
+                    let unsolvable = || TypeError::UnsolvableConstraints(lhs.clone(), rhs.clone());
+                    let (t2, r2, mut new_constraints) =
+                        self.rewrite_row(row2, label).ok_or_else(unsolvable)?;
+                    // `{ a : t | 'r }` and `{ b : t | 'r }` never unify, as
+                    // `'r` would have to contain both itself and `a`.
+                    if let (Some(tail), Some((MonoType::TypeVariable(x), _))) =
+                        (MonoType::row_fields(r1).1, new_constraints.front())
+                    {
+                        if tail == x {
+                            return Err(unsolvable());
+                        }
+                    }
+                    new_constraints.extend([(*t1.clone(), t2), (*r1.clone(), r2)]);
+                    new_constraints.extend(constraints.iter().skip(1).cloned());
+                    self.unify(&new_constraints)
+                }
                 _ => {
                     println!("{constraints:?}");
                     Err(TypeError::UnsolvableConstraints(lhs.clone(), rhs.clone()))
@@ -294,12 +2017,94 @@ impl TypeChecker {
         }
     }
 
+    /// Solve `measure = 1` for its unit variables, as in Kennedy's
+    /// algorithm. The variable with the smallest exponent is solved for
+    /// when it divides all the others; otherwise it is replaced with a
+    /// fresh variable times what can be divided out, which leaves only the
+    /// remainders of the other exponents. `None` if there is no solution.
+    fn unify_measure(&mut self, mut measure: BTreeMap<String, i32>) -> Option<Substitutions> {
+        let mut substitutions = Substitutions::new();
+        loop {
+            let Some((x, e)) = measure
+                .iter()
+                .filter(|(x, _)| x.starts_with('\''))
+                .min_by_key(|(_, e)| e.abs())
+                .map(|(x, e)| (x.clone(), *e))
+            else {
+                return measure.is_empty().then_some(substitutions);
+            };
+            measure.remove(&x);
+            if measure.values().all(|e_y| e_y % e == 0) {
+                let solution = measure.iter().map(|(y, e_y)| (y.clone(), -e_y / e));
+                substitutions.push_back((x, MonoType::Measure(solution.collect())));
+                return Some(substitutions);
+            }
+            if !measure.keys().any(|y| y.starts_with('\'')) {
+                return None;
+            }
+            let fresh = self.type_var_name_generator.next();
+            let mut solution = BTreeMap::from([(fresh.clone(), 1)]);
+            for (y, e_y) in measure.iter_mut().filter(|(y, _)| y.starts_with('\'')) {
+                solution.insert(y.clone(), -e_y.div_euclid(e));
+                *e_y = e_y.rem_euclid(e);
+            }
+            solution.retain(|_, e| *e != 0);
+            measure.retain(|_, e| *e != 0);
+            measure.insert(fresh, e);
+            substitutions.push_back((x, MonoType::Measure(solution)));
+        }
+    }
+
+    /// `row` with the field `label` moved to its front, as the field type,
+    /// the rest of the row, and the constraints this takes. A row ending in a
+    /// type variable is extended with the field when it does not have one.
+    fn rewrite_row(
+        &mut self,
+        row: &MonoType,
+        label: &str,
+    ) -> Option<(MonoType, MonoType, TypeConstraints)> {
+        match row {
+            MonoType::RowExtend(l, t, rest) if l == label => {
+                Some((*t.clone(), *rest.clone(), TypeConstraints::new()))
+            }
+            MonoType::RowExtend(l, t, rest) => {
+                let (field, rest, constraints) = self.rewrite_row(rest, label)?;
+                Some((
+                    field,
+                    MonoType::RowExtend(l.clone(), t.clone(), Box::new(rest)),
+                    constraints,
+                ))
+            }
+            MonoType::TypeVariable(_) => {
+                let field = MonoType::TypeVariable(self.type_var_name_generator.next());
+                let rest = MonoType::TypeVariable(self.type_var_name_generator.next());
+                let extended = MonoType::RowExtend(
+                    label.into(),
+                    Box::new(field.clone()),
+                    Box::new(rest.clone()),
+                );
+                Some((
+                    field,
+                    rest,
+                    TypeConstraints::from([(row.clone(), extended)]),
+                ))
+            }
+            _ => None,
+        }
+    }
+
     fn apply_substitutions_for_monotype(
         monotype: &MonoType,
         substitutions: &Substitutions,
     ) -> Result<MonoType, TypeError> {
         match monotype {
-            MonoType::Int | MonoType::Bool => Ok(monotype.clone()),
+            MonoType::Unit
+            | MonoType::Int
+            | MonoType::Fixed(_)
+            | MonoType::Dyn(_)
+            | MonoType::Bool
+            | MonoType::String
+            | MonoType::RowEmpty => Ok(monotype.clone()),
             MonoType::TypeVariable(x) => {
                 for (type_var, monotype) in substitutions.iter() {
                     if x == type_var {
@@ -308,10 +2113,63 @@ impl TypeChecker {
                 }
                 Ok(monotype.clone())
             }
+            MonoType::Lazy(t) => Ok(MonoType::Lazy(Box::new(
+                Self::apply_substitutions_for_monotype(t, substitutions)?,
+            ))),
+            MonoType::Array(t) => Ok(MonoType::Array(Box::new(
+                Self::apply_substitutions_for_monotype(t, substitutions)?,
+            ))),
+            MonoType::Task(t) => Ok(MonoType::Task(Box::new(
+                Self::apply_substitutions_for_monotype(t, substitutions)?,
+            ))),
+            MonoType::Chan(t) => Ok(MonoType::Chan(Box::new(
+                Self::apply_substitutions_for_monotype(t, substitutions)?,
+            ))),
+            MonoType::Map(k, v) => Ok(MonoType::Map(
+                Box::new(Self::apply_substitutions_for_monotype(k, substitutions)?),
+                Box::new(Self::apply_substitutions_for_monotype(v, substitutions)?),
+            )),
             MonoType::Fn(i, o) => Ok(MonoType::Fn(
                 Box::new(Self::apply_substitutions_for_monotype(i, substitutions)?),
                 Box::new(Self::apply_substitutions_for_monotype(o, substitutions)?),
             )),
+            MonoType::Labeled(label, i, o) => Ok(MonoType::Labeled(
+                label.clone(),
+                Box::new(Self::apply_substitutions_for_monotype(i, substitutions)?),
+                Box::new(Self::apply_substitutions_for_monotype(o, substitutions)?),
+            )),
+            MonoType::Record(row) => Ok(MonoType::Record(Box::new(
+                Self::apply_substitutions_for_monotype(row, substitutions)?,
+            ))),
+            MonoType::RowExtend(label, t, row) => Ok(MonoType::RowExtend(
+                label.clone(),
+                Box::new(Self::apply_substitutions_for_monotype(t, substitutions)?),
+                Box::new(Self::apply_substitutions_for_monotype(row, substitutions)?),
+            )),
+            MonoType::Measured(t, measure) => MonoType::measured(
+                Self::apply_substitutions_for_monotype(t, substitutions)?,
+                Self::apply_substitutions_for_monotype(measure, substitutions)?,
+            ),
+            // A unit variable is solved to a measure, or renamed to another
+            // variable when a scheme is instantiated.
+            MonoType::Measure(measure) => {
+                let mut product = MonoType::Measure(BTreeMap::new());
+                for (x, e) in measure {
+                    let unit = |x: String| MonoType::Measure(BTreeMap::from([(x, 1)]));
+                    let factor = match x.starts_with('\'') {
+                        true => match Self::apply_substitutions_for_monotype(
+                            &MonoType::TypeVariable(x.clone()),
+                            substitutions,
+                        )? {
+                            MonoType::TypeVariable(y) => unit(y),
+                            factor => factor,
+                        },
+                        false => unit(x.clone()),
+                    };
+                    product = MonoType::measure_product(&product, &factor, *e)?;
+                }
+                Ok(product)
+            }
         }
     }
 
@@ -328,11 +2186,26 @@ impl TypeChecker {
             .collect::<Substitutions>();
         Ok(PolyType {
             bounded_type_vars: polytype.bounded_type_vars.clone(),
+            predicates: polytype
+                .predicates
+                .iter()
+                .map(|predicate| {
+                    Ok(Predicate {
+                        class: predicate.class.clone(),
+                        typ: Self::apply_substitutions_for_monotype(
+                            &predicate.typ,
+                            &filtered_substitutions,
+                        )?,
+                    })
+                })
+                .collect::<Result<_, TypeError>>()?,
             typ: Self::apply_substitutions_for_monotype(&polytype.typ, &filtered_substitutions)?,
         })
     }
 
-    fn instantiate(&mut self, polytype: &PolyType) -> Result<MonoType, TypeError> {
+    /// A fresh instance of `polytype`, and the placeholders of the
+    /// dictionaries it has to be applied to.
+    fn instantiate(&mut self, polytype: &PolyType) -> Result<(MonoType, Vec<String>), TypeError> {
         let bounded_type_var_count = polytype.bounded_type_vars.len();
         let substitutions = (0..bounded_type_var_count)
             .map(|i| {
@@ -342,54 +2215,183 @@ impl TypeChecker {
                 )
             })
             .collect();
-        Self::apply_substitutions_for_monotype(&polytype.typ, &substitutions)
+        let mut placeholders = vec![];
+        for predicate in polytype.predicates.iter() {
+            let placeholder = match self.classes.contains_key(&predicate.class) {
+                true => Some(self.fresh_dict_name("$dict")),
+                false => None,
+            };
+            placeholders.extend(placeholder.clone());
+            self.predicates.push((
+                Predicate {
+                    class: predicate.class.clone(),
+                    typ: Self::apply_substitutions_for_monotype(&predicate.typ, &substitutions)?,
+                },
+                placeholder,
+            ));
+        }
+        Ok((
+            Self::apply_substitutions_for_monotype(&polytype.typ, &substitutions)?,
+            placeholders,
+        ))
     }
 
+    /// Whether evaluating `expr` might create a channel. A `let` of such an
+    /// expression is not generalized, since e.g. `let c = chan() in ...`
+    /// must give `c` one type that every send and receive agrees on. Any
+    /// call might, unless it applies a `fn` written in place whose body
+    /// might not, and so might performing an operation or piping into a
+    /// function.
+    fn may_create_channel(expr: &Expr) -> bool {
+        match expr {
+            Expr::Unit
+            | Expr::Int(_)
+            | Expr::Fixed(_)
+            | Expr::Bool(_)
+            | Expr::Str(_)
+            | Expr::Literal { .. }
+            | Expr::Var(_)
+            | Expr::Fn { .. }
+            | Expr::LabeledFn { .. } => false,
+            Expr::Apply { func, arg } => match &**func {
+                Expr::Fn { body, .. } => {
+                    Self::may_create_channel(body) || Self::may_create_channel(arg)
+                }
+                _ => true,
+            },
+            Expr::Bin { op, e1, e2 } => {
+                matches!(op, BinOp::Pipe | BinOp::PipeBack)
+                    || Self::may_create_channel(e1)
+                    || Self::may_create_channel(e2)
+            }
+            Expr::Let { e1, e2, .. } => {
+                Self::may_create_channel(e1) || Self::may_create_channel(e2)
+            }
+            Expr::If { guard, e1, e2 } => [guard, e1, e2]
+                .into_iter()
+                .any(|e| Self::may_create_channel(e)),
+            Expr::Record(fields) => fields.iter().any(|(_, e)| Self::may_create_channel(e)),
+            Expr::Extend { value, record, .. } => {
+                Self::may_create_channel(value) || Self::may_create_channel(record)
+            }
+            Expr::Select { record, .. } | Expr::Remove { record, .. } => {
+                Self::may_create_channel(record)
+            }
+            Expr::Lazy(expr) | Expr::Measured { expr, .. } | Expr::Ascribe { expr, .. } => {
+                Self::may_create_channel(expr)
+            }
+            _ => true,
+        }
+    }
+
+    /// Generalize the type of `x` in `var_and_type` over the type variables
+    /// that are not free in `env`, unless it is not `generalizable`. Only
+    /// the predicates from index `pending` on were produced by its binding.
     fn generalize(
         &mut self,
         constraints: &TypeConstraints,
         env: &TypeEnvironment,
         var_and_type: (&String, &MonoType),
-    ) -> Result<TypeEnvironment, TypeError> {
+        generalizable: bool,
+        pending: usize,
+    ) -> Result<(TypeEnvironment, Vec<Vec<String>>), TypeError> {
         let substitutions = self.unify(constraints)?;
         let u = Self::apply_substitutions_for_monotype(var_and_type.1, &substitutions)?;
         let mut new_env: TypeEnvironment = env
             .iter()
             .map(|(varname, typ)| {
-                // FIXME: Do not `unwrap()`.
                 let typ = match typ {
                     Type::MonoType(monotype) => Type::MonoType(
-                        Self::apply_substitutions_for_monotype(&monotype, &substitutions).unwrap(),
+                        Self::apply_substitutions_for_monotype(monotype, &substitutions)?,
                     ),
                     Type::PolyType(polytype) => Type::PolyType(
-                        Self::apply_substitutions_for_polytype(&polytype, &substitutions).unwrap(),
+                        Self::apply_substitutions_for_polytype(polytype, &substitutions)?,
                     ),
                 };
-                (varname.clone(), typ)
+                Ok((varname.clone(), typ))
             })
-            .collect();
+            .collect::<Result<_, TypeError>>()?;
         let free_vars_in_u = self.free_type_vars(&Type::MonoType(u.clone()));
         let free_vars_in_new_env: HashSet<String> = new_env
             .values()
-            .map(|typ| self.free_type_vars(typ))
-            .flatten()
+            .flat_map(|typ| self.free_type_vars(typ))
             .collect();
-        let diff: HashSet<String> = free_vars_in_u
-            .difference(&free_vars_in_new_env)
-            .map(|s| s.clone())
+        // The type a `Dyn` is used at is one unknown type, and so is every
+        // type variable it is solved to.
+        let free_vars_in_fixed: HashSet<String> = self
+            .dyn_vars
+            .iter()
+            .map(|x| {
+                let typ = MonoType::TypeVariable(x.clone());
+                let typ = Self::apply_substitutions_for_monotype(&typ, &substitutions)?;
+                Ok(self.free_type_vars(&Type::MonoType(typ)))
+            })
+            .collect::<Result<Vec<_>, TypeError>>()?
+            .into_iter()
+            .flatten()
             .collect();
+        let diff: HashSet<String> = match generalizable {
+            true => free_vars_in_u
+                .difference(&free_vars_in_new_env)
+                .filter(|x| !free_vars_in_fixed.contains(*x))
+                .cloned()
+                .collect(),
+            false => HashSet::new(),
+        };
+        // Predicates on the generalized type variables move into the type
+        // scheme, and are checked again on every instantiation. The
+        // placeholders of each such predicate of a declared class are
+        // grouped, as they all stand for the same dictionary parameter.
+        // Those on type variables that are neither generalized nor reachable
+        // from the type or the environment can never be determined.
+        let mut predicates: Vec<Predicate> = vec![];
+        let mut dicts: Vec<Vec<String>> = vec![];
+        for (predicate, placeholder) in self.predicates.split_off(pending) {
+            let predicate = Predicate {
+                class: predicate.class,
+                typ: Self::apply_substitutions_for_monotype(&predicate.typ, &substitutions)?,
+            };
+            let free_vars = self.free_type_vars(&Type::MonoType(predicate.typ.clone()));
+            if free_vars.is_disjoint(&diff) {
+                let determined = free_vars.iter().all(|x| {
+                    free_vars_in_u.contains(x)
+                        || free_vars_in_new_env.contains(x)
+                        || free_vars_in_fixed.contains(x)
+                });
+                if placeholder.is_some() && !determined {
+                    return Err(TypeError::AmbiguousPredicate(predicate));
+                }
+                self.predicates.push((predicate, placeholder));
+                continue;
+            }
+            if !predicates.contains(&predicate) {
+                predicates.push(predicate.clone());
+            }
+            if let Some(placeholder) = placeholder {
+                let index = predicates
+                    .iter()
+                    .take_while(|p| **p != predicate)
+                    .filter(|p| self.classes.contains_key(&p.class))
+                    .count();
+                if index == dicts.len() {
+                    dicts.push(vec![]);
+                }
+                dicts[index].push(placeholder);
+            }
+        }
         if diff.is_empty() {
             new_env.insert(var_and_type.0.clone(), Type::MonoType(u.clone()));
-            Ok(new_env)
+            Ok((new_env, dicts))
         } else {
             new_env.insert(
                 var_and_type.0.clone(),
                 Type::PolyType(PolyType {
                     bounded_type_vars: Vec::from_iter(diff),
+                    predicates,
                     typ: u.clone(),
                 }),
             );
-            Ok(new_env)
+            Ok((new_env, dicts))
         }
     }
 
@@ -397,17 +2399,39 @@ impl TypeChecker {
         let typ = typ.clone();
         match typ {
             Type::MonoType(monotype) => match monotype {
-                MonoType::Int | MonoType::Bool => HashSet::new(),
+                MonoType::Unit
+                | MonoType::Int
+                | MonoType::Fixed(_)
+                | MonoType::Dyn(_)
+                | MonoType::Bool
+                | MonoType::String
+                | MonoType::RowEmpty => HashSet::new(),
                 MonoType::TypeVariable(x) => HashSet::from([x]),
-                MonoType::Fn(i, o) => {
+                MonoType::Lazy(t)
+                | MonoType::Array(t)
+                | MonoType::Task(t)
+                | MonoType::Chan(t)
+                | MonoType::Record(t) => self.free_type_vars(&Type::MonoType(*t)),
+                MonoType::Fn(i, o)
+                | MonoType::Map(i, o)
+                | MonoType::Labeled(_, i, o)
+                | MonoType::RowExtend(_, i, o) => {
                     &self.free_type_vars(&Type::MonoType(*i))
                         | &self.free_type_vars(&Type::MonoType(*o))
                 }
+                MonoType::Measured(t, measure) => {
+                    &self.free_type_vars(&Type::MonoType(*t))
+                        | &self.free_type_vars(&Type::MonoType(*measure))
+                }
+                MonoType::Measure(measure) => measure
+                    .into_keys()
+                    .filter(|x| x.starts_with('\''))
+                    .collect(),
             },
             Type::PolyType(polytype) => {
                 let free = self.free_type_vars(&Type::MonoType(polytype.typ));
                 let bounded = HashSet::from_iter(polytype.bounded_type_vars);
-                free.difference(&bounded).map(|s| s.clone()).collect()
+                free.difference(&bounded).cloned().collect()
             }
         }
     }
@@ -457,4 +2481,611 @@ mod tests {
 
         println!("{substitutions:#?}");
     }
+
+    #[test]
+    fn test_operator_as_function() {
+        use super::*;
+        use crate::parser::RainParser;
+
+        let program = "let apply = fn (f) -> f(3, 4) in apply((<=))";
+        let expr = RainParser::parse_program(program).unwrap();
+        let mut type_checker = TypeChecker::new();
+        assert_eq!(type_checker.type_check(&expr).unwrap(), MonoType::Bool);
+    }
+
+    #[test]
+    fn test_where_generalizes() {
+        use super::*;
+        use crate::parser::RainParser;
+
+        let program = "if id(true) then id(1) else 0 where id = fn (x) -> x";
+        let expr = RainParser::parse_program(program).unwrap();
+        let mut type_checker = TypeChecker::new();
+        assert_eq!(type_checker.type_check(&expr).unwrap(), MonoType::Int);
+    }
+
+    #[test]
+    fn test_interpolation_predicates() {
+        use super::*;
+        use crate::parser::RainParser;
+
+        let program = r#"let f = fn (x) -> "<${x}>" in f(1) ^ f(true)"#;
+        let expr = RainParser::parse_program(program).unwrap();
+        let mut type_checker = TypeChecker::new();
+        assert_eq!(type_checker.type_check(&expr).unwrap(), MonoType::String);
+
+        let program = r#"let f = fn (x) -> "<${x}>" in f((+))"#;
+        let expr = RainParser::parse_program(program).unwrap();
+        let mut type_checker = TypeChecker::new();
+        assert!(matches!(
+            type_checker.type_check(&expr),
+            Err(TypeError::MissingInstance(Predicate { ref class, .. })) if class == "Show"
+        ));
+    }
+
+    #[test]
+    fn test_fixed_width() {
+        use super::*;
+        use crate::int::Width;
+        use crate::parser::RainParser;
+
+        let check = |program| {
+            let expr = RainParser::parse_program(program).unwrap();
+            TypeChecker::new().type_check(&expr)
+        };
+        assert_eq!(
+            check("let add = fn (x, y) -> x + y in add(1u8, 2u8)").unwrap(),
+            MonoType::Fixed(Width::U8)
+        );
+        assert_eq!(
+            check("fn (x, y) -> x * y").unwrap().to_string(),
+            "Int -> Int -> Int"
+        );
+        assert!(matches!(
+            check("1u8 + 1"),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+        assert!(matches!(
+            check("1 land 2"),
+            Err(TypeError::MissingInstance(Predicate { ref class, .. })) if class == "Bits"
+        ));
+    }
+
+    #[test]
+    fn test_lazy() {
+        use super::*;
+        use crate::parser::RainParser;
+
+        let check = |program| {
+            let expr = RainParser::parse_program(program).unwrap();
+            TypeChecker::new().type_check(&expr)
+        };
+        assert_eq!(
+            check("lazy fn (x) -> x + 1").unwrap().to_string(),
+            "(Int -> Int) lazy"
+        );
+        assert_eq!(
+            check("fn (s) -> force(s) <= 1").unwrap().to_string(),
+            "Int lazy -> Bool"
+        );
+        assert!(matches!(
+            check("force(1)"),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+    }
+
+    #[test]
+    fn test_records() {
+        use super::*;
+        use crate::parser::RainParser;
+
+        let check = |program| {
+            let expr = RainParser::parse_program(program).unwrap();
+            TypeChecker::new().type_check(&expr)
+        };
+        let name = check("fn (r) -> r.name").unwrap();
+        let MonoType::Fn(ref record, ref field) = name else {
+            panic!("{name} is not a function type");
+        };
+        let MonoType::Record(ref row) = **record else {
+            panic!("{record} is not a record type");
+        };
+        assert!(matches!(
+            **row,
+            MonoType::RowExtend(ref label, ref t, ref rest)
+                if label == "name" && t == field && matches!(**rest, MonoType::TypeVariable(_))
+        ));
+        assert_eq!(
+            check("let r = { x = 1, y = true } in { z = \"s\" | r \\ y }")
+                .unwrap()
+                .to_string(),
+            "{ z : String, x : Int }"
+        );
+        assert_eq!(
+            check("let x = fn (r) -> r.x in x({ x = 1 }) + x({ y = true, x = 2 })")
+                .unwrap()
+                .to_string(),
+            "Int"
+        );
+        // Field order does not matter.
+        assert!(check("{ x = 1, y = true } == { y = true, x = 1 }").is_ok());
+        assert!(matches!(
+            check("{ x = 1 }.y"),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+        // The row of `r` would have to contain itself.
+        assert!(matches!(
+            check("fn (r) -> if true then { x = 1 | r } else { y = 1 | r }"),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+        assert!(matches!(
+            check("show({ f = fn (x) -> x + 1 })"),
+            Err(TypeError::MissingInstance(_))
+        ));
+        // A record is shown field by field, so the predicate that fails is
+        // the one on the field, however late its type is known.
+        assert!(matches!(
+            check("let show_x = fn (x) -> show({ x = x }) in show_x({ y = fn (y) -> y })"),
+            Err(TypeError::MissingInstance(Predicate {
+                typ: MonoType::Fn(..),
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_effects() {
+        use super::*;
+        use crate::parser::RainParser;
+
+        let check = |program| {
+            let expr = RainParser::parse_program(program).unwrap();
+            TypeChecker::new().type_check(&expr)
+        };
+        let program = "effect Yield : Int -> Bool
+            handle perform Yield(1) with | Yield(x, k) -> \"${x} ${k(true)}\" | return b -> \"\"";
+        assert_eq!(check(program).unwrap(), MonoType::String);
+        assert!(matches!(
+            check("effect Yield : Int -> Bool perform Yield(true)"),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+        // `k` returns what the handler does.
+        assert!(matches!(
+            check(
+                "effect Yield : Int -> Bool
+                handle perform Yield(1) with | Yield(x, k) -> x + k(true) | return b -> b"
+            ),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+        assert!(matches!(
+            check("perform Yield(1)"),
+            Err(TypeError::UnknownEffect(ref op)) if op == "Yield"
+        ));
+        // Type variables in a signature are not generalized.
+        assert!(check("effect Id : 'a -> 'a let f = fn (x) -> perform Id(x) in f(1)").is_ok());
+        assert!(matches!(
+            check("effect Id : 'a -> 'a let f = fn (x) -> perform Id(x) in f(1) + f(true)"),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+    }
+
+    #[test]
+    fn test_type_classes() {
+        use super::*;
+        use crate::parser::RainParser;
+
+        let check = |program: &str| {
+            let expr = RainParser::parse_program(program).unwrap();
+            TypeChecker::new().type_check(&expr)
+        };
+        let monoid = "class Monoid 'a { empty : 'a, (<>) : 'a -> 'a -> 'a }
+            instance Monoid String { empty = \"\", (<>) = fn (x, y) -> x ^ y }";
+        assert_eq!(
+            check(&format!(
+                "{monoid} let f = fn (x) -> x <> empty in f(\"a\")"
+            ))
+            .unwrap(),
+            MonoType::String
+        );
+        assert!(matches!(
+            check(&format!("{monoid} empty <> 1")),
+            Err(TypeError::MissingInstance(Predicate { ref class, .. })) if class == "Monoid"
+        ));
+        assert!(matches!(
+            check(&format!("{monoid} show(empty)")),
+            Err(TypeError::AmbiguousPredicate(_))
+        ));
+        assert!(matches!(
+            check(&format!(
+                "{monoid} let f = fn (x) -> let s = show(empty) in x in f(1)"
+            )),
+            Err(TypeError::AmbiguousPredicate(_))
+        ));
+        // A predicate left to the rest of the program is not generalized by
+        // the `let`s inside it.
+        assert_eq!(
+            check(&format!(
+                "{monoid} let e = (fn (u) -> empty)(()) in let f = fn (x) -> x in f(e) ^ \"\""
+            ))
+            .unwrap(),
+            MonoType::String
+        );
+        assert!(matches!(
+            check(&format!(
+                "{monoid} instance Monoid Int {{ empty = true, (<>) = (+) }} 1"
+            )),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+        assert!(matches!(
+            check("instance Eq Int { eq = 1 } 1"),
+            Err(TypeError::BuiltinClass(_))
+        ));
+        assert_eq!(check("1 == 2").unwrap(), MonoType::Bool);
+        assert!(matches!(
+            check("(+) == (+)"),
+            Err(TypeError::MissingInstance(Predicate { ref class, .. })) if class == "Eq"
+        ));
+        // A generic comparison is checked where it is used on a function.
+        assert!(matches!(
+            check("let f = fn (x) -> x == x in f(fn (y) -> y)"),
+            Err(TypeError::MissingInstance(Predicate { ref class, .. })) if class == "Eq"
+        ));
+    }
+
+    #[test]
+    fn test_labeled_arguments() {
+        use super::*;
+        use crate::parser::RainParser;
+
+        let check = |program: &str| {
+            let expr = RainParser::parse_program(program).unwrap();
+            TypeChecker::new().type_check(&expr)
+        };
+        let area = "let area = fn (~width, ~height) -> width * height in ";
+        assert_eq!(
+            check(&format!("{area}area")).unwrap().to_string(),
+            "~width: Int -> ~height: Int -> Int"
+        );
+        assert_eq!(
+            check(&format!("{area}area(~height: 1, ~width: 2)")).unwrap(),
+            MonoType::Int
+        );
+        assert_eq!(check(&format!("{area}area(1, 2)")).unwrap(), MonoType::Int);
+        // Parameters that are passed nothing are left for a later call.
+        assert_eq!(
+            check(&format!("{area}area(~height: 1)"))
+                .unwrap()
+                .to_string(),
+            "~width: Int -> Int"
+        );
+        assert!(matches!(
+            check(&format!("{area}area(~depth: 1, 2, 3)")),
+            Err(TypeError::UnknownLabel(ref label)) if label == "depth"
+        ));
+        // The type of a function that comes out of another call is known
+        // by the time its labels are matched up.
+        assert_eq!(
+            check(&format!(
+                "{area}let id = fn (x) -> x in id(area)(~height: 1, ~width: 2)"
+            ))
+            .unwrap(),
+            MonoType::Int
+        );
+        // A function passed as an argument is assumed to take its labels in
+        // the order they are written.
+        assert!(check("let f = fn (g) -> g(~x: 1) in f(fn (~x) -> x)").is_ok());
+        let pad = "let pad = fn (?width = 8, s) -> s ^ show(width) in ";
+        assert_eq!(
+            check(&format!("{pad}pad")).unwrap().to_string(),
+            "?width: Int -> String -> String"
+        );
+        assert_eq!(
+            check(&format!("{pad}pad(\"x\")")).unwrap(),
+            MonoType::String
+        );
+        assert!(matches!(
+            check(&format!("{pad}pad(~width: \"x\", \"y\")")),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+    }
+
+    #[test]
+    fn test_binding_ops() {
+        use super::*;
+        use crate::parser::RainParser;
+
+        let check = |program: &str| {
+            let expr = RainParser::parse_program(program).unwrap();
+            TypeChecker::new().type_check(&expr)
+        };
+        let option = "let bind = fn (f, m) -> if m.ok then f(m.value) else m,
+            map = fn (f, m) -> { ok = m.ok, value = f(m.value) } in ";
+        assert_eq!(
+            check(&format!(
+                "{option}let* x = {{ ok = true, value = 1 }} in {{ ok = x == 1, value = x }}"
+            ))
+            .unwrap()
+            .to_string(),
+            "{ ok : Bool, value : Int }"
+        );
+        assert_eq!(
+            check(&format!(
+                "{option}let+ x = {{ ok = true, value = 1 }} in x == 1"
+            ))
+            .unwrap()
+            .to_string(),
+            "{ ok : Bool, value : Bool }"
+        );
+        // `bind` must return what `f` does.
+        assert!(matches!(
+            check(&format!("{option}\nlet* x = {{ ok = true, value = 1 }} in x")),
+            Err(TypeError::BindingOp { ref op, pos: (3, 1), ref error })
+                if op == "let*" && matches!(**error, TypeError::UnsolvableConstraints(..))
+        ));
+        assert!(matches!(
+            check("let+ x = 1 in x"),
+            Err(TypeError::BindingOp { ref error, .. })
+                if matches!(**error, TypeError::UnboundVariable(ref x) if x == "map")
+        ));
+        // Errors in the operands are not the binding operator's.
+        assert!(matches!(
+            check(&format!("{option}let* x = 1 + true in x")),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+    }
+
+    #[test]
+    fn test_arrays_and_maps() {
+        use super::*;
+        use crate::parser::RainParser;
+
+        let check = |program: &str| {
+            let expr = RainParser::parse_program(program).unwrap();
+            TypeChecker::new().type_check(&expr)
+        };
+        assert_eq!(
+            check("array_set(array_init(3, fn (i) -> i), 0, 5)")
+                .unwrap()
+                .to_string(),
+            "Int array"
+        );
+        assert!(matches!(
+            check("map_empty()").unwrap(),
+            MonoType::Map(ref k, ref v) if matches!((&**k, &**v), (MonoType::TypeVariable(k), MonoType::TypeVariable(v)) if k != v)
+        ));
+        assert_eq!(
+            check("map_keys(map_set(map_empty(), { x = 1 }, true))")
+                .unwrap()
+                .to_string(),
+            "{ x : Int } array"
+        );
+        // Keys are compared, so they cannot be functions.
+        assert!(check("map_set(map_empty(), fn (x) -> x, 1)").is_err());
+        assert!(check("array_get(map_empty(), 0)").is_err());
+    }
+
+    #[test]
+    fn test_pipelines() {
+        use super::*;
+        use crate::parser::RainParser;
+
+        let check = |program: &str| {
+            let expr = RainParser::parse_program(program).unwrap();
+            TypeChecker::new().type_check(&expr)
+        };
+        assert_eq!(check("1 |> (+ 1) |> show").unwrap().to_string(), "String");
+        assert_eq!(check("((+ 1) >> show) <| 1").unwrap().to_string(), "String");
+        assert_eq!(
+            check("(show << (+ 1))").unwrap().to_string(),
+            "Int -> String"
+        );
+        assert!(matches!(
+            check("1 |> 2"),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+        assert!(matches!(
+            check("show >> (+ 1)"),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+    }
+
+    #[test]
+    fn test_dyn() {
+        use super::*;
+        use crate::parser::RainParser;
+
+        let check = |program: &str| {
+            let expr = RainParser::parse_program(program).unwrap();
+            TypeChecker::new().type_check(&expr)
+        };
+        assert_eq!(check("(1 : Dyn) + 1").unwrap().to_string(), "Int");
+        assert_eq!(check("(true : Dyn) + 1").unwrap().to_string(), "Int");
+        assert_eq!(
+            check("fn (x) -> (x : Dyn)").unwrap().to_string(),
+            "'1 -> Dyn"
+        );
+        assert_eq!(
+            check("let f = (show : Int -> Dyn) in f(1) ^ \"\"")
+                .unwrap()
+                .to_string(),
+            "String"
+        );
+        // Only a `Dyn` is consistent with other types.
+        assert!(matches!(
+            check("(1 : Bool)"),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+        assert!(matches!(
+            check("let f = (show : Dyn -> String) in f(1) + 1"),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+        // The type a `Dyn` is used at is not generalized.
+        let id = "let id = (fn (x) -> x : Dyn) in let f = fn (y) -> id(y) in ";
+        assert_eq!(check(&format!("{id}f(1) + 1")).unwrap().to_string(), "Int");
+        assert!(check(&format!("{id}{{ a = f(1), b = f(true) }}")).is_err());
+    }
+
+    #[test]
+    fn test_measures() {
+        use super::*;
+        use crate::parser::RainParser;
+
+        let check = |program: &str| {
+            let expr = RainParser::parse_program(program).unwrap();
+            TypeChecker::new().type_check(&expr)
+        };
+        assert_eq!(check("5<m> + 3<m>").unwrap().to_string(), "Int<m>");
+        assert_eq!(check("5<m> * 3<s> * 2").unwrap().to_string(), "Int<m*s>");
+        assert_eq!(
+            check("(fn (x) -> x * x)(3<m/s>)").unwrap().to_string(),
+            "Int<m^2/s^2>"
+        );
+        assert_eq!(check("1u8<kg> <= 2u8<kg>").unwrap().to_string(), "Bool");
+        assert!(matches!(
+            check("5<m> + 3<s>"),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+        assert!(matches!(
+            check("5<m> + 1"),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+        // Units are generalized by `let`, and only those left in the type
+        // of the program are taken to be `1`.
+        assert_eq!(
+            check("fn (x, y) -> x * y").unwrap().to_string(),
+            "Int -> Int -> Int"
+        );
+        assert_eq!(
+            check("let mul = fn (w, h) -> w * h in mul(2<m>, 3<m>)")
+                .unwrap()
+                .to_string(),
+            "Int<m^2>"
+        );
+        assert_eq!(
+            check("let twice = fn (x) -> 2 * x in twice(3<m>)")
+                .unwrap()
+                .to_string(),
+            "Int<m>"
+        );
+        assert_eq!(
+            check("let sq = fn (x) -> (x : Int<'u>) * x in { a = sq(3<m>), b = sq(2) }")
+                .unwrap()
+                .to_string(),
+            "{ a : Int<m^2>, b : Int }"
+        );
+        assert!(matches!(
+            check("1<m^2147483647> * 1<m>"),
+            Err(TypeError::ExponentOverflow(..))
+        ));
+        assert!(matches!(
+            check("let sq = fn (x) -> x * x in sq(sq(1<m^1073741824>))"),
+            Err(TypeError::ExponentOverflow(..))
+        ));
+
+        // 'x^2 * 'y^3 = m^4 has the solution 'x = m^2 * 'z^3, 'y = 'z^-2.
+        let measure = |units: &[(&str, i32)]| {
+            units
+                .iter()
+                .map(|(x, e)| (x.to_string(), *e))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let mut type_checker = TypeChecker::new();
+        let substitutions = type_checker
+            .unify_measure(measure(&[("'x", 2), ("'y", 3), ("m", -4)]))
+            .unwrap();
+        let solve = |x: &str| {
+            TypeChecker::apply_substitutions_for_monotype(
+                &MonoType::Measure(measure(&[(x, 1)])),
+                &substitutions,
+            )
+            .unwrap()
+        };
+        let (x, y) = (solve("'x"), solve("'y"));
+        assert_eq!(
+            MonoType::measure_product(&MonoType::measure_product(&x, &x, 1).unwrap(), &y, 3)
+                .unwrap(),
+            MonoType::Measure(measure(&[("m", 4)]))
+        );
+        assert!(type_checker
+            .unify_measure(measure(&[("'x", 2), ("m", 1)]))
+            .is_none());
+    }
+
+    #[test]
+    fn test_tasks() {
+        use super::*;
+        use crate::parser::RainParser;
+
+        let check = |program: &str| {
+            let expr = RainParser::parse_program(program).unwrap();
+            TypeChecker::new().type_check(&expr)
+        };
+        assert_eq!(check("spawn(fn (_) -> 1)").unwrap().to_string(), "Int task");
+        assert_eq!(
+            check("await(spawn(fn (_) -> \"done\"))")
+                .unwrap()
+                .to_string(),
+            "String"
+        );
+        assert_eq!(
+            check("let c = chan() in let u = send(c, 1) in recv(c)")
+                .unwrap()
+                .to_string(),
+            "Int"
+        );
+        // A function that uses a channel of its own is still generic.
+        assert_eq!(
+            check(
+                "let id2 = fn (x) -> let c = chan() in let u = send(c, x) in recv(c) in \
+                { a = id2(1), b = id2(true) }"
+            )
+            .unwrap()
+            .to_string(),
+            "{ a : Int, b : Bool }"
+        );
+        // A channel carries values of one type, even when it is bound by
+        // `let`.
+        assert!(matches!(
+            check("let c = chan() in let u = send(c, 1) in recv(c) == true"),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+        assert!(matches!(
+            check("let c = chan() in let u = send(c, 1) in let v = send(c, true) in ()"),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+        // Functions that only use channels stay generic.
+        let relay = "let relay = fn (c, d) -> send(d, recv(c)) in \
+            let b = chan() in let i = chan() in \
+            { x = relay(b, b), y = relay(i, i), z = send(b, true), w = send(i, 1) }";
+        assert!(check(relay).is_ok());
+
+        // Only a `let` of an expression that might create a channel is kept
+        // from generalizing. A call of a `fn` written in place, whose body
+        // makes a function, does not.
+        assert_eq!(
+            check("let k = (fn (x) -> fn (y) -> y)(1) in let a = k(1) in k(true)")
+                .unwrap()
+                .to_string(),
+            "Bool"
+        );
+        assert_eq!(
+            check("let f = if true then fn (x) -> x else fn (y) -> y in { a = f(1), b = f(true) }")
+                .unwrap()
+                .to_string(),
+            "{ a : Int, b : Bool }"
+        );
+        // Any other call might, even if the channel does not show in the
+        // type of what it returns.
+        let pair = "let pair = fn (_) -> let c = chan() in \
+            { s = fn (x) -> send(c, x), r = fn (_) -> recv(c) } in \
+            let p = pair(()) in let u = p.s(1) in p.r(()) == true";
+        assert!(matches!(
+            check(pair),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+        assert!(matches!(
+            check("let l = lazy chan() in let u = send(force(l), 1) in recv(force(l)) == true"),
+            Err(TypeError::UnsolvableConstraints(..))
+        ));
+    }
 }
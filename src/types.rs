@@ -0,0 +1,193 @@
+use crate::int::Width;
+use std::{collections::BTreeMap, fmt::Display};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MonoType {
+    Unit,
+    Int,
+    Bool,
+    String,
+    Fixed(Width),
+    /// The type of values that are only checked at runtime, consistent
+    /// with every type. It remembers where it is written, so that a failed
+    /// check can blame that place.
+    Dyn((usize, usize)),
+    Lazy(Box<MonoType>),                          // t lazy
+    Array(Box<MonoType>),                         // t array
+    Task(Box<MonoType>),                          // t task
+    Chan(Box<MonoType>),                          // t chan
+    Map(Box<MonoType>, Box<MonoType>),            // (k, v) map
+    Fn(Box<MonoType>, Box<MonoType>),             // t1 -> t2
+    Labeled(Label, Box<MonoType>, Box<MonoType>), // ~label: t1 -> t2
+    TypeVariable(String),                         // '1, '2, '3...
+    Record(Box<MonoType>),                        // { row }
+    /// The row without fields. A row that may have more fields ends in a
+    /// type variable instead.
+    RowEmpty,
+    RowExtend(String, Box<MonoType>, Box<MonoType>), // label : t | row
+    /// An integer type with a unit of measure other than `1`, e.g.
+    /// `Int<m/s>`. The unit is a `Measure`.
+    Measured(Box<MonoType>, Box<MonoType>),
+    /// A unit of measure: the exponents of base units like `m`, and of unit
+    /// variables like `'u`, in a product. Units form an abelian group, and
+    /// factors with the exponent `0` are left out.
+    Measure(BTreeMap<String, i32>),
+}
+
+/// The label of a parameter: `~label`, or `?label` when the argument may
+/// be left out.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Label {
+    Required(String),
+    Optional(String),
+}
+
+impl Label {
+    pub fn name(&self) -> &str {
+        match self {
+            Label::Required(name) | Label::Optional(name) => name,
+        }
+    }
+}
+
+impl Display for Label {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Label::Required(name) => write!(f, "~{name}"),
+            Label::Optional(name) => write!(f, "?{name}"),
+        }
+    }
+}
+
+impl MonoType {
+    /// The fields of `row`, and the type variable it ends in, if any.
+    pub(crate) fn row_fields(row: &MonoType) -> (Vec<(&String, &MonoType)>, Option<&String>) {
+        let mut fields = vec![];
+        let mut row = row;
+        while let MonoType::RowExtend(label, t, rest) = row {
+            fields.push((label, &**t));
+            row = rest;
+        }
+        match row {
+            MonoType::TypeVariable(x) => (fields, Some(x)),
+            _ => (fields, None),
+        }
+    }
+}
+
+impl Display for MonoType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn helper(f: &mut std::fmt::Formatter<'_>, monotype: &MonoType) -> std::fmt::Result {
+            let monotype = monotype.clone();
+            match monotype {
+                MonoType::Unit => write!(f, "Unit"),
+                MonoType::Int => write!(f, "Int"),
+                MonoType::Bool => write!(f, "Bool"),
+                MonoType::String => write!(f, "String"),
+                MonoType::Fixed(width) => write!(f, "{width}"),
+                MonoType::Dyn(_) => write!(f, "Dyn"),
+                MonoType::Lazy(t) if matches!(*t, MonoType::Fn(_, _)) => {
+                    write!(f, "(")?;
+                    helper(f, &t)?;
+                    write!(f, ") lazy")
+                }
+                MonoType::Lazy(t) => {
+                    helper(f, &t)?;
+                    write!(f, " lazy")
+                }
+                MonoType::Array(t) if matches!(*t, MonoType::Fn(_, _)) => {
+                    write!(f, "(")?;
+                    helper(f, &t)?;
+                    write!(f, ") array")
+                }
+                MonoType::Array(t) => {
+                    helper(f, &t)?;
+                    write!(f, " array")
+                }
+                MonoType::Task(t) if matches!(*t, MonoType::Fn(_, _)) => {
+                    write!(f, "(")?;
+                    helper(f, &t)?;
+                    write!(f, ") task")
+                }
+                MonoType::Task(t) => {
+                    helper(f, &t)?;
+                    write!(f, " task")
+                }
+                MonoType::Chan(t) if matches!(*t, MonoType::Fn(_, _)) => {
+                    write!(f, "(")?;
+                    helper(f, &t)?;
+                    write!(f, ") chan")
+                }
+                MonoType::Chan(t) => {
+                    helper(f, &t)?;
+                    write!(f, " chan")
+                }
+                MonoType::Map(k, v) => {
+                    write!(f, "(")?;
+                    helper(f, &k)?;
+                    write!(f, ", ")?;
+                    helper(f, &v)?;
+                    write!(f, ") map")
+                }
+                MonoType::TypeVariable(x) => write!(f, "{x}"),
+                MonoType::Fn(i, o) => {
+                    helper(f, &i)?;
+                    write!(f, " -> ")?;
+                    helper(f, &o)
+                }
+                MonoType::Labeled(label, i, o) => {
+                    write!(f, "{label}: ")?;
+                    helper(f, &i)?;
+                    write!(f, " -> ")?;
+                    helper(f, &o)
+                }
+                MonoType::Record(row) => {
+                    let (fields, tail) = MonoType::row_fields(&row);
+                    if fields.is_empty() && tail.is_none() {
+                        return write!(f, "{{}}");
+                    }
+                    write!(f, "{{")?;
+                    for (i, (label, t)) in fields.into_iter().enumerate() {
+                        write!(f, "{} {label} : ", if i > 0 { "," } else { "" })?;
+                        helper(f, t)?;
+                    }
+                    if let Some(x) = tail {
+                        write!(f, " | {x}")?;
+                    }
+                    write!(f, " }}")
+                }
+                row @ (MonoType::RowEmpty | MonoType::RowExtend(_, _, _)) => {
+                    helper(f, &MonoType::Record(Box::new(row)))
+                }
+                MonoType::Measured(t, measure) => {
+                    helper(f, &t)?;
+                    write!(f, "<")?;
+                    helper(f, &measure)?;
+                    write!(f, ">")
+                }
+                // `kg*m/s^2`, and `1/s` when nothing is left above the line.
+                MonoType::Measure(measure) => {
+                    let factor = |x: &String, e: i32| match e {
+                        1 => x.clone(),
+                        e => format!("{x}^{e}"),
+                    };
+                    let above: Vec<_> = measure
+                        .iter()
+                        .filter(|(_, e)| **e > 0)
+                        .map(|(x, e)| factor(x, *e))
+                        .collect();
+                    match above.is_empty() {
+                        true => write!(f, "1")?,
+                        false => write!(f, "{}", above.join("*"))?,
+                    }
+                    for (x, e) in measure.iter().filter(|(_, e)| **e < 0) {
+                        write!(f, "/{}", factor(x, -e))?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+
+        helper(f, self)
+    }
+}